@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::grammar::{Expression, Loop, Operator, Span, Token};
+
+/// The range of positions the cell pointer may be at, relative to the first cell.
+///
+/// A bound of `None` means the pointer may move arbitrarily far in that direction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointerRange {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl PointerRange {
+    /// Creates a range containing a single position.
+    fn exact(position: i64) -> Self {
+        PointerRange {
+            min: Some(position),
+            max: Some(position),
+        }
+    }
+
+    /// Gets the position of the pointer if it is known exactly.
+    pub fn position(&self) -> Option<i64> {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => Some(min),
+            _ => None,
+        }
+    }
+
+    /// Removes the positions before the first cell, which no running program can reach.
+    fn clamp_to_tape(self) -> Self {
+        PointerRange {
+            min: Some(self.min.unwrap_or(0).max(0)),
+            max: self.max,
+        }
+    }
+
+    /// Creates the smallest range containing both `self` and `other`.
+    fn join(self, other: PointerRange) -> Self {
+        PointerRange {
+            min: self.min.zip(other.min).map(|(a, b)| a.min(b)),
+            max: self.max.zip(other.max).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    /// Moves every position in the range by `offset` cells.
+    fn shift(self, offset: i64) -> Self {
+        PointerRange {
+            min: self.min.map(|min| min + offset),
+            max: self.max.map(|max| max + offset),
+        }
+    }
+}
+
+/// How a loop moves the pointer each time its body runs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopShape {
+    /// The pointer ends each iteration where it started
+    Balanced,
+    /// The pointer drifts left by a fixed amount each iteration
+    UnboundedLeft,
+    /// The pointer drifts right by a fixed amount each iteration
+    UnboundedRight,
+    /// The body contains unbalanced loops, so the drift cannot be determined
+    Unknown,
+}
+
+/// Whether a loop body runs when the loop is reached.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopEntry {
+    /// The current cell is provably zero, so the body never runs
+    Never,
+    /// The current cell is provably nonzero, so the body runs at least once
+    Always,
+    /// The value of the current cell is not known
+    Maybe,
+}
+
+/// How sure the analysis is that a block of the program runs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Path {
+    /// The block is certain to run, so an underflow in it is an error
+    Certain,
+    /// The block runs if a loop is entered, so an underflow in it is a warning
+    Conditional,
+    /// The block is a later iteration of a loop, which is only analysed to find
+    /// out which nested loops may run
+    Generic,
+}
+
+/// What the analysis found out about a single loop.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoopInfo {
+    /// Location of the loop
+    pub span: Span,
+    /// How the loop moves the pointer
+    pub shape: LoopShape,
+    /// Whether the loop body runs when the loop is reached
+    pub entry: LoopEntry,
+    /// Where the pointer may be when the loop is reached
+    pub pointer: PointerRange,
+}
+
+/// The knowledge the analysis has about the machine at a point in the program.
+#[derive(Debug, Clone)]
+struct State {
+    /// Where the pointer may be
+    pointer: PointerRange,
+    /// Value of the cell under the pointer, if known
+    current: Option<u8>,
+    /// Values of cells by position, `None` marks a cell whose value is unknown.
+    /// Only consulted while the pointer position is known exactly.
+    cells: HashMap<i64, Option<u8>>,
+    /// Whether cells missing from `cells` still hold their initial zero
+    untouched_zero: bool,
+}
+
+impl State {
+    /// Creates a state where the pointer may be on any cell and no cell value is known.
+    fn unknown() -> Self {
+        State {
+            pointer: PointerRange {
+                min: Some(0),
+                max: None,
+            },
+            current: None,
+            cells: HashMap::new(),
+            untouched_zero: false,
+        }
+    }
+
+    /// Gets the value of the cell at `position`, if known.
+    fn cell(&self, position: i64) -> Option<u8> {
+        match self.cells.get(&position) {
+            Some(value) => *value,
+            None if self.untouched_zero => Some(0),
+            None => None,
+        }
+    }
+
+    /// Forgets the value of every cell.
+    fn forget_cells(&mut self) {
+        self.cells.clear();
+        self.untouched_zero = false;
+        self.current = None;
+    }
+
+    /// Records a new value for the cell under the pointer.
+    fn write_current(&mut self, value: Option<u8>) {
+        match self.pointer.position() {
+            Some(position) => {
+                self.cells.insert(position, value);
+                self.current = value;
+            }
+            // Any cell could have been written, so nothing is known anymore
+            None => {
+                self.forget_cells();
+                self.current = value;
+            }
+        }
+    }
+
+    /// Moves the pointer by `offset` cells.
+    fn move_pointer(&mut self, offset: i64) {
+        self.pointer = self.pointer.shift(offset);
+        self.load_current();
+    }
+
+    /// Removes pointer positions before the first cell, as the program stops if it gets there.
+    fn clamp_pointer(&mut self) {
+        self.pointer = self.pointer.clamp_to_tape();
+        self.load_current();
+    }
+
+    /// Looks up the value of the cell under the pointer after the pointer has changed.
+    fn load_current(&mut self) {
+        self.current = self
+            .pointer
+            .position()
+            .and_then(|position| self.cell(position));
+    }
+}
+
+/// Abstract interpretation of the pointer movement of a program.
+///
+/// Tracks the range of positions the pointer may be at along with the cell values
+/// that are known, and reports a pointer underflow only when it is provable:
+/// as an error when the offending instruction is certain to run, and as a warning
+/// when it runs only if a loop whose condition cannot be decided is entered.
+#[derive(Debug, Default)]
+pub struct PointerAnalysis {
+    /// Information about every loop reached by the analysis, in source order
+    loops: Vec<LoopInfo>,
+    /// Index into `loops` of each loop, by its span
+    loop_index: HashMap<Span, usize>,
+    /// Provable underflows found in the program
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl PointerAnalysis {
+    /// Analyses the pointer movement of a program.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The abstract syntax tree (AST) of the program.
+    ///
+    /// # Returns
+    ///
+    /// The result of the analysis.
+    pub fn analyse(ast: &[Expression]) -> Self {
+        let mut analysis = PointerAnalysis::default();
+        let state = State {
+            pointer: PointerRange::exact(0),
+            current: Some(0),
+            cells: HashMap::new(),
+            untouched_zero: true,
+        };
+
+        analysis.analyse_block(ast, state, Path::Certain);
+        analysis.loops.sort_by_key(|info| info.span.byte_offset);
+        analysis.loop_index.clear();
+        analysis
+    }
+
+    /// Gets the information collected about each loop reached by the analysis.
+    pub fn loops(&self) -> &[LoopInfo] {
+        &self.loops
+    }
+
+    /// Gets the underflows found in the program.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Classifies how a loop moves the pointer.
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The body of the loop.
+    ///
+    /// # Returns
+    ///
+    /// The shape of the loop.
+    pub fn loop_shape(body: &[Expression]) -> LoopShape {
+        match Self::net_shift(body) {
+            Some(0) => LoopShape::Balanced,
+            Some(shift) if shift < 0 => LoopShape::UnboundedLeft,
+            Some(_) => LoopShape::UnboundedRight,
+            None => LoopShape::Unknown,
+        }
+    }
+
+    /// Computes how far the pointer moves after running `expressions` once.
+    ///
+    /// # Returns
+    ///
+    /// The net movement, or `None` if it depends on how often an unbalanced loop runs.
+    fn net_shift(expressions: &[Expression]) -> Option<i64> {
+        let mut shift = 0;
+        for expression in expressions {
+            match expression {
+                Expression::Loop(_loop) => {
+                    if Self::loop_shape(&_loop.body) != LoopShape::Balanced {
+                        return None;
+                    }
+                }
+                // Defining a procedure does not run its body
+                Expression::Procedure(_) => {}
+                Expression::Operator(_op) => match _op.type_name {
+                    Token::MoveForward => shift += _op.count as i64,
+                    Token::MoveBack => shift -= _op.count as i64,
+                    // A procedure can move the pointer by any amount
+                    Token::Call => return None,
+                    _ => {}
+                },
+            }
+        }
+        Some(shift)
+    }
+
+    /// Runs the analysis over a block of expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `expressions` - The expressions to analyse.
+    /// * `state` - What is known when the block starts.
+    /// * `path` - How sure the analysis is that the block runs.
+    ///
+    /// # Returns
+    ///
+    /// What is known when the block ends, or `None` if it provably stops the program.
+    fn analyse_block(
+        &mut self,
+        expressions: &[Expression],
+        mut state: State,
+        path: Path,
+    ) -> Option<State> {
+        for expression in expressions {
+            state = match expression {
+                Expression::Loop(_loop) => self.analyse_loop(_loop, state, path)?,
+                Expression::Procedure(procedure) => {
+                    // The body runs wherever the procedure is called from, so only the
+                    // loops in it are analysed, starting from a state where nothing is known
+                    self.analyse_block(&procedure.body, State::unknown(), Path::Generic);
+                    state
+                }
+                Expression::Operator(_op) => self.analyse_operator(_op, state, path)?,
+            };
+        }
+        Some(state)
+    }
+
+    /// Updates the state for a single operator.
+    ///
+    /// # Returns
+    ///
+    /// The state after the operator, or `None` if it provably underflows the pointer.
+    fn analyse_operator(
+        &mut self,
+        operator: &Operator,
+        mut state: State,
+        path: Path,
+    ) -> Option<State> {
+        let count = operator.count as i64;
+        match operator.type_name {
+            Token::MoveForward => state.move_pointer(count),
+            Token::MoveBack => {
+                state.move_pointer(-count);
+                if matches!(state.pointer.max, Some(max) if max < 0) {
+                    match path {
+                        Path::Certain => self.diagnostics.push(Diagnostic::error(
+                            operator.span,
+                            "Pointer moves before the start of the tape",
+                        )),
+                        Path::Conditional => self.diagnostics.push(Diagnostic::warning(
+                            operator.span,
+                            "Pointer moves before the start of the tape if the enclosing loop runs",
+                        )),
+                        // Also covers the first iteration, which has already been reported
+                        Path::Generic => {}
+                    }
+                    return None;
+                }
+                state.clamp_pointer();
+            }
+            Token::Add => {
+                let value = state.current.map(|value| value.wrapping_add(count as u8));
+                state.write_current(value);
+            }
+            Token::Sub => {
+                let value = state.current.map(|value| value.wrapping_sub(count as u8));
+                state.write_current(value);
+            }
+            Token::StdIn => state.write_current(None),
+            Token::Fork => {
+                // The parent goes on with the cell zeroed and the child one cell to the
+                // right with that cell set to one, so the rest runs from either position
+                state.pointer = state.pointer.join(state.pointer.shift(1));
+                state.forget_cells();
+            }
+            Token::Call => state = State::unknown(),
+            Token::ShiftRight => {
+                let value = state
+                    .current
+                    .map(|value| value.checked_shr(count as u32).unwrap_or(0));
+                state.write_current(value);
+            }
+            Token::ShiftLeft => {
+                let value = state
+                    .current
+                    .map(|value| value.checked_shl(count as u32).unwrap_or(0));
+                state.write_current(value);
+            }
+            Token::Not => {
+                let value = state.current.map(|value| !value);
+                state.write_current(value);
+            }
+            // The storage register is not tracked
+            Token::Retrieve | Token::Xor | Token::And | Token::Or => state.write_current(None),
+            // Nothing after `@` runs
+            Token::End => return None,
+            _ => {}
+        }
+        Some(state)
+    }
+
+    /// Updates the state for a loop, analysing the first run of its body followed
+    /// by a run starting from anywhere later iterations may start.
+    ///
+    /// # Returns
+    ///
+    /// The state after the loop has finished, or `None` if it provably stops the program.
+    fn analyse_loop(&mut self, _loop: &Loop, state: State, path: Path) -> Option<State> {
+        let entry = match state.current {
+            Some(0) => LoopEntry::Never,
+            Some(_) => LoopEntry::Always,
+            None => LoopEntry::Maybe,
+        };
+        self.record_loop(LoopInfo {
+            span: _loop.span,
+            shape: Self::loop_shape(&_loop.body),
+            entry,
+            pointer: state.pointer,
+        });
+
+        if entry == LoopEntry::Never {
+            return Some(state);
+        }
+
+        // Only the first iteration starts from a known state
+        if path != Path::Generic {
+            let first_path = match (path, entry) {
+                (Path::Certain, LoopEntry::Always) => Path::Certain,
+                _ => Path::Conditional,
+            };
+            let first_iteration = self.analyse_block(&_loop.body, state.clone(), first_path);
+            if first_iteration.is_none() && entry == LoopEntry::Always {
+                return None;
+            }
+        }
+
+        // The pointer drifts by the net shift on every iteration and a body that is
+        // certain to run drifts it at least once
+        let drift = match entry {
+            LoopEntry::Always => 1,
+            _ => 0,
+        };
+        let mut after = state;
+        after.pointer = match Self::net_shift(&_loop.body) {
+            Some(0) => after.pointer,
+            Some(shift) if shift < 0 => PointerRange {
+                min: None,
+                max: after.pointer.max.map(|max| max + shift * drift),
+            },
+            Some(shift) => PointerRange {
+                min: after.pointer.min.map(|min| min + shift * drift),
+                max: None,
+            },
+            None => PointerRange {
+                min: None,
+                max: None,
+            },
+        };
+        after.clamp_pointer();
+        after.forget_cells();
+
+        // Nested loops that do not run on the first iteration may still run on a later one
+        self.analyse_block(&_loop.body, after.clone(), Path::Generic);
+
+        // A loop only ends once the cell under the pointer is zero
+        after.write_current(Some(0));
+        Some(after)
+    }
+
+    /// Records what was found out about a loop, combining it with earlier visits.
+    fn record_loop(&mut self, info: LoopInfo) {
+        match self.loop_index.get(&info.span) {
+            Some(&idx) => {
+                let known = &mut self.loops[idx];
+                if known.entry != info.entry {
+                    known.entry = LoopEntry::Maybe;
+                }
+                known.pointer = known.pointer.join(info.pointer);
+            }
+            None => {
+                self.loop_index.insert(info.span, self.loops.len());
+                self.loops.push(info);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+    use crate::lexer::LexerOptions;
+    use crate::parser::Parser;
+
+    /// Parses `program` without optimizing it and analyses it.
+    fn analyse(program: &str) -> PointerAnalysis {
+        let mut parser = Parser::new(program.to_string(), false, LexerOptions::default());
+        parser.generate_syntax_tree();
+        PointerAnalysis::analyse(&parser.into_ast())
+    }
+
+    /// Gets the severity and column of every diagnostic for `program`.
+    fn diagnostics(program: &str) -> Vec<(Severity, usize)> {
+        analyse(program)
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.span.col))
+            .collect()
+    }
+
+    #[test]
+    fn certain_underflow_is_an_error() {
+        assert_eq!(diagnostics("+<"), [(Severity::Error, 2)]);
+        assert_eq!(diagnostics(">[-]<<"), [(Severity::Error, 6)]);
+    }
+
+    #[test]
+    fn underflow_in_loop_that_always_runs_is_an_error() {
+        assert_eq!(diagnostics("+[<]"), [(Severity::Error, 3)]);
+    }
+
+    #[test]
+    fn underflow_in_loop_that_may_run_is_a_warning() {
+        assert_eq!(diagnostics(",[<]"), [(Severity::Warning, 3)]);
+    }
+
+    #[test]
+    fn underflow_in_loop_that_never_runs_is_ignored() {
+        assert_eq!(diagnostics("[<]"), []);
+    }
+
+    #[test]
+    fn balanced_moves_are_fine() {
+        assert_eq!(diagnostics("+>+<-[->+<]>."), []);
+    }
+
+    #[test]
+    fn loop_entry_follows_known_cell_values() {
+        let entries: Vec<LoopEntry> = analyse("+[-][-],[-]")
+            .loops()
+            .iter()
+            .map(|info| info.entry)
+            .collect();
+        assert_eq!(
+            entries,
+            [LoopEntry::Always, LoopEntry::Never, LoopEntry::Maybe]
+        );
+    }
+
+    #[test]
+    fn loop_shape_follows_net_shift() {
+        let shape = |program: &str| analyse(program).loops()[0].shape;
+        assert_eq!(shape(",[->+<]"), LoopShape::Balanced);
+        assert_eq!(shape(",[>]"), LoopShape::UnboundedRight);
+        assert_eq!(shape(",[<]"), LoopShape::UnboundedLeft);
+        assert_eq!(shape(",[[>]]"), LoopShape::Unknown);
+    }
+}
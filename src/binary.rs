@@ -0,0 +1,140 @@
+//! Helpers shared by the binary file formats, such as compiled programs and snapshots.
+
+/// Errors returned when a binary file cannot be read.
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    /// The data does not start with the magic bytes of the expected format
+    BadMagic,
+    /// The file was written by a version of the layout this build cannot read
+    UnsupportedVersion(u16),
+    /// The data ends in the middle of a value
+    Truncated,
+    /// A node starts with an unknown tag
+    InvalidTag(u8),
+    /// An operator refers to a byte that is not a command
+    InvalidToken(u8),
+    /// An operator repeats its command a number of times the parser never produces
+    InvalidCount(usize),
+    /// Loops and procedures are nested deeper than a program may be loaded with
+    TooDeep(usize),
+    /// A number does not fit in the integer it is read into
+    Overflow,
+    /// A snapshot was taken while running a different program
+    ProgramMismatch,
+    /// A snapshot's program counter, pointer or call stack are not a state a program
+    /// can reach
+    InvalidState,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "file is not in the expected format"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "written with unsupported format version {}", version)
+            }
+            LoadError::Truncated => write!(f, "file is truncated"),
+            LoadError::InvalidTag(tag) => write!(f, "invalid node tag {}", tag),
+            LoadError::InvalidToken(byte) => write!(f, "invalid command byte {}", byte),
+            LoadError::InvalidCount(count) => write!(f, "invalid operator count {}", count),
+            LoadError::TooDeep(depth) => {
+                write!(f, "loops are nested more than {} deep", depth)
+            }
+            LoadError::Overflow => write!(f, "number out of range"),
+            LoadError::ProgramMismatch => {
+                write!(f, "snapshot was taken while running a different program")
+            }
+            LoadError::InvalidState => {
+                write!(f, "snapshot's state is not one the program can reach")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Appends the magic bytes and a little-endian `u16` version.
+pub(crate) fn write_header(bytes: &mut Vec<u8>, magic: &[u8; 4], version: u16) {
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Appends a length followed by the bytes themselves.
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_varint(bytes, data.len() as u64);
+    bytes.extend_from_slice(data);
+}
+
+/// Cursor over the bytes of a binary file.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    /// Checks the data starts with `magic` followed by `version`.
+    pub(crate) fn header(&mut self, magic: &[u8; 4], version: u16) -> Result<(), LoadError> {
+        if self.take(magic.len())? != magic {
+            return Err(LoadError::BadMagic);
+        }
+        let found = u16::from_le_bytes([self.byte()?, self.byte()?]);
+        if found != version {
+            return Err(LoadError::UnsupportedVersion(found));
+        }
+        Ok(())
+    }
+
+    /// Gets the number of bytes left to read.
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.position.checked_add(len).ok_or(LoadError::Truncated)?;
+        let slice = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(LoadError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, LoadError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(LoadError::Overflow)
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, LoadError> {
+        usize::try_from(self.varint()?).map_err(|_| LoadError::Overflow)
+    }
+
+    /// Reads a length followed by that many bytes.
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], LoadError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+}
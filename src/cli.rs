@@ -0,0 +1,480 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use brainfk::dialect::DialectTable;
+use brainfk::dump::DumpFormat;
+use brainfk::grammar::Token;
+use brainfk::lexer::{Dialect, LexerOptions};
+use brainfk::limits::Limits;
+use brainfk::preprocessor;
+use brainfk::schedule::Schedule;
+use brainfk::stats::StatsFormat;
+use brainfk::trace::{TraceFormat, TraceOptions};
+use colored::Colorize;
+
+/// Program used when no file is given on the command line.
+const DEFAULT_PROGRAM: &str = "resources/program.bfk";
+
+/// Directory searched for dialect definitions given by name.
+const DIALECTS_DIR: &str = "resources/dialects";
+
+/// Number of steps the debugger can undo unless `--history` is given.
+const DEFAULT_HISTORY: usize = 100_000;
+
+/// The action requested on the command line.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    /// Execute the program
+    Run,
+    /// Execute the program and report where the time was spent
+    Profile,
+    /// Report likely mistakes without running the program
+    Lint,
+    /// Rewrite the program with a canonical layout
+    Fmt,
+    /// Print the shortest equivalent program
+    Minify,
+    /// Print the syntax tree
+    Dump,
+    /// Save the parsed program so it can be run without parsing it again
+    Compile,
+    /// Run lines of code interactively on a persistent tape
+    Repl,
+    /// Step through the program forwards and backwards
+    Debug,
+    /// Rewrite the program in another dialect
+    Translate,
+}
+
+/// Options parsed from the command line.
+#[derive(Debug)]
+pub struct Args {
+    /// The action to perform
+    pub command: Command,
+    /// Path of the Brainfuck program to run
+    pub file_path: String,
+    /// Flag indicating whether to optimize the AST
+    pub should_optimise: bool,
+    /// Options controlling how the program is tokenized
+    pub lexer_options: LexerOptions,
+    /// Print the source annotated with execution counts after profiling
+    pub annotate: bool,
+    /// Resource limits enforced while running
+    pub limits: Limits,
+    /// Check for pointer underflows before running
+    pub check_pointer: bool,
+    /// Path of a `brainfk.toml` to use instead of searching for one
+    pub config_path: Option<String>,
+    /// Only check whether the program is formatted instead of rewriting it
+    pub check: bool,
+    /// Line width for the formatter, overriding `brainfk.toml`
+    pub width: Option<usize>,
+    /// How `dump` renders the syntax tree
+    pub dump_format: DumpFormat,
+    /// Where `compile` writes the compiled program
+    pub output_path: Option<String>,
+    /// Print execution statistics after running, in the given format
+    pub stats: Option<StatsFormat>,
+    /// Where to save a snapshot if the run is stopped by a limit
+    pub checkpoint_path: Option<String>,
+    /// Snapshot to continue the run from
+    pub resume_path: Option<String>,
+    /// Number of steps the debugger can undo
+    pub history: usize,
+    /// Cells whose every change is logged to stderr
+    pub trace_cells: Vec<usize>,
+    /// Which executed steps to trace, or `None` when tracing is disabled
+    pub trace: Option<TraceOptions>,
+    /// File the trace is written to instead of stderr
+    pub trace_path: Option<String>,
+    /// Number of times `-v` was given, each showing more detailed log messages
+    pub verbosity: u8,
+    /// Only log errors
+    pub quiet: bool,
+    /// Dialect the program is translated into
+    pub target_dialect: Option<Dialect>,
+    /// How the thread running each step is picked once the program forks
+    pub schedule: Schedule,
+}
+
+impl Args {
+    /// Parses the arguments the program was started with.
+    ///
+    /// Exits the process with a usage message if an argument is not recognised.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Args`.
+    pub fn parse() -> Self {
+        let mut args = Args {
+            command: Command::Run,
+            file_path: String::from(DEFAULT_PROGRAM),
+            should_optimise: true,
+            lexer_options: LexerOptions::default(),
+            annotate: false,
+            limits: Limits::default(),
+            check_pointer: true,
+            config_path: None,
+            check: false,
+            width: None,
+            dump_format: DumpFormat::default(),
+            output_path: None,
+            stats: None,
+            checkpoint_path: None,
+            resume_path: None,
+            history: DEFAULT_HISTORY,
+            trace_cells: vec![],
+            trace: None,
+            trace_path: None,
+            verbosity: 0,
+            quiet: false,
+            target_dialect: None,
+            schedule: Schedule::default(),
+        };
+
+        let mut dialect = None;
+        let mut seed = None;
+        let mut raw_args = env::args().skip(1).enumerate();
+        while let Some((idx, arg)) = raw_args.next() {
+            // Flags taking a value accept both `--flag value` and `--flag=value`
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| raw_args.next().map(|(_, v)| v))
+            };
+
+            match flag {
+                "run" if idx == 0 => args.command = Command::Run,
+                "profile" if idx == 0 => args.command = Command::Profile,
+                "lint" if idx == 0 => args.command = Command::Lint,
+                "fmt" if idx == 0 => args.command = Command::Fmt,
+                "minify" if idx == 0 => args.command = Command::Minify,
+                "dump" if idx == 0 => args.command = Command::Dump,
+                "compile" if idx == 0 => args.command = Command::Compile,
+                "repl" if idx == 0 => args.command = Command::Repl,
+                "debug" if idx == 0 => args.command = Command::Debug,
+                "translate" if idx == 0 => args.command = Command::Translate,
+                "--no-optimise" => args.should_optimise = false,
+                "--dialect" | "--from" => {
+                    dialect = Some(Self::find_dialect(flag, Self::parse_value(flag, value())))
+                }
+                "--to" => {
+                    args.target_dialect =
+                        Some(Self::find_dialect(flag, Self::parse_value(flag, value())))
+                }
+                "--debug-symbol" => args.lexer_options.debug_symbol = true,
+                "--macros" => args.lexer_options.macros = true,
+                "--ext" => {
+                    let name: String = Self::parse_value(flag, value());
+                    if let Err(why) = args.lexer_options.extensions.enable(&name) {
+                        Self::exit_with_usage(Some(&format!(
+                            "Invalid value '{}' for '{}': {}",
+                            name, flag, why
+                        )));
+                    }
+                }
+                "--schedule" => args.schedule = Self::parse_value(flag, value()),
+                "--seed" => seed = Some(Self::parse_value(flag, value())),
+                "--comments" => args.lexer_options.comment_style = Self::parse_value(flag, value()),
+                "--annotate" => args.annotate = true,
+                // The format is optional, so only a value given with `=` belongs to the flag
+                "--stats" => {
+                    args.stats = Some(match &inline_value {
+                        Some(_) => Self::parse_value(flag, inline_value.clone()),
+                        None => StatsFormat::Human,
+                    })
+                }
+                "--no-ptr-check" => args.check_pointer = false,
+                "--check" => args.check = true,
+                "--format" => args.dump_format = Self::parse_value(flag, value()),
+                "-o" | "--output" => args.output_path = Some(Self::parse_value(flag, value())),
+                "--checkpoint" => args.checkpoint_path = Some(Self::parse_value(flag, value())),
+                "--resume" => args.resume_path = Some(Self::parse_value(flag, value())),
+                // Like `--stats` the format is optional, and every trace option turns tracing on
+                "--trace" => {
+                    args.trace.get_or_insert_with(TraceOptions::default).format =
+                        match &inline_value {
+                            Some(_) => Self::parse_value(flag, inline_value.clone()),
+                            None => TraceFormat::Text,
+                        }
+                }
+                "--trace-file" => {
+                    args.trace.get_or_insert_with(TraceOptions::default);
+                    args.trace_path = Some(Self::parse_value(flag, value()));
+                }
+                "--trace-every" => {
+                    let every: u64 = Self::parse_value(flag, value());
+                    if every == 0 {
+                        Self::exit_with_usage(Some(&format!("Invalid value '0' for '{}'", flag)));
+                    }
+                    args.trace.get_or_insert_with(TraceOptions::default).every = every;
+                }
+                "--trace-filter" => {
+                    let commands: String = Self::parse_value(flag, value());
+                    let tokens = commands.bytes().map(Token::from_byte).collect();
+                    match tokens {
+                        Some(tokens) => {
+                            args.trace.get_or_insert_with(TraceOptions::default).tokens = tokens
+                        }
+                        None => Self::exit_with_usage(Some(&format!(
+                            "Invalid value '{}' for '{}'",
+                            commands, flag
+                        ))),
+                    }
+                }
+                "--trace-cell" => args.trace_cells.push(Self::parse_value(flag, value())),
+                "--history" => args.history = Self::parse_value(flag, value()),
+                "--width" => args.width = Some(Self::parse_value(flag, value())),
+                "--config" => args.config_path = Some(Self::parse_value(flag, value())),
+                "--max-steps" => args.limits.max_steps = Some(Self::parse_value(flag, value())),
+                "--max-tape" => args.limits.max_tape_cells = Some(Self::parse_value(flag, value())),
+                "--max-output" => {
+                    args.limits.max_output_bytes = Some(Self::parse_value(flag, value()))
+                }
+                "--max-threads" => args.limits.max_threads = Some(Self::parse_value(flag, value())),
+                "--max-depth" => {
+                    args.limits.max_call_depth = Some(Self::parse_value(flag, value()))
+                }
+                "--timeout" => {
+                    let seconds: f64 = Self::parse_value(flag, value());
+                    match Duration::try_from_secs_f64(seconds) {
+                        Ok(timeout) => args.limits.wall_timeout = Some(timeout),
+                        Err(_) => Self::exit_with_usage(Some(&format!(
+                            "Invalid value '{}' for '{}'",
+                            seconds, flag
+                        ))),
+                    }
+                }
+                "-v" | "--verbose" => args.verbosity += 1,
+                "-vv" => args.verbosity += 2,
+                "-vvv" => args.verbosity += 3,
+                "-q" | "--quiet" => args.quiet = true,
+                "-h" | "--help" => Self::exit_with_usage(None),
+                _ if arg.starts_with('-') => {
+                    Self::exit_with_usage(Some(&format!("Unknown flag '{}'", arg)))
+                }
+                _ => args.file_path = arg,
+            }
+        }
+
+        // Without `--dialect` the extension of the program decides, either naming a
+        // built-in dialect or a definition in the dialects directory
+        args.lexer_options.dialect = dialect
+            .or_else(|| {
+                let extension = Path::new(&args.file_path).extension()?.to_str()?;
+                Dialect::from_extension(extension).or_else(|| {
+                    let path = Self::dialect_path(extension);
+                    path.is_file()
+                        .then(|| Self::find_dialect("--dialect", extension.to_string()))
+                })
+            })
+            .unwrap_or_default();
+
+        // Programs written with macros are recognised by their extension, and include
+        // files next to them
+        let file_path = Path::new(&args.file_path);
+        if file_path.extension() == Some(preprocessor::EXTENSION.as_ref()) {
+            args.lexer_options.macros = true;
+        }
+        args.lexer_options.include_dir = file_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        // A repetition is written with `{` and `}`, the same characters as the shifts
+        if args.lexer_options.macros && args.lexer_options.extensions.type1 {
+            Self::exit_with_usage(Some(
+                "Macros can't be used with '--ext type1', whose '{' and '}' shifts would be read as repetitions",
+            ));
+        }
+
+        // The seed only means something to the random schedule
+        match (&mut args.schedule, seed) {
+            (Schedule::Random(random_seed), Some(seed)) => *random_seed = seed,
+            (_, Some(_)) => Self::exit_with_usage(Some("'--seed' requires '--schedule random'")),
+            _ => {}
+        }
+
+        args
+    }
+
+    /// Finds a dialect by name, which is either a built-in dialect, the path of a
+    /// definition file or the name of a definition in the dialects directory.
+    ///
+    /// Exits the process with a usage message if the definition cannot be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - The flag the name was given to.
+    /// * `name` - The name of the dialect.
+    ///
+    /// # Returns
+    ///
+    /// The dialect.
+    fn find_dialect(flag: &str, name: String) -> Dialect {
+        if let Ok(dialect) = name.parse() {
+            return dialect;
+        }
+
+        let path = if Path::new(&name).is_file() {
+            PathBuf::from(&name)
+        } else {
+            Self::dialect_path(&name)
+        };
+        match DialectTable::load(&path) {
+            Ok(table) => Dialect::Table(Arc::new(table)),
+            Err(why) => Self::exit_with_usage(Some(&format!(
+                "Invalid value '{}' for '{}': {}",
+                name, flag, why
+            ))),
+        }
+    }
+
+    /// Gets the path of the definition of the dialect `name` in the dialects directory.
+    fn dialect_path(name: &str) -> PathBuf {
+        Path::new(DIALECTS_DIR).join(name).with_extension("toml")
+    }
+
+    /// Parses the value given to a flag.
+    ///
+    /// Exits the process with a usage message if the value is missing or invalid.
+    ///
+    /// # Arguments
+    ///
+    /// * `flag` - The flag the value belongs to.
+    /// * `value` - The raw value, if one was given.
+    ///
+    /// # Returns
+    ///
+    /// The parsed value.
+    fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+        match value {
+            Some(value) => match value.parse() {
+                Ok(parsed) => parsed,
+                Err(_) => Self::exit_with_usage(Some(&format!(
+                    "Invalid value '{}' for '{}'",
+                    value, flag
+                ))),
+            },
+            None => Self::exit_with_usage(Some(&format!("Missing value for '{}'", flag))),
+        }
+    }
+
+    /// Prints the usage message and exits.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - An optional error to print before the usage message.
+    fn exit_with_usage(error: Option<&str>) -> ! {
+        if let Some(error) = error {
+            eprintln!("{}: {}", "Error".red(), error);
+        }
+        eprintln!(
+            r#"Usage: brainfk [command] [flags] [file]
+
+Uses `{}` when no file is given. `run` and `profile` also accept
+programs saved by `compile`, recognised by the `.bfc` extension.
+
+Commands:
+    run               Execute the program (default)
+    profile           Execute the program and report hot loops and instructions
+    lint              Report likely mistakes, configured by the `[lints]` table of
+                      a brainfk.toml next to the program or in the current directory
+    fmt               Rewrite the program with a canonical layout, configured by the
+                      `[fmt]` table of brainfk.toml
+    minify            Print the program without comments, cancelled operators or
+                      loops that can never run
+    dump              Print the optimised syntax tree, or the parsed one with
+                      `--no-optimise`
+    compile           Save the parsed program to a `.bfc` file next to the source
+    repl              Run lines of code interactively, keeping the tape and
+                      procedures between them
+    debug             Step through the program forwards and backwards, with breakpoints
+    translate         Print the program in the dialect given by `--to`, without its
+                      comments
+
+Flags:
+    --no-optimise     Use the syntax tree without folding repeated operators
+    --dialect NAME    Read the program as `brainfuck` (default), `ook` for Ook!, or
+                      the dialect defined by the TOML file NAME or by NAME.toml in
+                      `{}`. Files whose extension names a dialect
+                      are read in it, such as `.ook`
+    --from NAME       The same as `--dialect`
+    --to NAME         With `translate`, the dialect to write the program in
+    --debug-symbol    Treat '#' as an instruction that dumps the tape to stderr
+    --ext NAME        Enable an extension to the language, can be given more than
+                      once: `fork` for Brainfork's `Y`, which forks a thread with a
+                      copy of the tape, zeroing the current cell in the parent and
+                      starting the child one cell to the right with that cell set to 1,
+                      `pbrain` for `(` and `)`, which define a procedure numbered by the
+                      current cell, and `:`, which calls the procedure it numbers,
+                      `type1` for Extended Brainfuck Type I's `@` end, `$` and `!`
+                      which store the current cell and retrieve it, `}}` and `{{`
+                      shifts, and the `~`, `^`, `&` and `|` bitwise operators, which
+                      combine the current cell with the stored value
+    --schedule NAME   How forked threads take turns: `round-robin` (default) runs
+                      one step of each in the order they were forked, `random` picks
+                      a thread for every step. Both give the same output every run
+    --seed N          With `--schedule random`, the seed of the generator (default 0)
+    --macros          Expand macros before reading the program, always done for
+                      `.bfm` files. `#const NAME 10` defines a constant, `#define
+                      NAME(a, b) code` a macro, continued onto the next line by a
+                      trailing `\`, and `#include "path"` expands another file.
+                      `%NAME` expands a constant or macro, `%NAME(x; y)` calls a
+                      macro, `%a` expands an argument inside a macro, and `{{+*10}}`
+                      or `{{+*%NAME}}` repeats code, so `type1` can't be enabled.
+                      Errors point at the macro call
+    --comments STYLE  How comments are written: `ignore` (default) skips every
+                      non-command character, `line` skips the rest of a line after
+                      the first one, `c` recognises `//` and `/* */` comments
+    --no-ptr-check    Skip the static check for pointer underflows before running
+    --config PATH     Read settings from PATH instead of searching for brainfk.toml
+    --check           With `fmt`, exit with an error if the program is not formatted
+                      instead of rewriting it
+    --width N         With `fmt`, the maximum number of commands on a line
+    --format FORMAT   With `dump`, render the tree as `tree` (default), `sexpr`,
+                      `json` or `dot` for a Graphviz control-flow graph
+    -o, --output PATH With `compile`, write the compiled program to PATH, with
+                      `translate`, write the translated program to PATH
+    --stats[=FORMAT]  After running, print execution statistics to stderr as a
+                      table, or as JSON with `--stats=json`
+    --annotate        With `profile`, print the source annotated with execution counts
+    --max-steps N     Stop after executing N operators and loop tests
+    --max-tape N      Stop when the tape would grow beyond N cells. Every forked
+                      thread has its own tape of up to N cells
+    --max-output N    Stop when the program writes more than N bytes
+    --max-threads N   Stop when a `Y` would make more than N threads run at once
+    --max-depth N     Stop when a `:` would nest more than N procedure calls
+    --timeout SECS    Stop when the program runs longer than SECS seconds
+    --checkpoint PATH Save a snapshot of the run to PATH if a limit stops it
+    --resume PATH     Continue the run saved by `--checkpoint` in PATH
+    --trace[=FORMAT]  Write a line for every executed step to stderr, with the step
+                      number, location, command, pointer and the value of the cell
+                      before and after, or one JSON object per step with
+                      `--trace=jsonl`
+    --trace-file PATH Write the trace to PATH instead of stderr
+    --trace-every N   Only trace every Nth step
+    --trace-filter CMDS
+                      Only trace the commands in CMDS, such as `+-`, where `[` is
+                      entering a loop and `]` is testing it again, `(` is defining
+                      a procedure and `)` is returning from it
+    --trace-cell N    Log every change to the value of cell N to stderr with the
+                      location of the instruction responsible, can be given more
+                      than once. Writes that leave the value as it was are skipped
+    --history N       With `debug`, the number of steps that can be undone
+                      (default {})
+    -v, --verbose     Log what the compiler and interpreter are doing to stderr,
+                      `-vv` adds details of every phase and `-vvv` every event
+    -q, --quiet       Only log errors
+    -h, --help        Print this message"#,
+            DEFAULT_PROGRAM, DIALECTS_DIR, DEFAULT_HISTORY
+        );
+        std::process::exit(if error.is_some() { 2 } else { 0 });
+    }
+}
@@ -0,0 +1,334 @@
+use crate::binary::{write_header, write_varint, Reader};
+use crate::grammar::{Expression, Loop, Operator, Procedure, Span, Token};
+
+pub use crate::binary::LoadError;
+
+/// File extension of compiled programs.
+pub const EXTENSION: &str = "bfc";
+
+/// Bytes every compiled program starts with.
+const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// Version of the container layout, bumped whenever it changes.
+const VERSION: u16 = 1;
+
+/// Tag written before an operator node.
+const OPERATOR_TAG: u8 = 0;
+/// Tag written before a loop node.
+const LOOP_TAG: u8 = 1;
+/// Tag written before a pbrain procedure node.
+const PROCEDURE_TAG: u8 = 2;
+
+/// Deepest nesting of loops and procedures a program is loaded with, which keeps
+/// the recursive passes over the tree from overflowing the stack.
+const MAX_NESTING: usize = 1024;
+
+/// Largest count of a pointer move, which keeps a single operator from growing
+/// the tape without bound.
+const MAX_MOVE_COUNT: usize = 1 << 24;
+
+/// A parsed program that can be saved and run later without its source.
+///
+/// The binary container is the magic bytes `BFC\0`, a little-endian `u16` version,
+/// a flags byte and the syntax tree. Each node is a tag byte followed by its span;
+/// operators then hold their command byte and count, loops and procedures the number
+/// of nodes in their body followed by those nodes. Every number is an unsigned LEB128 varint.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompiledProgram {
+    /// Whether the optimisation passes were run on the tree
+    pub optimised: bool,
+    /// The syntax tree to execute
+    pub ast: Vec<Expression>,
+}
+
+impl CompiledProgram {
+    /// Serializes the program into the `.bfc` container.
+    ///
+    /// # Returns
+    ///
+    /// The encoded bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_header(&mut bytes, MAGIC, VERSION);
+        bytes.push(self.optimised as u8);
+        write_varint(&mut bytes, self.ast.len() as u64);
+        Self::write_nodes(&mut bytes, &self.ast);
+        bytes
+    }
+
+    /// Reads a program from the `.bfc` container.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The encoded program.
+    ///
+    /// # Returns
+    ///
+    /// The program, or why it could not be read.
+    pub fn decode(bytes: &[u8]) -> Result<Self, LoadError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(MAGIC, VERSION)?;
+        let optimised = reader.byte()? & 1 == 1;
+        let ast = Self::read_nodes(&mut reader, 0)?;
+        log::debug!(
+            "decoded compiled program with {} top-level nodes",
+            ast.len()
+        );
+
+        Ok(CompiledProgram { optimised, ast })
+    }
+
+    fn write_nodes(bytes: &mut Vec<u8>, ast: &[Expression]) {
+        for expression in ast {
+            match expression {
+                Expression::Operator(operator) => {
+                    bytes.push(OPERATOR_TAG);
+                    Self::write_span(bytes, operator.span);
+                    bytes.push(operator.type_name as u8);
+                    write_varint(bytes, operator.count as u64);
+                }
+                Expression::Loop(_loop) => {
+                    bytes.push(LOOP_TAG);
+                    Self::write_span(bytes, _loop.span);
+                    write_varint(bytes, _loop.body.len() as u64);
+                    Self::write_nodes(bytes, &_loop.body);
+                }
+                Expression::Procedure(procedure) => {
+                    bytes.push(PROCEDURE_TAG);
+                    Self::write_span(bytes, procedure.span);
+                    write_varint(bytes, procedure.body.len() as u64);
+                    Self::write_nodes(bytes, &procedure.body);
+                }
+            }
+        }
+    }
+
+    fn write_span(bytes: &mut Vec<u8>, span: Span) {
+        for value in [span.line, span.col, span.byte_offset, span.len] {
+            write_varint(bytes, value as u64);
+        }
+    }
+
+    /// Reads a node count followed by that many nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Cursor over the encoded program.
+    /// * `depth` - Number of loops and procedures the nodes are nested in.
+    fn read_nodes(reader: &mut Reader, depth: usize) -> Result<Vec<Expression>, LoadError> {
+        if depth > MAX_NESTING {
+            return Err(LoadError::TooDeep(MAX_NESTING));
+        }
+        let count = reader.usize()?;
+        // Every node takes at least one byte, which bounds the allocation for bad counts
+        let mut ast = Vec::with_capacity(count.min(reader.remaining()));
+
+        for _ in 0..count {
+            let tag = reader.byte()?;
+            let span = Span {
+                line: reader.usize()?,
+                col: reader.usize()?,
+                byte_offset: reader.usize()?,
+                len: reader.usize()?,
+            };
+            ast.push(match tag {
+                OPERATOR_TAG => {
+                    let byte = reader.byte()?;
+                    let type_name = Token::from_byte(byte)
+                        .filter(|token| {
+                            !matches!(
+                                token,
+                                Token::LoopStart
+                                    | Token::LoopEnd
+                                    | Token::ProcStart
+                                    | Token::ProcEnd
+                            )
+                        })
+                        .ok_or(LoadError::InvalidToken(byte))?;
+                    let count = reader.usize()?;
+                    if !Self::is_valid_count(type_name, count) {
+                        return Err(LoadError::InvalidCount(count));
+                    }
+                    Expression::Operator(Box::new(Operator {
+                        type_name,
+                        count,
+                        span,
+                    }))
+                }
+                LOOP_TAG => Expression::Loop(Loop {
+                    body: Self::read_nodes(reader, depth + 1)?,
+                    span,
+                }),
+                PROCEDURE_TAG => Expression::Procedure(Procedure {
+                    body: Self::read_nodes(reader, depth + 1)?,
+                    span,
+                }),
+                _ => return Err(LoadError::InvalidTag(tag)),
+            });
+        }
+
+        Ok(ast)
+    }
+
+    /// Checks whether the parser can produce an operator repeating `token` `count` times.
+    ///
+    /// Only foldable commands are repeated, and changes to a cell are folded modulo
+    /// the cell size.
+    fn is_valid_count(token: Token, count: usize) -> bool {
+        match token {
+            Token::Add | Token::Sub => (1..=u8::MAX as usize).contains(&count),
+            Token::MoveForward | Token::MoveBack => (1..=MAX_MOVE_COUNT).contains(&count),
+            _ if token.is_foldable() => count >= 1,
+            _ => count == 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::LexerOptions;
+    use crate::pipeline::Pipeline;
+
+    /// Encodes a program with a single operator node.
+    fn encode_operator(token: Token, count: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_header(&mut bytes, MAGIC, VERSION);
+        bytes.push(1);
+        write_varint(&mut bytes, 1);
+        bytes.push(OPERATOR_TAG);
+        CompiledProgram::write_span(&mut bytes, Span::default());
+        bytes.push(token as u8);
+        write_varint(&mut bytes, count);
+        bytes
+    }
+
+    /// Encodes a program made of `depth` loops nested in each other.
+    fn encode_nested(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_header(&mut bytes, MAGIC, VERSION);
+        bytes.push(0);
+        for _ in 0..depth {
+            write_varint(&mut bytes, 1);
+            bytes.push(LOOP_TAG);
+            CompiledProgram::write_span(&mut bytes, Span::default());
+        }
+        write_varint(&mut bytes, 0);
+        bytes
+    }
+
+    #[test]
+    fn round_trip_keeps_tree() {
+        let mut options = LexerOptions::default();
+        options.extensions.pbrain = true;
+        let program = CompiledProgram {
+            optimised: true,
+            ast: Pipeline::compile(String::from("+(>++[-<])>>:,."), true, options).into_ast(),
+        };
+
+        let decoded = CompiledProgram::decode(&program.encode()).unwrap();
+        assert!(decoded.optimised);
+        assert_eq!(format!("{:?}", decoded.ast), format!("{:?}", program.ast));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_tree() {
+        let mut options = LexerOptions::default();
+        options.extensions.pbrain = true;
+        let program = CompiledProgram {
+            optimised: false,
+            ast: Pipeline::compile(String::from("+(>++[-<])>>:,."), false, options).into_ast(),
+        };
+
+        let text = toml::to_string(&program).unwrap();
+        let decoded: CompiledProgram = toml::from_str(&text).unwrap();
+        assert!(!decoded.optimised);
+        assert_eq!(format!("{:?}", decoded.ast), format!("{:?}", program.ast));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = encode_operator(Token::Add, 1);
+        bytes[0] = b'X';
+        assert_eq!(
+            CompiledProgram::decode(&bytes).unwrap_err(),
+            LoadError::BadMagic
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let bytes = encode_operator(Token::Add, 1);
+        assert_eq!(
+            CompiledProgram::decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+            LoadError::Truncated
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_tag() {
+        let mut bytes = encode_operator(Token::Add, 1);
+        bytes[8] = 9;
+        assert_eq!(
+            CompiledProgram::decode(&bytes).unwrap_err(),
+            LoadError::InvalidTag(9)
+        );
+    }
+
+    #[test]
+    fn rejects_bracket_operator() {
+        let bytes = encode_operator(Token::LoopStart, 1);
+        assert_eq!(
+            CompiledProgram::decode(&bytes).unwrap_err(),
+            LoadError::InvalidToken(b'[')
+        );
+    }
+
+    #[test]
+    fn rejects_cell_change_above_cell_size() {
+        let bytes = encode_operator(Token::Sub, 300);
+        assert_eq!(
+            CompiledProgram::decode(&bytes).unwrap_err(),
+            LoadError::InvalidCount(300)
+        );
+    }
+
+    #[test]
+    fn rejects_huge_move() {
+        let count = MAX_MOVE_COUNT as u64 + 1;
+        let bytes = encode_operator(Token::MoveForward, count);
+        assert_eq!(
+            CompiledProgram::decode(&bytes).unwrap_err(),
+            LoadError::InvalidCount(count as usize)
+        );
+    }
+
+    #[test]
+    fn rejects_zero_and_repeated_io_counts() {
+        for (token, count) in [(Token::Add, 0), (Token::StdOut, 2)] {
+            let bytes = encode_operator(token, count);
+            assert_eq!(
+                CompiledProgram::decode(&bytes).unwrap_err(),
+                LoadError::InvalidCount(count as usize)
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_largest_counts() {
+        assert!(CompiledProgram::decode(&encode_operator(Token::Sub, 255)).is_ok());
+        let bytes = encode_operator(Token::MoveForward, MAX_MOVE_COUNT as u64);
+        assert!(CompiledProgram::decode(&bytes).is_ok());
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        assert!(CompiledProgram::decode(&encode_nested(MAX_NESTING)).is_ok());
+        assert_eq!(
+            CompiledProgram::decode(&encode_nested(MAX_NESTING + 1)).unwrap_err(),
+            LoadError::TooDeep(MAX_NESTING)
+        );
+    }
+}
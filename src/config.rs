@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formatter::FormatOptions;
+use crate::lint::{LintKind, LintLevel};
+
+/// Settings read from a `brainfk.toml` file.
+///
+/// ```toml
+/// [lints]
+/// pointer-drift = "allow"
+/// infinite-loop = "deny"
+///
+/// [fmt]
+/// max_width = 100
+/// indent = 2
+/// comment_column = 32
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// Level of every configured lint, the others use the severity chosen by the check
+    pub lints: HashMap<LintKind, LintLevel>,
+    /// Layout used by the formatter
+    pub format: FormatOptions,
+}
+
+impl Config {
+    /// Name of the file settings are read from
+    pub const FILE_NAME: &'static str = "brainfk.toml";
+
+    /// Parses the settings from the contents of a `brainfk.toml` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The contents of the file.
+    ///
+    /// # Returns
+    ///
+    /// The parsed settings, or a description of what is wrong with them.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|error| format!("{}", error))?;
+        let mut config = Config::default();
+
+        if let Some(lints) = table.get("lints") {
+            let lints = lints
+                .as_table()
+                .ok_or_else(|| String::from("'lints' must be a table"))?;
+            for (name, level) in lints {
+                let kind =
+                    LintKind::from_name(name).ok_or_else(|| format!("Unknown lint '{}'", name))?;
+                let level = level
+                    .as_str()
+                    .ok_or_else(|| format!("Level of lint '{}' must be a string", name))?
+                    .parse()?;
+                config.lints.insert(kind, level);
+            }
+        }
+
+        if let Some(format) = table.get("fmt") {
+            let format = format
+                .as_table()
+                .ok_or_else(|| String::from("'fmt' must be a table"))?;
+            for (name, value) in format {
+                let value = value
+                    .as_integer()
+                    .and_then(|value| usize::try_from(value).ok())
+                    .ok_or_else(|| format!("'{}' must be a positive integer", name))?;
+                match name.as_str() {
+                    "max_width" => config.format.max_width = value,
+                    "indent" => config.format.indent = value,
+                    "comment_column" => config.format.comment_column = value,
+                    _ => return Err(format!("Unknown formatter setting '{}'", name)),
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the settings from a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file to read.
+    ///
+    /// # Returns
+    ///
+    /// The parsed settings, or a description of why they could not be read.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|why| format!("couldn't read {}: {}", path.display(), why))?;
+        Self::parse(&text).map_err(|why| format!("{}: {}", path.display(), why))
+    }
+
+    /// Finds the settings that apply to a program, looking in the directory of the
+    /// program and then in the current directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `program_path` - Path of the program.
+    ///
+    /// # Returns
+    ///
+    /// The settings found, the defaults if there is no `brainfk.toml`, or a description
+    /// of why the file that was found could not be read.
+    pub fn find(program_path: &Path) -> Result<Self, String> {
+        let program_dir = program_path.parent().map(|dir| dir.join(Self::FILE_NAME));
+        let candidates: Vec<PathBuf> = program_dir
+            .into_iter()
+            .chain(std::iter::once(PathBuf::from(Self::FILE_NAME)))
+            .collect();
+
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => {
+                log::debug!("using settings from {}", path.display());
+                Self::load(path)
+            }
+            None => {
+                log::debug!("no {} found, using the default settings", Self::FILE_NAME);
+                Ok(Config::default())
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use brainfk::grammar::{Expression, Span, Token};
+use brainfk::interpreter::Interpreter;
+use brainfk::watch::{WatchAction, Watchpoint};
+use colored::Colorize;
+
+use crate::cli::Args;
+
+/// Prompt shown when waiting for a debugger command.
+const PROMPT: &str = "(bfdb) ";
+
+/// A location execution stops at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Breakpoint {
+    line: usize,
+    /// Column within the line, or `None` to stop when execution reaches the line
+    col: Option<usize>,
+}
+
+impl Breakpoint {
+    /// Parses a breakpoint written as `LINE` or `LINE:COL`.
+    fn parse(location: &str) -> Option<Self> {
+        let (line, col) = match location.split_once(':') {
+            Some((line, col)) => (line, Some(col.parse().ok()?)),
+            None => (location, None),
+        };
+        Some(Breakpoint {
+            line: line.parse().ok()?,
+            col,
+        })
+    }
+
+    /// Checks whether execution should stop before the instruction at `span`.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Location of the instruction about to execute.
+    /// * `previous` - Location of the instruction that executed before it.
+    fn is_hit(&self, span: Span, previous: Option<Span>) -> bool {
+        match self.col {
+            Some(col) => span.line == self.line && (span.col..span.col + span.len).contains(&col),
+            // Without a column only stop when execution arrives on the line
+            None => span.line == self.line && previous.is_none_or(|prev| prev.line != self.line),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.col {
+            Some(col) => write!(f, "{}:{}", self.line, col),
+            None => write!(f, "{}", self.line),
+        }
+    }
+}
+
+/// Interactive debugger that steps through a program forwards and backwards.
+pub struct Debugger<'a> {
+    /// Runs the program one step at a time, recording each step so it can be undone
+    interpreter: Interpreter<'a>,
+    /// The program being debugged
+    source: &'a str,
+    /// Locations execution stops at, in the order they were added
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a> Debugger<'a> {
+    /// Creates a debugger paused before the first instruction.
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax_tree` - The program to debug.
+    /// * `source` - The source the program was parsed from.
+    /// * `args` - The parsed command line.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Debugger`.
+    pub fn new(syntax_tree: &'a [Expression], source: &'a str, args: &Args) -> Self {
+        let mut interpreter = Interpreter::new(syntax_tree);
+        interpreter.set_limits(args.limits);
+        interpreter.set_schedule(args.schedule);
+        interpreter.enable_undo_log(args.history);
+        for &cell in &args.trace_cells {
+            interpreter.add_watchpoint(Watchpoint::Change(cell), WatchAction::Log);
+        }
+        if let Some(options) = &args.trace {
+            interpreter.enable_trace(options.clone());
+        }
+
+        Debugger {
+            interpreter,
+            source,
+            breakpoints: vec![],
+        }
+    }
+
+    /// Reads and runs debugger commands from stdin until `quit` or the end of input.
+    pub fn run(&mut self) {
+        println!("Brainfuck debugger, enter help for the list of commands");
+        self.show_position();
+
+        let stdin = io::stdin();
+        let mut line = String::new();
+        let mut last_command = String::from("step");
+        loop {
+            print!("{}", PROMPT);
+            let _ = io::stdout().flush();
+
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) => {
+                    println!();
+                    break;
+                }
+                Ok(_) => {}
+                Err(why) => {
+                    eprintln!("{}: couldn't read input: {}", "Error".red(), why);
+                    break;
+                }
+            }
+
+            // An empty line repeats the previous command
+            let command = match line.trim() {
+                "" => last_command.clone(),
+                command => command.to_string(),
+            };
+            if !self.command(&command) {
+                break;
+            }
+            last_command = command;
+        }
+    }
+
+    /// Runs a debugger command.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The line entered.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the session should end.
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+        let count = || match argument {
+            "" => Some(1),
+            count => count.parse::<usize>().ok(),
+        };
+
+        let output_bytes = self.interpreter.stats().output_bytes;
+        match name {
+            "s" | "step" => match count() {
+                Some(count) => self.step(count),
+                None => return self.usage(command),
+            },
+            "c" | "continue" => self.resume(),
+            "rs" | "reverse-step" => match count() {
+                Some(count) => self.reverse_step(count),
+                None => return self.usage(command),
+            },
+            "rc" | "reverse-continue" => self.reverse_continue(),
+            "b" | "break" if argument.is_empty() => {
+                for (idx, breakpoint) in self.breakpoints.iter().enumerate() {
+                    println!("{}: {}", idx + 1, breakpoint);
+                }
+            }
+            "b" | "break" => match Breakpoint::parse(argument) {
+                Some(breakpoint) => {
+                    self.breakpoints.push(breakpoint);
+                    println!("Breakpoint {} at {}", self.breakpoints.len(), breakpoint);
+                }
+                None => return self.usage(command),
+            },
+            "d" | "delete" if argument.is_empty() => self.breakpoints.clear(),
+            "d" | "delete" => match argument.parse::<usize>() {
+                Ok(number) if (1..=self.breakpoints.len()).contains(&number) => {
+                    self.breakpoints.remove(number - 1);
+                }
+                _ => return self.usage(command),
+            },
+            "watch" if argument.is_empty() => {
+                for (idx, (watchpoint, action)) in self.interpreter.watchpoints().iter().enumerate()
+                {
+                    let kind = match action {
+                        WatchAction::Break => "",
+                        WatchAction::Log => " (trace)",
+                    };
+                    println!("{}: {}{}", idx + 1, watchpoint, kind);
+                }
+            }
+            "watch" => match argument.parse::<Watchpoint>() {
+                Ok(watchpoint) => {
+                    let idx = self
+                        .interpreter
+                        .add_watchpoint(watchpoint, WatchAction::Break);
+                    println!("Watchpoint {}: {}", idx + 1, watchpoint);
+                }
+                Err(why) => eprintln!("{}: {}", "Error".red(), why),
+            },
+            "unwatch" if argument.is_empty() => {
+                while self.interpreter.remove_watchpoint(0).is_some() {}
+            }
+            "unwatch" => {
+                let removed = argument
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                    .and_then(|idx| self.interpreter.remove_watchpoint(idx));
+                if removed.is_none() {
+                    return self.usage(command);
+                }
+            }
+            "tape" => println!("{}", self.interpreter.render_tape()),
+            "p" | "ptr" => {
+                let cell_ptr = self.interpreter.cell_ptr();
+                println!(
+                    "cell_ptr={} value={}",
+                    cell_ptr,
+                    self.interpreter.tape()[cell_ptr]
+                );
+            }
+            "w" | "where" => self.show_position(),
+            "q" | "quit" => return false,
+            "h" | "help" => println!(
+                r#"Commands:
+    s, step [N]             Execute the next N instructions (default 1)
+    c, continue             Run until a breakpoint, a watchpoint, a `#` or the end of
+                            the program
+    rs, reverse-step [N]    Undo the last N instructions (default 1)
+    rc, reverse-continue    Undo instructions until a breakpoint, a watchpoint or the
+                            start of the history
+    b, break [LINE[:COL]]   Stop at a location, or list the breakpoints
+    d, delete [N]           Delete breakpoint N, or every breakpoint
+    watch [WATCHPOINT]      Stop when a step meets a condition, or list the watchpoints:
+                              read CELL          the cell is read
+                              write CELL         the cell is written
+                              change CELL        the cell is written with a new value
+                              value CELL VALUE   the cell takes the value
+                              ptr START END      the pointer moves onto the cells
+    unwatch [N]             Delete watchpoint N, or every watchpoint
+    tape                    Show the cells around the pointer
+    p, ptr                  Show the pointer and the value of the current cell
+    w, where                Show the instruction about to execute
+    q, quit                 Leave the debugger
+An empty line repeats the previous command."#
+            ),
+            _ => return self.usage(command),
+        }
+
+        // Show what the program printed, which can no longer be undone
+        self.interpreter.flush_output();
+        if self.interpreter.stats().output_bytes != output_bytes {
+            println!();
+        }
+        true
+    }
+
+    /// Prints an error for a command that was not understood.
+    fn usage(&self, command: &str) -> bool {
+        eprintln!(
+            "{}: Invalid command '{}', enter help for the list of commands",
+            "Error".red(),
+            command
+        );
+        true
+    }
+
+    /// Executes up to `count` instructions, stopping early at a watchpoint.
+    fn step(&mut self, count: usize) {
+        for _ in 0..count {
+            if !self.step_once() {
+                return;
+            }
+            if self.report_watch_hits() {
+                break;
+            }
+        }
+        self.show_position();
+    }
+
+    /// Executes instructions until a breakpoint, a `#` or the end of the program.
+    fn resume(&mut self) {
+        loop {
+            let previous = self.interpreter.current_span();
+            if !self.step_once() {
+                return;
+            }
+            if self.report_watch_hits() {
+                break;
+            }
+            if self.interpreter.current_token() == Some(Token::Debug) {
+                println!("Paused at '#'");
+                break;
+            }
+            if let Some(number) = self.hit_breakpoint(previous) {
+                println!("Breakpoint {}", number);
+                break;
+            }
+        }
+        self.show_position();
+    }
+
+    /// Undoes up to `count` instructions, stopping early at a watchpoint.
+    fn reverse_step(&mut self, count: usize) {
+        for _ in 0..count {
+            if !self.step_back_once() || self.report_watch_hits() {
+                break;
+            }
+        }
+        self.show_position();
+    }
+
+    /// Undoes instructions until a breakpoint, a watchpoint or the start of the
+    /// recorded history.
+    fn reverse_continue(&mut self) {
+        loop {
+            let previous = self.interpreter.current_span();
+            if !self.step_back_once() || self.report_watch_hits() {
+                break;
+            }
+            if let Some(number) = self.hit_breakpoint(previous) {
+                println!("Breakpoint {}", number);
+                break;
+            }
+        }
+        self.show_position();
+    }
+
+    /// Executes a single instruction, reporting why if execution cannot continue.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the program has finished or the instruction failed.
+    fn step_once(&mut self) -> bool {
+        match self.interpreter.step() {
+            Ok(_) if self.interpreter.is_finished() => {
+                self.interpreter.flush_output();
+                println!("Program finished");
+                false
+            }
+            Ok(more) => more,
+            Err(error) => {
+                eprintln!("{}: {}", "Error".red(), error);
+                self.show_position();
+                false
+            }
+        }
+    }
+
+    /// Undoes a single instruction, reporting if there is nothing left to undo.
+    fn step_back_once(&mut self) -> bool {
+        if self.interpreter.step_back() {
+            return true;
+        }
+        println!("Reached the start of the recorded history");
+        false
+    }
+
+    /// Prints the watchpoints hit by the last step, or by the step just undone.
+    ///
+    /// # Returns
+    ///
+    /// `true` if any watchpoint was hit.
+    fn report_watch_hits(&mut self) -> bool {
+        let hits = self.interpreter.take_watch_hits();
+        for hit in &hits {
+            println!("Watchpoint {} ({}): {}", hit.index + 1, hit.watchpoint, hit);
+        }
+        !hits.is_empty()
+    }
+
+    /// Gets the number of the first breakpoint at the instruction about to execute.
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - Location of the instruction that executed before it.
+    fn hit_breakpoint(&self, previous: Option<Span>) -> Option<usize> {
+        let span = self.interpreter.current_span()?;
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| breakpoint.is_hit(span, previous))
+            .map(|idx| idx + 1)
+    }
+
+    /// Prints the source line of the instruction about to execute, with a marker under it.
+    fn show_position(&self) {
+        let Some(span) = self.interpreter.current_span() else {
+            println!("At the end of the program");
+            return;
+        };
+
+        let line = self.source.lines().nth(span.line - 1).unwrap_or_default();
+        let gutter = " ".repeat(span.line.to_string().len());
+        println!(
+            "{} {}\n{} | {}\n{} | {}{}",
+            "at".bold(),
+            span,
+            span.line,
+            line,
+            gutter,
+            " ".repeat(span.col - 1),
+            "^".yellow()
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use colored::Colorize;
+
+use crate::grammar::Span;
+
+/// How serious a reported problem is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "Warning"),
+            Severity::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// A problem found in a program, pointing at the source that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Identifies the check that produced the diagnostic, such as a lint code
+    pub code: Option<&'static str>,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Number of characters shown on each side of the reported column
+    const CONTEXT_LEN: usize = 30;
+
+    /// Creates a new error diagnostic.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Location of the problem.
+    /// * `message` - Description of the problem.
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Creates a new warning diagnostic.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Location of the problem.
+    /// * `message` - Description of the problem.
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: None,
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// Attaches the code of the check that produced the diagnostic.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code to attach.
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Renders the diagnostic along with the line of source it points at.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The program the diagnostic was reported for.
+    ///
+    /// # Returns
+    ///
+    /// The diagnostic as a printable string.
+    pub fn render(&self, source: &str) -> String {
+        let line: Vec<char> = source
+            .lines()
+            .nth(self.span.line.saturating_sub(1))
+            .unwrap_or_default()
+            .chars()
+            .collect();
+        let column = self.span.col.saturating_sub(1).min(line.len());
+        let start = column.saturating_sub(Self::CONTEXT_LEN);
+        let end = (column + Self::CONTEXT_LEN).min(line.len());
+        let excerpt: String = line[start..end].iter().collect();
+        let space = " ".repeat(column - start);
+
+        let label = match self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        };
+        let severity = match self.severity {
+            Severity::Warning => label.yellow(),
+            Severity::Error => label.red(),
+        };
+        let line_details = format!("Line={} | Col={}", self.span.line, self.span.col).bold();
+
+        format!(
+            "{severity}: {line_details}\n    {}\n    {space}^\n    {space}|----- {}",
+            excerpt.white(),
+            self.message
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(
+                f,
+                "{}[{}]: {} at {}",
+                self.severity, code, self.message, self.span
+            ),
+            None => write!(f, "{}: {} at {}", self.severity, self.message, self.span),
+        }
+    }
+}
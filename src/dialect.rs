@@ -0,0 +1,454 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::grammar::Token;
+
+/// The languages a program can be written in, all tokenized into Brainfuck commands.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Dialect {
+    /// One character per command
+    #[default]
+    Brainfuck,
+    /// Pairs of the words `Ook.`, `Ook?` and `Ook!`, such as `Ook. Ook?` for `>`
+    Ook,
+    /// Commands spelled as given by a dialect definition file
+    Table(Arc<DialectTable>),
+}
+
+impl Dialect {
+    /// Maximum number of characters on a line written by `write`
+    const LINE_WIDTH: usize = 80;
+
+    /// Gets the built-in dialect a file extension, without the leading dot, is used for.
+    ///
+    /// # Returns
+    ///
+    /// The dialect, or `None` if the extension is not specific to one.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "ook" => Some(Dialect::Ook),
+            _ => None,
+        }
+    }
+
+    /// Gets the name of the dialect.
+    pub fn name(&self) -> &str {
+        match self {
+            Dialect::Brainfuck => "brainfuck",
+            Dialect::Ook => "ook",
+            Dialect::Table(table) => &table.name,
+        }
+    }
+
+    /// Gets how a command is written in the dialect.
+    ///
+    /// # Returns
+    ///
+    /// The spelling, or `None` if the dialect has no way to write the command.
+    pub fn spelling(&self, token: Token) -> Option<&str> {
+        match self {
+            Dialect::Brainfuck => Some(match token {
+                Token::MoveBack => "<",
+                Token::MoveForward => ">",
+                Token::Add => "+",
+                Token::Sub => "-",
+                Token::StdOut => ".",
+                Token::StdIn => ",",
+                Token::LoopStart => "[",
+                Token::LoopEnd => "]",
+                Token::Debug => "#",
+                Token::Fork => "Y",
+                Token::ProcStart => "(",
+                Token::ProcEnd => ")",
+                Token::Call => ":",
+                Token::End => "@",
+                Token::Store => "$",
+                Token::Retrieve => "!",
+                Token::ShiftRight => "}",
+                Token::ShiftLeft => "{",
+                Token::Not => "~",
+                Token::Xor => "^",
+                Token::And => "&",
+                Token::Or => "|",
+            }),
+            Dialect::Ook => match token {
+                Token::MoveBack => Some("Ook? Ook."),
+                Token::MoveForward => Some("Ook. Ook?"),
+                Token::Add => Some("Ook. Ook."),
+                Token::Sub => Some("Ook! Ook!"),
+                Token::StdOut => Some("Ook! Ook."),
+                Token::StdIn => Some("Ook. Ook!"),
+                Token::LoopStart => Some("Ook! Ook?"),
+                Token::LoopEnd => Some("Ook? Ook!"),
+                _ => None,
+            },
+            Dialect::Table(table) => table.spelling(token),
+        }
+    }
+
+    /// Writes a list of commands in the dialect, wrapping lines at 80 characters.
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The commands to write.
+    ///
+    /// # Returns
+    ///
+    /// The program, or an error naming a command the dialect cannot write.
+    pub fn write(&self, tokens: &[Token]) -> Result<String, String> {
+        let separator = match self {
+            Dialect::Brainfuck => "",
+            Dialect::Ook => " ",
+            Dialect::Table(table) => &table.separator,
+        };
+
+        let mut out = String::new();
+        let mut line_len = 0;
+        for &token in tokens {
+            let spelling = self.spelling(token).ok_or_else(|| {
+                format!(
+                    "Dialect '{}' has no spelling for '{}'",
+                    self.name(),
+                    token as u8 as char
+                )
+            })?;
+
+            if line_len > 0 && line_len + separator.len() + spelling.len() > Self::LINE_WIDTH {
+                out.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                out.push_str(separator);
+                line_len += separator.len();
+            }
+            out.push_str(spelling);
+            line_len += spelling.len();
+        }
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+impl std::str::FromStr for Dialect {
+    type Err = String;
+
+    /// Parses the name of a built-in dialect.
+    fn from_str(dialect: &str) -> Result<Self, Self::Err> {
+        match dialect {
+            "brainfuck" | "bf" => Ok(Dialect::Brainfuck),
+            "ook" => Ok(Dialect::Ook),
+            _ => Err(format!("Unknown dialect '{}'", dialect)),
+        }
+    }
+}
+
+/// A dialect that spells every command with its own strings, read from a TOML file.
+///
+/// ```toml
+/// name = "blub"
+/// # Written between commands when translating into the dialect, a space by default
+/// separator = " "
+///
+/// # Every Brainfuck command is mapped to a spelling, or a list of them where the
+/// # first is used when translating. Spaces match any run of whitespace.
+/// [commands]
+/// ">" = "Blub. Blub?"
+/// "<" = ["Blub? Blub.", "Back."]
+/// ```
+///
+/// `#` and the commands of extensions, such as `Y`, are optional, every other
+/// command must have a spelling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialectTable {
+    /// Name of the dialect
+    pub name: String,
+    /// Text written between commands
+    pub separator: String,
+    /// Every spelling with the command it stands for, first spellings first
+    spellings: Vec<(String, Token)>,
+}
+
+impl DialectTable {
+    /// Parses a dialect definition from the contents of a TOML file.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The contents of the file.
+    ///
+    /// # Returns
+    ///
+    /// The dialect, or a description of what is wrong with the definition.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|error| format!("{}", error))?;
+
+        let name = match table.get("name") {
+            Some(name) => name
+                .as_str()
+                .ok_or_else(|| String::from("'name' must be a string"))?,
+            None => return Err(String::from("Missing 'name'")),
+        };
+        let separator = match table.get("separator") {
+            Some(separator) => separator
+                .as_str()
+                .ok_or_else(|| String::from("'separator' must be a string"))?,
+            None => " ",
+        };
+        let commands = table
+            .get("commands")
+            .and_then(|commands| commands.as_table())
+            .ok_or_else(|| String::from("Missing the 'commands' table"))?;
+
+        let mut first_spellings: Vec<(String, Token)> = vec![];
+        let mut aliases: Vec<(String, Token)> = vec![];
+        for (command, spellings) in commands {
+            let token = match command.as_bytes() {
+                &[byte] => Token::from_byte(byte),
+                _ => None,
+            }
+            .ok_or_else(|| format!("'{}' is not a Brainfuck command", command))?;
+
+            let spellings: Vec<&str> = match spellings {
+                toml::Value::String(spelling) => vec![spelling],
+                toml::Value::Array(spellings) => spellings
+                    .iter()
+                    .map(|spelling| spelling.as_str())
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| format!("Spellings of '{}' must be strings", command))?,
+                _ => return Err(format!("Spelling of '{}' must be a string", command)),
+            };
+            for (idx, spelling) in spellings.into_iter().enumerate() {
+                // Whitespace inside a spelling matches any run of whitespace
+                let spelling = spelling.split_whitespace().collect::<Vec<_>>().join(" ");
+                if spelling.is_empty() {
+                    return Err(format!("Spelling of '{}' is empty", command));
+                }
+                let list = if idx == 0 {
+                    &mut first_spellings
+                } else {
+                    &mut aliases
+                };
+                list.push((spelling, token));
+            }
+        }
+
+        let mut spellings = first_spellings;
+        spellings.extend(aliases);
+        for (idx, (spelling, token)) in spellings.iter().enumerate() {
+            if let Some((_, other)) = spellings[..idx].iter().find(|(s, _)| s == spelling) {
+                if other != token {
+                    return Err(format!(
+                        "'{}' spells both '{}' and '{}'",
+                        spelling, *other as u8 as char, *token as u8 as char
+                    ));
+                }
+            }
+        }
+        if let Some(token) = Token::ALL.into_iter().find(|&token| {
+            token.is_standard() && spellings.iter().all(|(_, other)| *other != token)
+        }) {
+            return Err(format!("Missing a spelling for '{}'", token as u8 as char));
+        }
+
+        Ok(DialectTable {
+            name: name.to_string(),
+            separator: separator.to_string(),
+            spellings,
+        })
+    }
+
+    /// Reads a dialect definition from a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file to read.
+    ///
+    /// # Returns
+    ///
+    /// The dialect, or a description of why it could not be read.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|why| format!("couldn't read {}: {}", path.display(), why))?;
+        Self::parse(&text).map_err(|why| format!("{}: {}", path.display(), why))
+    }
+
+    /// Gets the first spelling of a command.
+    pub fn spelling(&self, token: Token) -> Option<&str> {
+        self.spellings
+            .iter()
+            .find(|(_, other)| *other == token)
+            .map(|(spelling, _)| spelling.as_str())
+    }
+
+    /// Finds the longest spelling that `text` starts with.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The rest of the program.
+    ///
+    /// # Returns
+    ///
+    /// The command and the number of bytes of `text` its spelling covers, or `None`
+    /// if no spelling matches.
+    pub fn longest_match(&self, text: &str) -> Option<(Token, usize)> {
+        self.spellings
+            .iter()
+            .filter_map(|(spelling, token)| Some((*token, Self::match_len(spelling, text)?)))
+            .fold(None, |longest, (token, len)| match longest {
+                Some((_, longest_len)) if longest_len >= len => longest,
+                _ => Some((token, len)),
+            })
+    }
+
+    /// Gets the number of bytes of `text` matched by `spelling`, where each space in
+    /// the spelling matches one or more whitespace characters.
+    fn match_len(spelling: &str, text: &str) -> Option<usize> {
+        let mut len = 0;
+        for (idx, word) in spelling.split(' ').enumerate() {
+            if idx > 0 {
+                let rest = &text[len..];
+                let whitespace = rest.len() - rest.trim_start().len();
+                if whitespace == 0 {
+                    return None;
+                }
+                len += whitespace;
+            }
+            if !text[len..].starts_with(word) {
+                return None;
+            }
+            len += word.len();
+        }
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, LexerOptions};
+
+    /// The commands every dialect has to spell, with `a` and `ab` for `+` and `-`.
+    const OVERLAPPING: &str = r#"
+        name = "overlap"
+
+        [commands]
+        ">" = "r"
+        "<" = "l"
+        "+" = "a"
+        "-" = ["ab", "minus  one"]
+        "." = "o"
+        "," = "i"
+        "[" = "("
+        "]" = ")"
+    "#;
+
+    /// Tokenizes `program` in `dialect`, returning only the commands.
+    fn tokens(program: &str, dialect: Dialect) -> Vec<Token> {
+        let options = LexerOptions {
+            dialect,
+            ..LexerOptions::default()
+        };
+        let lexer = Lexer::try_new(program, options).unwrap();
+        lexer.tokens().iter().map(|token| token.token).collect()
+    }
+
+    #[test]
+    fn longest_spelling_wins() {
+        let table = DialectTable::parse(OVERLAPPING).unwrap();
+        assert_eq!(table.longest_match("abc"), Some((Token::Sub, 2)));
+        assert_eq!(table.longest_match("ac"), Some((Token::Add, 1)));
+        assert_eq!(table.longest_match("x"), None);
+
+        let dialect = Dialect::Table(Arc::new(table));
+        assert_eq!(
+            tokens("aab a\nminus \t one", dialect),
+            [Token::Add, Token::Sub, Token::Add, Token::Sub]
+        );
+    }
+
+    #[test]
+    fn spellings_can_cover_several_lines() {
+        let dialect = Dialect::Table(Arc::new(DialectTable::parse(OVERLAPPING).unwrap()));
+        let options = LexerOptions {
+            dialect,
+            ..LexerOptions::default()
+        };
+        let lexer = Lexer::try_new("minus\none a", options).unwrap();
+        let spans: Vec<(usize, usize)> = lexer
+            .tokens()
+            .iter()
+            .map(|token| (token.span.line, token.span.col))
+            .collect();
+        assert_eq!(spans, [(1, 1), (2, 5)]);
+    }
+
+    #[test]
+    fn rejects_spelling_shared_by_two_commands() {
+        let text = OVERLAPPING.replace(r#""." = "o""#, r#""." = "a""#);
+        assert_eq!(
+            DialectTable::parse(&text).unwrap_err(),
+            "'a' spells both '+' and '.'"
+        );
+    }
+
+    #[test]
+    fn accepts_repeated_spelling_of_one_command() {
+        let text = OVERLAPPING.replace(r#""+" = "a""#, r#""+" = ["a", "a"]"#);
+        assert!(DialectTable::parse(&text).is_ok());
+    }
+
+    #[test]
+    fn rejects_incomplete_or_unknown_commands() {
+        let missing = OVERLAPPING.replace(r#"">" = "r""#, "");
+        assert_eq!(
+            DialectTable::parse(&missing).unwrap_err(),
+            "Missing a spelling for '>'"
+        );
+        let unknown = OVERLAPPING.replace(r#"">" = "r""#, r#"">>" = "r""#);
+        assert_eq!(
+            DialectTable::parse(&unknown).unwrap_err(),
+            "'>>' is not a Brainfuck command"
+        );
+        assert_eq!(
+            DialectTable::parse(r#"name = "x""#).unwrap_err(),
+            "Missing the 'commands' table"
+        );
+    }
+
+    #[test]
+    fn loads_bundled_dialect() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/dialects/alphuck.toml");
+        let table = DialectTable::load(&path).unwrap();
+        assert_eq!(table.name, "alphuck");
+        assert_eq!(table.spelling(Token::LoopStart), Some("p"));
+
+        let missing = DialectTable::load(Path::new("no/such/dialect.toml")).unwrap_err();
+        assert!(missing.starts_with("couldn't read"), "{}", missing);
+    }
+
+    #[test]
+    fn translation_round_trips() {
+        let program = [
+            Token::Add,
+            Token::LoopStart,
+            Token::MoveForward,
+            Token::Sub,
+            Token::MoveBack,
+            Token::LoopEnd,
+            Token::StdIn,
+            Token::StdOut,
+        ];
+        let table = Dialect::Table(Arc::new(DialectTable::parse(OVERLAPPING).unwrap()));
+        for dialect in [Dialect::Brainfuck, Dialect::Ook, table] {
+            let written = dialect.write(&program).unwrap();
+            assert_eq!(tokens(&written, dialect.clone()), program, "{}", written);
+        }
+    }
+
+    #[test]
+    fn write_rejects_command_without_spelling() {
+        assert_eq!(
+            Dialect::Ook.write(&[Token::Fork]).unwrap_err(),
+            "Dialect 'ook' has no spelling for 'Y'"
+        );
+    }
+}
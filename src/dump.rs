@@ -0,0 +1,430 @@
+use std::fmt::Write;
+
+use crate::grammar::{Expression, Loop, Operator, Procedure, Span};
+
+/// The ways a syntax tree can be rendered for inspection.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DumpFormat {
+    /// An indented tree drawn with box characters
+    #[default]
+    Tree,
+    /// Nested S-expressions
+    Sexpr,
+    /// A JSON array of nodes
+    Json,
+    /// A Graphviz DOT control-flow graph
+    Dot,
+}
+
+impl std::str::FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "tree" => Ok(DumpFormat::Tree),
+            "sexpr" => Ok(DumpFormat::Sexpr),
+            "json" => Ok(DumpFormat::Json),
+            "dot" => Ok(DumpFormat::Dot),
+            _ => Err(format!("Unknown dump format '{}'", format)),
+        }
+    }
+}
+
+/// Renders syntax trees in a human or machine readable form.
+pub struct Dumper;
+
+impl Dumper {
+    /// Renders a syntax tree, including the count and location of every node.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The abstract syntax tree (AST) to render.
+    /// * `format` - The format to render it in.
+    ///
+    /// # Returns
+    ///
+    /// The rendered tree, ending with a newline.
+    pub fn dump(ast: &[Expression], format: DumpFormat) -> String {
+        let mut out = String::new();
+        match format {
+            DumpFormat::Tree => {
+                out.push_str("program\n");
+                Self::tree(ast, "", &mut out);
+            }
+            DumpFormat::Sexpr => {
+                out.push_str("(program");
+                Self::sexpr(ast, 1, &mut out);
+                out.push_str(")\n");
+            }
+            DumpFormat::Json => {
+                Self::json(ast, 0, &mut out);
+                out.push('\n');
+            }
+            DumpFormat::Dot => Self::dot(ast, &mut out),
+        }
+        out
+    }
+
+    /// Describes an operator as its name, command and count, e.g. `Add '+' x8`.
+    fn describe(operator: &Operator) -> String {
+        format!(
+            "{:?} '{}' x{}",
+            operator.type_name, operator.type_name as u8 as char, operator.count
+        )
+    }
+
+    /// Describes a location as `line:col` followed by the number of bytes it covers.
+    fn location(span: Span) -> String {
+        format!("{} len {}", span, span.len)
+    }
+
+    fn tree(ast: &[Expression], prefix: &str, out: &mut String) {
+        for (idx, expression) in ast.iter().enumerate() {
+            let last = idx + 1 == ast.len();
+            let branch = if last { "└── " } else { "├── " };
+            match expression {
+                Expression::Loop(_loop) => {
+                    let _ = writeln!(
+                        out,
+                        "{}{}loop @ {}",
+                        prefix,
+                        branch,
+                        Self::location(_loop.span)
+                    );
+                    let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                    Self::tree(&_loop.body, &child_prefix, out);
+                }
+                Expression::Procedure(procedure) => {
+                    let _ = writeln!(
+                        out,
+                        "{}{}procedure @ {}",
+                        prefix,
+                        branch,
+                        Self::location(procedure.span)
+                    );
+                    let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                    Self::tree(&procedure.body, &child_prefix, out);
+                }
+                Expression::Operator(operator) => {
+                    let _ = writeln!(
+                        out,
+                        "{}{}{} @ {}",
+                        prefix,
+                        branch,
+                        Self::describe(operator),
+                        Self::location(operator.span)
+                    );
+                }
+            }
+        }
+    }
+
+    fn sexpr(ast: &[Expression], depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for expression in ast {
+            match expression {
+                Expression::Loop(_loop) => {
+                    let _ = write!(out, "\n{}(loop {}", indent, _loop.span);
+                    Self::sexpr(&_loop.body, depth + 1, out);
+                    out.push(')');
+                }
+                Expression::Procedure(procedure) => {
+                    let _ = write!(out, "\n{}(procedure {}", indent, procedure.span);
+                    Self::sexpr(&procedure.body, depth + 1, out);
+                    out.push(')');
+                }
+                Expression::Operator(operator) => {
+                    let _ = write!(
+                        out,
+                        "\n{}({} {} {})",
+                        indent,
+                        format!("{:?}", operator.type_name).to_lowercase(),
+                        operator.count,
+                        operator.span
+                    );
+                }
+            }
+        }
+    }
+
+    fn json(ast: &[Expression], depth: usize, out: &mut String) {
+        if ast.is_empty() {
+            out.push_str("[]");
+            return;
+        }
+
+        let indent = "  ".repeat(depth + 1);
+        out.push('[');
+        for (idx, expression) in ast.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "\n{}{{", indent);
+            match expression {
+                Expression::Loop(_loop) => {
+                    let _ = write!(
+                        out,
+                        r#""type": "loop", "span": {}, "body": "#,
+                        Self::json_span(_loop.span)
+                    );
+                    Self::json(&_loop.body, depth + 1, out);
+                }
+                Expression::Procedure(procedure) => {
+                    let _ = write!(
+                        out,
+                        r#""type": "procedure", "span": {}, "body": "#,
+                        Self::json_span(procedure.span)
+                    );
+                    Self::json(&procedure.body, depth + 1, out);
+                }
+                Expression::Operator(operator) => {
+                    let _ = write!(
+                        out,
+                        r#""type": "operator", "token": "{:?}", "command": "{}", "count": {}, "span": {}"#,
+                        operator.type_name,
+                        operator.type_name as u8 as char,
+                        operator.count,
+                        Self::json_span(operator.span)
+                    );
+                }
+            }
+            out.push('}');
+        }
+        let _ = write!(out, "\n{}]", "  ".repeat(depth));
+    }
+
+    fn json_span(span: Span) -> String {
+        format!(
+            r#"{{"line": {}, "col": {}, "offset": {}, "len": {}}}"#,
+            span.line, span.col, span.byte_offset, span.len
+        )
+    }
+
+    /// Writes a control-flow graph where straight runs of operators are boxes and
+    /// every loop is a diamond that either enters its body or falls through.
+    fn dot(ast: &[Expression], out: &mut String) {
+        let mut graph = ControlFlowGraph::default();
+        let start = graph.node("start", "ellipse");
+        let exits = graph.block(ast, vec![(start, "")]);
+        let end = graph.node("end", "ellipse");
+        graph.connect(&exits, end);
+
+        out.push_str("digraph program {\n    node [fontname=\"monospace\"];\n");
+        for (idx, (label, shape)) in graph.nodes.iter().enumerate() {
+            let _ = writeln!(out, "    n{} [label=\"{}\", shape={}];", idx, label, shape);
+        }
+        for (from, to, label) in &graph.edges {
+            let _ = writeln!(out, "    n{} -> n{} [label=\"{}\"];", from, to, label);
+        }
+        out.push_str("}\n");
+    }
+}
+
+/// Nodes and edges of the graph written by `Dumper::dot`.
+#[derive(Default)]
+struct ControlFlowGraph {
+    /// Label and shape of every node
+    nodes: Vec<(String, &'static str)>,
+    /// Source node, target node and label of every edge
+    edges: Vec<(usize, usize, &'static str)>,
+}
+
+impl ControlFlowGraph {
+    fn node(&mut self, label: &str, shape: &'static str) -> usize {
+        self.nodes.push((label.to_string(), shape));
+        self.nodes.len() - 1
+    }
+
+    fn connect(&mut self, from: &[(usize, &'static str)], to: usize) {
+        for &(node, label) in from {
+            self.edges.push((node, to, label));
+        }
+    }
+
+    /// Adds the nodes for a sequence of expressions.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The expressions to add.
+    /// * `preds` - The nodes, and edge labels, control reaches the sequence from.
+    ///
+    /// # Returns
+    ///
+    /// The nodes, and edge labels, control leaves the sequence from.
+    fn block(
+        &mut self,
+        ast: &[Expression],
+        mut preds: Vec<(usize, &'static str)>,
+    ) -> Vec<(usize, &'static str)> {
+        // The box currently collecting operators, if the last expression was an operator
+        let mut current: Option<usize> = None;
+
+        for expression in ast {
+            match expression {
+                Expression::Operator(operator) => {
+                    let node = *current.get_or_insert_with(|| {
+                        let node = self.node("", "box");
+                        self.connect(&preds, node);
+                        preds = vec![(node, "")];
+                        node
+                    });
+                    let command = operator.type_name as u8 as char;
+                    self.nodes[node]
+                        .0
+                        .extend(std::iter::repeat_n(command, operator.count));
+                }
+                Expression::Loop(_loop) => {
+                    current = None;
+                    preds = self.loop_node(_loop, &preds);
+                }
+                Expression::Procedure(procedure) => {
+                    current = None;
+                    preds = self.procedure_node(procedure, &preds);
+                }
+            }
+        }
+
+        preds
+    }
+
+    fn loop_node(
+        &mut self,
+        _loop: &Loop,
+        preds: &[(usize, &'static str)],
+    ) -> Vec<(usize, &'static str)> {
+        let test = self.node(&format!("[ {}", _loop.span), "diamond");
+        self.connect(preds, test);
+
+        let exits = self.block(&_loop.body, vec![(test, "non-zero")]);
+        self.connect(&exits, test);
+
+        vec![(test, "zero")]
+    }
+
+    /// Adds a procedure definition, whose body hangs off the definition and ends in
+    /// a return instead of rejoining the program.
+    fn procedure_node(
+        &mut self,
+        procedure: &Procedure,
+        preds: &[(usize, &'static str)],
+    ) -> Vec<(usize, &'static str)> {
+        let definition = self.node(&format!("( {}", procedure.span), "component");
+        self.connect(preds, definition);
+
+        let exits = self.block(&procedure.body, vec![(definition, "call")]);
+        let ret = self.node("return", "ellipse");
+        self.connect(&exits, ret);
+
+        vec![(definition, "")]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::LexerOptions;
+    use crate::pipeline::Pipeline;
+
+    /// A folded run followed by a loop moving the cell one to the right.
+    const PROGRAM: &str = "++[->+<]";
+
+    /// Dumps the optimised tree of `program`, like the `dump` command.
+    fn dump(program: &str, options: LexerOptions, format: DumpFormat) -> String {
+        let ast = Pipeline::compile(String::from(program), true, options).into_ast();
+        Dumper::dump(&ast, format)
+    }
+
+    fn pbrain() -> LexerOptions {
+        let mut options = LexerOptions::default();
+        options.extensions.pbrain = true;
+        options
+    }
+
+    #[test]
+    fn tree_draws_nested_nodes() {
+        assert_eq!(
+            dump(PROGRAM, LexerOptions::default(), DumpFormat::Tree),
+            "program
+├── Add '+' x2 @ 1:1 len 2
+└── loop @ 1:3 len 6
+    ├── Sub '-' x1 @ 1:4 len 1
+    ├── MoveForward '>' x1 @ 1:5 len 1
+    ├── Add '+' x1 @ 1:6 len 1
+    └── MoveBack '<' x1 @ 1:7 len 1
+"
+        );
+    }
+
+    #[test]
+    fn tree_continues_branch_past_procedure() {
+        assert_eq!(
+            dump("(+):", pbrain(), DumpFormat::Tree),
+            "program
+├── procedure @ 1:1 len 3
+│   └── Add '+' x1 @ 1:2 len 1
+└── Call ':' x1 @ 1:4 len 1
+"
+        );
+    }
+
+    #[test]
+    fn sexpr_nests_bodies() {
+        assert_eq!(
+            dump(PROGRAM, LexerOptions::default(), DumpFormat::Sexpr),
+            "(program
+  (add 2 1:1)
+  (loop 1:3
+    (sub 1 1:4)
+    (moveforward 1 1:5)
+    (add 1 1:6)
+    (moveback 1 1:7)))
+"
+        );
+        assert_eq!(
+            dump("(+):", pbrain(), DumpFormat::Sexpr),
+            "(program\n  (procedure 1:1\n    (add 1 1:2))\n  (call 1 1:4))\n"
+        );
+    }
+
+    #[test]
+    fn json_lists_nodes_with_spans() {
+        assert_eq!(
+            dump("+[-]", LexerOptions::default(), DumpFormat::Json),
+            r#"[
+  {"type": "operator", "token": "Add", "command": "+", "count": 1, "span": {"line": 1, "col": 1, "offset": 0, "len": 1}},
+  {"type": "loop", "span": {"line": 1, "col": 2, "offset": 1, "len": 3}, "body": [
+    {"type": "operator", "token": "Sub", "command": "-", "count": 1, "span": {"line": 1, "col": 3, "offset": 2, "len": 1}}
+  ]}
+]
+"#
+        );
+    }
+
+    #[test]
+    fn dot_links_loop_test_to_body_and_exit() {
+        assert_eq!(
+            dump(PROGRAM, LexerOptions::default(), DumpFormat::Dot),
+            r#"digraph program {
+    node [fontname="monospace"];
+    n0 [label="start", shape=ellipse];
+    n1 [label="++", shape=box];
+    n2 [label="[ 1:3", shape=diamond];
+    n3 [label="->+<", shape=box];
+    n4 [label="end", shape=ellipse];
+    n0 -> n1 [label=""];
+    n1 -> n2 [label=""];
+    n2 -> n3 [label="non-zero"];
+    n3 -> n2 [label=""];
+    n2 -> n4 [label="zero"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("sexpr".parse(), Ok(DumpFormat::Sexpr));
+        assert_eq!("dot".parse(), Ok(DumpFormat::Dot));
+        assert!("yaml".parse::<DumpFormat>().is_err());
+    }
+}
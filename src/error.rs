@@ -0,0 +1,59 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::grammar::Span;
+use crate::limits::Limit;
+
+/// The state of the interpreter at the point a run was stopped.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RunState {
+    /// Location of the instruction that was about to execute
+    pub span: Span,
+    /// Number of steps executed so far
+    pub steps: u64,
+    /// Position of the cell pointer
+    pub cell_ptr: usize,
+    /// Value of the cell under the pointer
+    pub cell_value: u8,
+    /// Number of bytes written to stdout so far
+    pub output_bytes: usize,
+    /// Wall clock time since the run started
+    pub elapsed: Duration,
+}
+
+/// Errors that stop a program while it is running.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RunError {
+    /// The cell pointer was moved to the left of the first cell
+    PointerUnderflow { span: Span },
+    /// `:` called a procedure number that no `(` has defined
+    UndefinedProcedure { number: u8, span: Span },
+    /// One of the configured `Limits` was reached
+    LimitExceeded { limit: Limit, state: RunState },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::PointerUnderflow { span } => {
+                write!(f, "Pointer moved before the start of the tape at {}", span)
+            }
+            RunError::UndefinedProcedure { number, span } => {
+                write!(f, "Called procedure {} before defining it at {}", number, span)
+            }
+            RunError::LimitExceeded { limit, state } => write!(
+                f,
+                "Exceeded the {} at {} | steps={} cell_ptr={} cell={} output={} bytes elapsed={:.2?}",
+                limit,
+                state.span,
+                state.steps,
+                state.cell_ptr,
+                state.cell_value,
+                state.output_bytes,
+                state.elapsed
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
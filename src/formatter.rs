@@ -0,0 +1,428 @@
+use crate::grammar::{Comment, Span, SpannedToken, Token};
+use crate::lexer::Lexer;
+
+/// Settings controlling the layout produced by the `Formatter`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// Maximum number of characters of commands on a line
+    pub max_width: usize,
+    /// Number of spaces each nested loop is indented by
+    pub indent: usize,
+    /// Column trailing comments are aligned to when the commands before them fit
+    pub comment_column: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            max_width: 80,
+            indent: 4,
+            comment_column: 24,
+        }
+    }
+}
+
+/// A token or comment, in the order they appear in the program.
+#[derive(Debug, Copy, Clone)]
+enum Item<'a> {
+    Token(SpannedToken),
+    Comment(&'a Comment),
+}
+
+impl Item<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Item::Token(token) => token.span,
+            Item::Comment(comment) => comment.span,
+        }
+    }
+
+    /// Gets the source line the item ends on, as block comments may span several lines.
+    fn end_line(&self) -> usize {
+        match self {
+            Item::Token(token) => token.span.line,
+            Item::Comment(comment) => comment.span.line + comment.text.matches('\n').count(),
+        }
+    }
+}
+
+/// Re-emits a program from its tokens and comments with a canonical layout.
+///
+/// Commands keep the lines they were written on, except that every loop which
+/// contains other loops or comments is split over its own lines with the body
+/// indented, runs of the same command are only broken when longer than a line,
+/// trailing comments are aligned and consecutive blank lines are collapsed.
+/// A loop at the very start of the program never runs and is the usual way of
+/// writing a comment, so it is kept exactly as written.
+#[derive(Debug)]
+pub struct Formatter<'a> {
+    options: FormatOptions,
+    /// The program being formatted
+    source: &'a str,
+    /// Tokens and comments in source order
+    items: Vec<Item<'a>>,
+    /// Lines emitted so far
+    lines: Vec<String>,
+    /// Commands on the line being built
+    current: String,
+    /// Indentation depth of the line being built
+    current_depth: usize,
+    /// Whether the line being built cannot take any more commands
+    current_closed: bool,
+    /// Source line of the last trailing comment, which an indented comment on the next line continues
+    trailing_comment_line: Option<usize>,
+    /// Number of loops enclosing the next command
+    depth: usize,
+}
+
+impl<'a> Formatter<'a> {
+    /// Formats the program read by a lexer.
+    ///
+    /// # Arguments
+    ///
+    /// * `lexer` - The lexer that tokenized the program.
+    /// * `source` - The program the lexer tokenized.
+    /// * `options` - Settings controlling the layout.
+    ///
+    /// # Returns
+    ///
+    /// The formatted program.
+    pub fn format(lexer: &'a Lexer, source: &'a str, options: FormatOptions) -> String {
+        let mut items: Vec<Item> = lexer
+            .tokens()
+            .iter()
+            .map(|token| Item::Token(*token))
+            .collect();
+        items.extend(lexer.comments().iter().map(Item::Comment));
+        items.sort_by_key(|item| item.span().byte_offset);
+
+        let mut formatter = Formatter {
+            options,
+            source,
+            items,
+            lines: vec![],
+            current: String::new(),
+            current_depth: 0,
+            current_closed: false,
+            trailing_comment_line: None,
+            depth: 0,
+        };
+        formatter.layout();
+
+        let mut formatted = formatter.lines.join("\n");
+        formatted.push('\n');
+        formatted
+    }
+
+    /// Lays out every item into lines.
+    fn layout(&mut self) {
+        let mut prev_line: Option<usize> = None;
+        let mut idx = 0;
+
+        if let Some(end) = self.comment_loop_end() {
+            let start = self.items[0].span();
+            let end_span = self.items[end].span();
+            let text = &self.source[start.byte_offset..end_span.byte_offset + end_span.len];
+            self.lines
+                .extend(text.lines().map(|line| line.trim_end().to_string()));
+            prev_line = Some(end_span.line);
+            idx = end + 1;
+        }
+
+        while idx < self.items.len() {
+            let item = self.items[idx];
+            let line = item.span().line;
+
+            // Keep the line breaks of the source, collapsing blank lines to one
+            if let Some(prev_line) = prev_line {
+                if line > prev_line {
+                    self.flush();
+                    if line > prev_line + 1 {
+                        self.lines.push(String::new());
+                    }
+                }
+            }
+            prev_line = Some(item.end_line());
+
+            match item {
+                Item::Comment(comment) => self.push_comment(comment),
+                Item::Token(token) => match token.token {
+                    Token::LoopStart => {
+                        if let Some(end) = self.inline_loop_end(idx) {
+                            let text: String = self.items[idx..=end]
+                                .iter()
+                                .filter_map(|item| match item {
+                                    Item::Token(token) => Some(token.token as u8 as char),
+                                    Item::Comment(_) => None,
+                                })
+                                .collect();
+                            self.push_commands(&text);
+                            prev_line = Some(self.items[end].span().line);
+                            idx = end;
+                        } else {
+                            self.flush();
+                            self.current.push('[');
+                            self.current_closed = true;
+                            self.depth += 1;
+                        }
+                    }
+                    Token::LoopEnd => {
+                        self.flush();
+                        self.depth = self.depth.saturating_sub(1);
+                        self.current_depth = self.depth;
+                        self.current.push(']');
+                        self.current_closed = true;
+                    }
+                    _ => {
+                        let run = self.run_length(idx);
+                        let text = (token.token as u8 as char).to_string().repeat(run);
+                        self.push_commands(&text);
+                        idx += run - 1;
+                    }
+                },
+            }
+            idx += 1;
+        }
+
+        self.flush();
+
+        // Drop blank lines at the start and end of the program
+        while self.lines.first().is_some_and(|line| line.is_empty()) {
+            self.lines.remove(0);
+        }
+        while self.lines.last().is_some_and(|line| line.is_empty()) {
+            self.lines.pop();
+        }
+    }
+
+    /// Adds commands to the current line, starting a new line when they do not fit.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The commands to add, either a run of one command or an inline loop.
+    fn push_commands(&mut self, text: &str) {
+        if self.current_closed || self.current.len() + text.len() > self.available_width() {
+            self.flush();
+        }
+
+        // A run longer than a whole line is split over as many lines as needed
+        let mut remaining = text;
+        while remaining.len() > self.available_width() - self.current.len() {
+            let (head, tail) = remaining.split_at(self.available_width() - self.current.len());
+            self.current.push_str(head);
+            self.flush();
+            remaining = tail;
+        }
+        self.current.push_str(remaining);
+    }
+
+    /// Adds a comment after the commands of the current line, or on its own line.
+    fn push_comment(&mut self, comment: &Comment) {
+        let indent = self.depth * self.options.indent;
+        let continues_trailing = self.trailing_comment_line == Some(comment.span.line - 1)
+            && comment.span.col > indent + 1;
+
+        if !self.current.is_empty() {
+            let used = self.current_depth * self.options.indent + self.current.len();
+            let padding = self.options.comment_column.saturating_sub(used).max(1);
+            self.current.push_str(&" ".repeat(padding));
+            self.trailing_comment_line = Some(comment.span.line);
+        } else if continues_trailing {
+            // Keep a comment spanning several lines aligned with where it started
+            self.current_depth = 0;
+            self.current
+                .push_str(&" ".repeat(self.options.comment_column.max(indent)));
+            self.trailing_comment_line = Some(comment.span.line);
+        } else {
+            self.current_depth = self.depth;
+            self.trailing_comment_line = None;
+        }
+        self.current.push_str(&comment.text);
+        self.flush();
+    }
+
+    /// Ends the current line.
+    fn flush(&mut self) {
+        if !self.current.is_empty() {
+            let indent = " ".repeat(self.current_depth * self.options.indent);
+            self.lines.push(format!("{}{}", indent, self.current));
+            self.current.clear();
+        }
+        self.current_depth = self.depth;
+        self.current_closed = false;
+    }
+
+    /// Gets the number of command characters that fit on a line at the current depth.
+    fn available_width(&self) -> usize {
+        self.options
+            .max_width
+            .saturating_sub(self.current_depth * self.options.indent)
+            .max(1)
+    }
+
+    /// Finds the end of a loop that opens the program, which is kept as written.
+    ///
+    /// # Returns
+    ///
+    /// The index of the `]` that closes the loop, or `None` if the program does not start with a loop.
+    fn comment_loop_end(&self) -> Option<usize> {
+        let Some(Item::Token(first)) = self.items.first() else {
+            return None;
+        };
+        if first.token != Token::LoopStart {
+            return None;
+        }
+
+        let mut depth = 0;
+        for (idx, item) in self.items.iter().enumerate() {
+            match item {
+                Item::Token(token) if token.token == Token::LoopStart => depth += 1,
+                Item::Token(token) if token.token == Token::LoopEnd => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(idx);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Counts the tokens of the same command starting at `idx`, on the same line.
+    fn run_length(&self, idx: usize) -> usize {
+        let Item::Token(first) = self.items[idx] else {
+            return 1;
+        };
+        self.items[idx..]
+            .iter()
+            .take_while(|item| match item {
+                Item::Token(token) => {
+                    token.token == first.token && token.span.line == first.span.line
+                }
+                Item::Comment(_) => false,
+            })
+            .count()
+    }
+
+    /// Finds the end of a loop that can be kept on a single line, which is one
+    /// without nested loops or comments that fits within the line width.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Index of the `[` that opens the loop.
+    ///
+    /// # Returns
+    ///
+    /// The index of the matching `]`, or `None` if the loop must be split.
+    fn inline_loop_end(&self, start: usize) -> Option<usize> {
+        for (idx, item) in self.items.iter().enumerate().skip(start + 1) {
+            match item {
+                Item::Token(token) if token.token == Token::LoopEnd => {
+                    let fits = idx - start
+                        < self
+                            .options
+                            .max_width
+                            .saturating_sub(self.depth * self.options.indent);
+                    return fits.then_some(idx);
+                }
+                Item::Token(token) if token.token == Token::LoopStart => return None,
+                Item::Comment(_) => return None,
+                Item::Token(_) => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{CommentStyle, LexerOptions};
+
+    /// Formats `program` with the default layout, reading comments in `style`.
+    fn format(program: &str, style: CommentStyle) -> String {
+        let options = LexerOptions {
+            comment_style: style,
+            ..LexerOptions::default()
+        };
+        let lexer = Lexer::try_new(program, options).unwrap();
+        Formatter::format(&lexer, program, FormatOptions::default())
+    }
+
+    const PROGRAMS: [(&str, CommentStyle); 4] = [
+        (
+            "+++ set up\n>++[<+>-] move\n\n\n.[[-]>]\n",
+            CommentStyle::Ignore,
+        ),
+        ("+++ ; set up\n>>[-]\n  # clear it\n", CommentStyle::Line),
+        (
+            "+++ // set up\n/* block\n comment */ >[<[-]>-]\n",
+            CommentStyle::CStyle,
+        ),
+        ("[ leading\n   comment ] +++\n[>+<-]", CommentStyle::Ignore),
+    ];
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for (program, style) in PROGRAMS {
+            let formatted = format(program, style);
+            assert_eq!(format(&formatted, style), formatted, "{:?}", program);
+        }
+    }
+
+    #[test]
+    fn keeps_comments_in_every_style() {
+        let cases = [
+            (
+                "+++ set up\n>++ move\n",
+                CommentStyle::Ignore,
+                ["set up", "move"],
+            ),
+            (
+                "+++ ; set up\n>++ # move\n",
+                CommentStyle::Line,
+                ["; set up", "# move"],
+            ),
+            (
+                "+++ // set up\n/* move */ >++\n",
+                CommentStyle::CStyle,
+                ["// set up", "/* move */"],
+            ),
+        ];
+        for (program, style, comments) in cases {
+            let formatted = format(program, style);
+            for comment in comments {
+                assert!(formatted.contains(comment), "{:?}", formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn aligns_trailing_comments() {
+        let formatted = format("+++ one\n>>>>>>>> two\n", CommentStyle::Ignore);
+        assert_eq!(
+            formatted,
+            format!("+++{}one\n>>>>>>>>{}two\n", " ".repeat(21), " ".repeat(16))
+        );
+    }
+
+    #[test]
+    fn keeps_leading_comment_loop_verbatim() {
+        let program = "[ this loop never runs,\n  so [it] is a comment + - ]\n+++\n";
+        assert_eq!(format(program, CommentStyle::Ignore), program);
+    }
+
+    #[test]
+    fn splits_loops_with_nested_loops() {
+        assert_eq!(
+            format(">[>[-]<-]", CommentStyle::Ignore),
+            ">\n[\n    >[-]<-\n]\n"
+        );
+    }
+
+    #[test]
+    fn collapses_blank_lines() {
+        assert_eq!(format("+\n\n\n\n-\n\n", CommentStyle::Ignore), "+\n\n-\n");
+    }
+}
@@ -8,7 +8,7 @@
 /// - `InputSymbols = E`
 /// - `Operators = Op`
 ///
-/// ```
+/// ```text
 /// E => {
 ///     ( > ),
 ///     ( < ),
@@ -20,15 +20,16 @@
 /// }
 /// ```
 ///
-/// - `Expr => { Loop, Op }`
+/// - `Expr => { Loop, Proc, Op }`
 /// - `Expr => e`
 /// - `Loop => { "[" Expr "]" }`
+/// - `Proc => { "(" Expr ")" }`, only with the `pbrain` extension
 /// - `Op   => { ">" | "<" | "+" | "-" | "." | "," }*`
 ///
 /// # Tokens
 /// Enum representing different token types.
 ///
-/// ```
+/// ```text
 /// #[derive(Debug, Copy, Clone)]
 /// pub enum Token {
 ///     MoveBack = '<' as isize,
@@ -39,31 +40,70 @@
 ///     StdIn = ',' as isize,
 ///     LoopStart = '[' as isize,
 ///     LoopEnd = ']' as isize,
+///     Debug = '#' as isize,
+///     Fork = 'Y' as isize,
+///     ProcStart = '(' as isize,
+///     ProcEnd = ')' as isize,
+///     Call = ':' as isize,
+///     End = '@' as isize,
+///     Store = '$' as isize,
+///     Retrieve = '!' as isize,
+///     ShiftRight = '}' as isize,
+///     ShiftLeft = '{' as isize,
+///     Not = '~' as isize,
+///     Xor = '^' as isize,
+///     And = '&' as isize,
+///     Or = '|' as isize,
 /// }
 /// ```
 ///
 /// # Expressions
 /// Enum representing different expression types.
 ///
-/// ```
+/// ```text
 /// #[derive(Debug)]
 /// pub enum Expression {
-///     Loop(Vec<Expression>),
+///     Loop(Loop),
+///     Procedure(Procedure),
 ///     Operator(Box<Operators>),
 /// }
 /// ```
 ///
+/// # Loop
+/// Struct representing a loop body and the span from `[` to `]`.
+///
+/// ```text
+/// #[derive(Debug)]
+/// pub struct Loop {
+///     pub body: Vec<Expression>,
+///     pub span: Span,
+/// }
+/// ```
+///
+/// # Procedure
+/// Struct representing a pbrain procedure body and the span from `(` to `)`.
+///
+/// ```text
+/// #[derive(Debug)]
+/// pub struct Procedure {
+///     pub body: Vec<Expression>,
+///     pub span: Span,
+/// }
+/// ```
+///
 /// # Operator
 /// Struct representing operators with type and count.
 ///
-/// ```
+/// ```text
 /// #[derive(Debug)]
 /// pub struct Operator {
 ///     pub _type_name: Token,
 ///     pub _count: u32,
+///     pub span: Span,
 /// }
 /// ```
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     MoveBack = '<' as isize,
     MoveForward = '>' as isize,
@@ -73,16 +113,197 @@ pub enum Token {
     StdIn = ',' as isize,
     LoopStart = '[' as isize,
     LoopEnd = ']' as isize,
+    /// Only produced when the lexer runs with `debug_symbol` enabled
+    Debug = '#' as isize,
+    /// Brainfork's fork, only produced when the lexer runs with the `fork` extension
+    Fork = 'Y' as isize,
+    /// pbrain's start of a procedure definition, only produced when the lexer runs
+    /// with the `pbrain` extension, like `ProcEnd` and `Call`
+    ProcStart = '(' as isize,
+    ProcEnd = ')' as isize,
+    Call = ':' as isize,
+    /// Extended Brainfuck Type I's end of the program, only produced when the lexer
+    /// runs with the `type1` extension, like the commands using the storage register
+    End = '@' as isize,
+    /// Copies the current cell into the storage register
+    Store = '$' as isize,
+    /// Copies the storage register into the current cell
+    Retrieve = '!' as isize,
+    ShiftRight = '}' as isize,
+    ShiftLeft = '{' as isize,
+    Not = '~' as isize,
+    /// Combines the current cell with the storage register, like `And` and `Or`
+    Xor = '^' as isize,
+    And = '&' as isize,
+    Or = '|' as isize,
+}
+
+impl Token {
+    /// Every token, in no particular order.
+    pub const ALL: [Token; 22] = [
+        Token::MoveBack,
+        Token::MoveForward,
+        Token::Add,
+        Token::Sub,
+        Token::StdOut,
+        Token::StdIn,
+        Token::LoopStart,
+        Token::LoopEnd,
+        Token::Debug,
+        Token::Fork,
+        Token::ProcStart,
+        Token::ProcEnd,
+        Token::Call,
+        Token::End,
+        Token::Store,
+        Token::Retrieve,
+        Token::ShiftRight,
+        Token::ShiftLeft,
+        Token::Not,
+        Token::Xor,
+        Token::And,
+        Token::Or,
+    ];
+
+    /// Checks whether the token is one of the eight commands of standard Brainfuck.
+    pub fn is_standard(self) -> bool {
+        matches!(
+            self,
+            Token::MoveBack
+                | Token::MoveForward
+                | Token::Add
+                | Token::Sub
+                | Token::StdOut
+                | Token::StdIn
+                | Token::LoopStart
+                | Token::LoopEnd
+        )
+    }
+
+    /// Checks whether a run of the token can be folded into a single operator with a count.
+    pub fn is_foldable(self) -> bool {
+        matches!(
+            self,
+            Token::MoveBack
+                | Token::MoveForward
+                | Token::Add
+                | Token::Sub
+                | Token::ShiftRight
+                | Token::ShiftLeft
+        )
+    }
+
+    /// Gets the token written as the character `byte`.
+    ///
+    /// # Returns
+    ///
+    /// The token, or `None` if `byte` is not a command.
+    pub fn from_byte(byte: u8) -> Option<Token> {
+        Self::ALL.into_iter().find(|&token| token as u8 == byte)
+    }
 }
 
-#[derive(Debug)]
+/// Location of a token or expression in the original source.
+///
+/// `line` and `col` are 1-based and point at the first character, `byte_offset`
+/// is the 0-based offset of that character and `len` is the number of bytes covered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub byte_offset: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// Creates a span covering both `self` and `other`.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The span to merge with.
+    ///
+    /// # Returns
+    ///
+    /// A span starting at the earlier of the two and ending at the later.
+    pub fn merge(self, other: Span) -> Span {
+        let (first, last) = if self.byte_offset <= other.byte_offset {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let end = (first.byte_offset + first.len).max(last.byte_offset + last.len);
+
+        Span {
+            len: end - first.byte_offset,
+            ..first
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A token together with the location it was read from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Text between tokens that is ignored when running the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment as written, including any delimiters such as `//`
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
-    Loop(Vec<Expression>),
+    Loop(Loop),
+    Procedure(Procedure),
     Operator(Box<Operator>),
 }
 
-#[derive(Debug)]
+impl Expression {
+    /// Gets the location of the expression in the source.
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Loop(_loop) => _loop.span,
+            Expression::Procedure(procedure) => procedure.span,
+            Expression::Operator(_op) => _op.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Loop {
+    pub body: Vec<Expression>,
+    /// Covers everything from the opening `[` to the matching `]`
+    pub span: Span,
+}
+
+/// A pbrain procedure, numbered by the value of the current cell when the
+/// definition runs and run by `:` when the current cell holds that number.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Procedure {
+    pub body: Vec<Expression>,
+    /// Covers everything from the opening `(` to the matching `)`
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Operator {
     pub type_name: Token,
     pub count: usize,
+    /// Covers every folded occurrence of the operator
+    pub span: Span,
 }
@@ -1,5 +1,70 @@
-use crate::grammar::{Expression, Operator, Token};
-use std::io::Read;
+use crate::binary::LoadError;
+use crate::error::{RunError, RunState};
+use crate::grammar::{Expression, Operator, Span, Token};
+use crate::limits::{Limit, Limits};
+use crate::profiler::Profiler;
+use crate::schedule::{Schedule, Scheduler};
+use crate::snapshot::{Frame, Snapshot};
+use crate::stats::ExecutionStats;
+use crate::trace::{TraceEvent, TraceOptions, Tracer};
+use crate::watch::{Access, WatchAction, WatchHit, Watchpoint};
+use colored::Colorize;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+/// Changes made by a single step, kept so the step can be undone.
+#[derive(Debug, Clone)]
+struct UndoEntry {
+    /// The program counter before the step
+    frames: Vec<Frame>,
+    /// The cell pointer before the step
+    cell_ptr: usize,
+    /// The storage register before the step
+    storage: u8,
+    /// Index and previous value of the only cell a step can write to
+    cell: (usize, u8),
+    /// The step count before the step
+    steps: u64,
+    /// The statistics before the step
+    stats: ExecutionStats,
+    /// The byte read from stdin by the step, if it read one
+    input: Option<u8>,
+    /// Whether the step wrote a byte
+    output: bool,
+    /// Length of the call stack before the step, which a call or return changes
+    call_depth: usize,
+    /// Number and previous definition of the procedure the step defined, if it defined one
+    procedure: Option<(u8, Option<Vec<Frame>>)>,
+    /// The call stack before the step, if the step was a `@` that cleared it
+    call_stack: Option<Vec<Return>>,
+}
+
+/// Where a procedure call returns to.
+#[derive(Debug, Clone)]
+struct Return {
+    /// The program counter of the caller, just past its `:`
+    frames: Vec<Frame>,
+    /// When each loop the caller is running was entered
+    loop_starts: Vec<Option<Instant>>,
+}
+
+/// A thread forked by `Y` that is waiting for its turn to run.
+#[derive(Debug, Clone)]
+struct ThreadState {
+    /// The thread's own copy of the tape
+    cell_memory: Vec<u8>,
+    /// Points to the index in the thread's tape to be used
+    cell_ptr: usize,
+    /// The thread's own storage register
+    storage: u8,
+    /// The thread's program counter
+    frames: Vec<Frame>,
+    /// When each loop the thread is running was entered
+    loop_starts: Vec<Option<Instant>>,
+    /// Where each procedure call the thread is running returns to
+    call_stack: Vec<Return>,
+}
 
 /// Struct representing an interpreter for the custom language.
 #[derive(Debug)]
@@ -10,11 +75,56 @@ pub struct Interpreter<'a> {
     cell_memory: Vec<u8>,
     /// Points to the index in the tape to be used
     cell_ptr: usize,
+    /// Extended Brainfuck Type I's storage register, written by `$` and read by `!`
+    /// and the bitwise operators
+    storage: u8,
+    /// Collects hit counts and loop timings when profiling is enabled
+    profiler: Option<Profiler>,
+    /// Resource limits enforced while running
+    limits: Limits,
+    /// Number of steps executed so far
+    steps: u64,
+    /// Metrics collected while running
+    stats: ExecutionStats,
+    /// When the current run started
+    started: Option<Instant>,
+    /// The program counter, with one frame for the top level and one for every loop
+    /// being run; empty when no run is in progress
+    frames: Vec<Frame>,
+    /// When each loop being run was entered, used by the profiler
+    loop_starts: Vec<Option<Instant>>,
+    /// Where each procedure call being run returns to, innermost last
+    call_stack: Vec<Return>,
+    /// Position of the definition of every procedure by its number, shared by all threads
+    procedures: HashMap<u8, Vec<Frame>>,
+    /// Bytes read from stdin that have not been consumed by `,` yet
+    pending_input: VecDeque<u8>,
+    /// Bytes written by `.` that have not been flushed to stdout yet
+    pending_output: Vec<u8>,
+    /// Changes made by the most recent steps, newest last
+    undo_log: VecDeque<UndoEntry>,
+    /// Maximum number of steps kept in the undo log, zero when recording is disabled
+    undo_capacity: usize,
+    /// Conditions on the tape checked after every step, in the order they were added
+    watchpoints: Vec<(Watchpoint, WatchAction)>,
+    /// Hits of watchpoints set to break that have not been taken yet
+    watch_hits: Vec<WatchHit>,
+    /// Logs the selected steps, or `None` when tracing is disabled
+    tracer: Option<Tracer>,
+    /// Threads forked by `Y` waiting for their turn, oldest first; the tape, pointer
+    /// and program counter above belong to the thread that is running
+    threads: VecDeque<ThreadState>,
+    /// Picks the thread that runs each step while there is more than one
+    scheduler: Scheduler,
 }
 
 impl<'a> Interpreter<'a> {
     /// Maximum size of memory tape
     const MAX_SIZE: usize = u8::MAX as usize + 1;
+    /// Number of cells shown on each side of the pointer by a debug dump
+    const DEBUG_WINDOW: usize = 8;
+    /// Number of steps between checks of the wall clock timeout
+    const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
 
     /// Constructs a new `Interpreter` instance.
     ///
@@ -29,38 +139,892 @@ impl<'a> Interpreter<'a> {
         Interpreter {
             cell_memory: vec![0; 10],
             cell_ptr: 0,
+            storage: 0,
             syntax_tree,
+            profiler: None,
+            limits: Limits::default(),
+            steps: 0,
+            stats: ExecutionStats::default(),
+            started: None,
+            frames: vec![],
+            loop_starts: vec![],
+            call_stack: vec![],
+            procedures: HashMap::new(),
+            pending_input: VecDeque::new(),
+            pending_output: vec![],
+            undo_log: VecDeque::new(),
+            undo_capacity: 0,
+            watchpoints: vec![],
+            watch_hits: vec![],
+            tracer: None,
+            threads: VecDeque::new(),
+            scheduler: Scheduler::new(Schedule::default()),
         }
     }
 
+    /// Sets the resource limits enforced by the next run.
+    ///
+    /// # Arguments
+    ///
+    /// * `limits` - The limits to enforce.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Sets how the thread that runs each step is picked once the program forks.
+    ///
+    /// # Arguments
+    ///
+    /// * `schedule` - The schedule to follow.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.scheduler = Scheduler::new(schedule);
+    }
+
+    /// Enables collecting a profile of the next run.
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Logs the steps selected by `options` as `log` records with the `trace::TARGET` target.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Which steps to trace and how to format them.
+    pub fn enable_trace(&mut self, options: TraceOptions) {
+        self.tracer = Some(Tracer::new(options));
+    }
+
+    /// Gets the profile collected while running the code.
+    ///
+    /// # Returns
+    ///
+    /// The profiler, or `None` if profiling was not enabled.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Gets the metrics collected while running the code.
+    ///
+    /// # Returns
+    ///
+    /// The statistics of the last run, including one stopped by an error.
+    pub fn stats(&self) -> &ExecutionStats {
+        &self.stats
+    }
+
     /// Runs the interpreted code.
-    pub fn run_code(&mut self) {
-        self.execute(self.syntax_tree, false);
+    ///
+    /// A run stopped by a limit keeps its program counter, so calling this again,
+    /// or restoring a snapshot taken at that point, continues where it stopped.
+    ///
+    /// # Returns
+    ///
+    /// An error if the program failed or exceeded one of its limits.
+    pub fn run_code(&mut self) -> Result<(), RunError> {
+        self.run(self.syntax_tree)
     }
 
-    /// Executes the instructions in the AST.
+    /// Runs a syntax tree other than the one the interpreter was created with, from
+    /// the expression at `start` and on the current tape and pointer.
+    ///
+    /// Procedures defined by earlier runs stay defined, as long as every call is given
+    /// a tree that starts with the expressions the earlier calls were given, so code
+    /// can be appended to the tree and run a piece at a time.
+    ///
+    /// The statistics cover everything run since the last `reset`, while the step limit
+    /// and the wall clock timeout restart with every call.
     ///
     /// # Arguments
     ///
     /// * `expressions` - The list of expressions to execute.
-    /// * `is_in_loop` - Indicates if the expression executing is within a loop.
-    fn execute(&mut self, expressions: &[Expression], is_in_loop: bool) {
-        loop {
-            for instruction in expressions {
-                match instruction {
-                    Expression::Loop(_loop) => {
-                        self.execute(_loop, true);
+    /// * `start` - Index of the first expression to execute.
+    ///
+    /// # Returns
+    ///
+    /// An error if the program failed or exceeded one of its limits.
+    pub fn run_expressions(
+        &mut self,
+        expressions: &[Expression],
+        start: usize,
+    ) -> Result<(), RunError> {
+        self.frames = vec![Frame {
+            index: start,
+            iterations: 0,
+        }];
+        self.loop_starts.clear();
+        self.call_stack.clear();
+        self.threads.clear();
+        self.steps = 0;
+        self.run(expressions)
+    }
+
+    /// Runs `root` from the current program counter, timing the run.
+    fn run(&mut self, root: &[Expression]) -> Result<(), RunError> {
+        log::info!(
+            "running from step {} at depth {}",
+            self.steps,
+            self.frames.len()
+        );
+        let wall_time = self.stats.wall_time;
+        self.started = Some(Instant::now());
+        let result = self.execute(root);
+        self.flush_output();
+        self.stats.wall_time = wall_time + self.elapsed();
+
+        match &result {
+            Ok(()) => log::info!(
+                "finished after {} steps in {:.2?}",
+                self.steps,
+                self.stats.wall_time
+            ),
+            Err(error) => log::info!("stopped after {} steps: {}", self.steps, error),
+        }
+        result
+    }
+
+    /// Captures the state of the run, so it can be restored later.
+    ///
+    /// Only the running thread is captured, see `thread_count`.
+    ///
+    /// # Returns
+    ///
+    /// The tape, pointer, program counter, pending I/O and statistics.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut procedures: Vec<(u8, Vec<Frame>)> = self
+            .procedures
+            .iter()
+            .map(|(&number, position)| (number, position.clone()))
+            .collect();
+        procedures.sort_by_key(|&(number, _)| number);
+
+        Snapshot {
+            fingerprint: Snapshot::fingerprint(self.syntax_tree),
+            cell_memory: self.cell_memory.clone(),
+            cell_ptr: self.cell_ptr,
+            storage: self.storage,
+            frames: self.frames.clone(),
+            procedures,
+            call_stack: self
+                .call_stack
+                .iter()
+                .map(|caller| caller.frames.clone())
+                .collect(),
+            steps: self.steps,
+            stats: self.stats,
+            pending_input: self.pending_input.iter().copied().collect(),
+            pending_output: self.pending_output.clone(),
+        }
+    }
+
+    /// Replaces the state of the run with a snapshot, so the next call to `run_code`
+    /// continues from the point the snapshot was taken.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - A snapshot taken while running the same syntax tree.
+    ///
+    /// # Returns
+    ///
+    /// An error if the snapshot was taken while running a different program, or its
+    /// program counter and call stack could not have been reached by running it.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), LoadError> {
+        self.validate(&snapshot)?;
+        log::debug!(
+            "restoring snapshot at step {} with {} cells",
+            snapshot.steps,
+            snapshot.cell_memory.len()
+        );
+
+        self.cell_memory = snapshot.cell_memory;
+        self.cell_ptr = snapshot.cell_ptr;
+        self.storage = snapshot.storage;
+        self.steps = snapshot.steps;
+        self.stats = snapshot.stats;
+        self.pending_input = snapshot.pending_input.into();
+        self.pending_output = snapshot.pending_output;
+        // Loops entered before the snapshot are timed from the point it is restored
+        let now = self.profiler.as_ref().map(|_| Instant::now());
+        self.loop_starts = vec![now; snapshot.frames.len().saturating_sub(1)];
+        self.frames = snapshot.frames;
+        self.call_stack = snapshot
+            .call_stack
+            .into_iter()
+            .map(|frames| Return {
+                loop_starts: vec![now; frames.len().saturating_sub(1)],
+                frames,
+            })
+            .collect();
+        self.procedures = snapshot.procedures.into_iter().collect();
+        self.threads.clear();
+        Ok(())
+    }
+
+    /// Checks every frame but the last points at a loop or a procedure and the last
+    /// at most one past the end of its body.
+    fn is_valid_position(root: &[Expression], frames: &[Frame]) -> bool {
+        let mut body = root;
+        for (depth, frame) in frames.iter().enumerate() {
+            if depth + 1 == frames.len() {
+                return frame.index <= body.len();
+            }
+            match body.get(frame.index) {
+                Some(Expression::Loop(_loop)) => body = &_loop.body,
+                Some(Expression::Procedure(procedure)) => body = &procedure.body,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Checks a snapshot could have been taken while running the syntax tree, so
+    /// continuing from it cannot fail in ways running the program never does.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The snapshot about to be restored.
+    ///
+    /// # Returns
+    ///
+    /// An error describing the first part of the snapshot that does not match.
+    fn validate(&self, snapshot: &Snapshot) -> Result<(), LoadError> {
+        let root = self.syntax_tree;
+        if snapshot.fingerprint != Snapshot::fingerprint(root) {
+            log::warn!(
+                "snapshot fingerprint {:016x} does not match the program",
+                snapshot.fingerprint
+            );
+            return Err(LoadError::ProgramMismatch);
+        }
+        if !Self::is_valid_position(root, &snapshot.frames) {
+            log::warn!("snapshot program counter is not a position in the program");
+            return Err(LoadError::InvalidState);
+        }
+        if snapshot.cell_ptr >= snapshot.cell_memory.len() {
+            log::warn!(
+                "snapshot pointer {} is past the end of its {} cells",
+                snapshot.cell_ptr,
+                snapshot.cell_memory.len()
+            );
+            return Err(LoadError::InvalidState);
+        }
+        if !snapshot
+            .call_stack
+            .iter()
+            .all(|frames| Self::is_valid_position(root, frames))
+        {
+            log::warn!("snapshot call stack returns to a position outside the program");
+            return Err(LoadError::InvalidState);
+        }
+        if !snapshot
+            .procedures
+            .iter()
+            .all(|(_, position)| Self::is_procedure_at(root, position))
+        {
+            log::warn!("snapshot defines a procedure that is not in the program");
+            return Err(LoadError::InvalidState);
+        }
+        // The caller of the first call runs the top level and every other frame list runs
+        // the body of the procedure called from the one before it
+        let calls = snapshot
+            .call_stack
+            .iter()
+            .chain(std::iter::once(&snapshot.frames));
+        if !calls
+            .enumerate()
+            .all(|(depth, frames)| Self::is_in_procedure(root, frames) == (depth > 0))
+        {
+            log::warn!(
+                "snapshot has {} procedure calls that do not match its program counter",
+                snapshot.call_stack.len()
+            );
+            return Err(LoadError::InvalidState);
+        }
+        Ok(())
+    }
+
+    /// Checks whether a valid program counter is running the body of a procedure, which
+    /// has to return to a caller once it finishes.
+    fn is_in_procedure(root: &[Expression], frames: &[Frame]) -> bool {
+        let bodies = Self::bodies(root, frames);
+        frames
+            .iter()
+            .zip(bodies)
+            .take(frames.len().saturating_sub(1))
+            .any(|(frame, body)| matches!(body[frame.index], Expression::Procedure(_)))
+    }
+
+    /// Checks the last of the frames points at a procedure definition.
+    fn is_procedure_at(root: &[Expression], frames: &[Frame]) -> bool {
+        let Some(last) = frames.last() else {
+            return false;
+        };
+        Self::is_valid_position(root, frames)
+            && matches!(
+                Self::bodies(root, frames)
+                    .last()
+                    .and_then(|body| body.get(last.index)),
+                Some(Expression::Procedure(_))
+            )
+    }
+
+    /// Clears the tape, moves the pointer back to the first cell and discards the
+    /// statistics collected so far.
+    pub fn reset(&mut self) {
+        self.cell_memory = vec![0; 10];
+        self.cell_ptr = 0;
+        self.storage = 0;
+        self.steps = 0;
+        self.stats = ExecutionStats::default();
+        self.frames.clear();
+        self.loop_starts.clear();
+        self.call_stack.clear();
+        self.procedures.clear();
+        self.threads.clear();
+        self.undo_log.clear();
+        self.watch_hits.clear();
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+    }
+
+    /// Gets the cells of the tape that have been allocated so far.
+    pub fn tape(&self) -> &[u8] {
+        &self.cell_memory
+    }
+
+    /// Gets the index of the cell under the pointer.
+    pub fn cell_ptr(&self) -> usize {
+        self.cell_ptr
+    }
+
+    /// Gets the number of threads that have not finished yet, including the one
+    /// running, which is only ever more than one for programs using `Y`.
+    pub fn thread_count(&self) -> usize {
+        self.threads.len() + 1
+    }
+
+    /// Records the changes made by each step from now on, so they can be undone with
+    /// `step_back`. Only the most recent `capacity` steps are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Maximum number of steps to keep, or zero to stop recording.
+    pub fn enable_undo_log(&mut self, capacity: usize) {
+        log::debug!("recording up to {} steps for undo", capacity);
+        self.undo_capacity = capacity;
+        while self.undo_log.len() > capacity {
+            self.undo_log.pop_front();
+        }
+    }
+
+    /// Adds a condition on the tape to check after every step.
+    ///
+    /// # Arguments
+    ///
+    /// * `watchpoint` - The condition to check.
+    /// * `action` - What to do when a step meets the condition.
+    ///
+    /// # Returns
+    ///
+    /// The index of the watchpoint.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint, action: WatchAction) -> usize {
+        self.watchpoints.push((watchpoint, action));
+        self.watchpoints.len() - 1
+    }
+
+    /// Removes a watchpoint, shifting the index of every watchpoint added after it.
+    ///
+    /// # Returns
+    ///
+    /// The removed watchpoint, or `None` if there is no watchpoint at `index`.
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<(Watchpoint, WatchAction)> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    /// Gets the watchpoints, in the order they were added.
+    pub fn watchpoints(&self) -> &[(Watchpoint, WatchAction)] {
+        &self.watchpoints
+    }
+
+    /// Takes the hits of watchpoints set to break since this was last called.
+    ///
+    /// Undoing a step with `step_back` also reports the watchpoints the step hit.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Gets the number of steps that can currently be undone.
+    pub fn undo_len(&self) -> usize {
+        self.undo_log.len()
+    }
+
+    /// Executes a single instruction of the interpreted code, or a single test of a
+    /// loop condition, recording it in the undo log if that is enabled.
+    ///
+    /// Steps taken while more than one thread is running are not recorded, and
+    /// clear the steps recorded before them.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the end of the program had already been reached or the step ended it
+    /// with `@`, or an error if the instruction failed.
+    pub fn step(&mut self) -> Result<bool, RunError> {
+        if self.frames.is_empty() {
+            self.frames.push(Frame::default());
+        }
+        self.started.get_or_insert_with(Instant::now);
+
+        let mut bodies = Self::bodies(self.syntax_tree, &self.frames);
+        let depth = bodies.len() - 1;
+        let next = bodies[depth].get(self.frames[depth].index);
+        let defines = matches!(next, Some(Expression::Procedure(_)));
+        let ends = matches!(next, Some(Expression::Operator(op)) if op.type_name == Token::End);
+        let before = (self.undo_capacity > 0 && self.threads.is_empty()).then(|| {
+            let cell = self.read_curr_cell();
+            UndoEntry {
+                frames: self.frames.clone(),
+                cell_ptr: self.cell_ptr,
+                storage: self.storage,
+                cell: (self.cell_ptr, cell),
+                steps: self.steps,
+                stats: self.stats,
+                input: None,
+                output: false,
+                call_depth: self.call_stack.len(),
+                procedure: defines.then(|| (cell, self.procedures.get(&cell).cloned())),
+                call_stack: ends.then(|| self.call_stack.clone()),
+            }
+        });
+
+        let more = self.advance(&mut bodies)?;
+
+        if !self.threads.is_empty() {
+            // Undoing a step cannot bring back the thread it switched away from
+            self.undo_log.clear();
+        } else if let (true, Some(mut entry)) = (more || ends, before) {
+            if self.stats.input_bytes != entry.stats.input_bytes {
+                entry.input = Some(self.read_curr_cell());
+            }
+            entry.output = self.stats.output_bytes != entry.stats.output_bytes;
+            if self.undo_log.len() == self.undo_capacity {
+                self.undo_log.pop_front();
+            }
+            self.undo_log.push_back(entry);
+        }
+        Ok(more)
+    }
+
+    /// Undoes the most recent step in the undo log.
+    ///
+    /// Output that has already been flushed to stdout stays there.
+    ///
+    /// # Returns
+    ///
+    /// `false` if there was no step left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.undo_log.pop_back() else {
+            return false;
+        };
+
+        let (index, value) = entry.cell;
+        let (new_cell_ptr, new_value) = (self.cell_ptr, self.cell_memory[index]);
+        self.cell_memory[index] = value;
+        self.cell_ptr = entry.cell_ptr;
+        self.storage = entry.storage;
+        self.steps = entry.steps;
+        self.stats = entry.stats;
+        if let Some(byte) = entry.input {
+            self.pending_input.push_front(byte);
+        }
+        if entry.output {
+            self.pending_output.pop();
+        }
+        if let Some(call_stack) = entry.call_stack {
+            // Undoing a `@`, which dropped every call being run
+            self.call_stack = call_stack;
+        } else if self.call_stack.len() > entry.call_depth {
+            // Undoing a call, which started from the program counter in the entry
+            self.call_stack.pop();
+        } else if self.call_stack.len() < entry.call_depth {
+            // Undoing a return, which left the program counter with the caller
+            self.call_stack.push(Return {
+                frames: std::mem::take(&mut self.frames),
+                loop_starts: std::mem::take(&mut self.loop_starts),
+            });
+        }
+        match entry.procedure {
+            Some((number, Some(position))) => {
+                self.procedures.insert(number, position);
+            }
+            Some((number, None)) => {
+                self.procedures.remove(&number);
+            }
+            None => {}
+        }
+        self.loop_starts = vec![None; entry.frames.len().saturating_sub(1)];
+        self.frames = entry.frames;
+
+        if let Some(expression) = self.current_expression() {
+            let mut access = Access::new(expression, index, value);
+            access.new_value = new_value;
+            access.new_cell_ptr = new_cell_ptr;
+            self.check_watchpoints(access, false);
+        }
+        true
+    }
+
+    /// Gets the location of the instruction the next step will execute, which is the
+    /// loop itself when its condition is about to be tested.
+    ///
+    /// # Returns
+    ///
+    /// The location, or `None` at the end of the program.
+    pub fn current_span(&self) -> Option<Span> {
+        self.current_expression().map(Expression::span)
+    }
+
+    /// Gets the operator the next step will execute.
+    ///
+    /// # Returns
+    ///
+    /// The operator's token, or `None` if the next step tests a loop condition or the
+    /// program has finished.
+    pub fn current_token(&self) -> Option<Token> {
+        match self.current_expression() {
+            Some(Expression::Operator(operator)) => Some(operator.type_name),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the end of the program has been reached.
+    pub fn is_finished(&self) -> bool {
+        self.current_expression().is_none()
+    }
+
+    /// Gets the expression at the program counter, which is the loop or procedure
+    /// itself when its body has just finished.
+    fn current_expression(&self) -> Option<&'a Expression> {
+        let root = self.syntax_tree;
+        if self.frames.is_empty() {
+            return root.first();
+        }
+
+        Self::expression_at(&Self::bodies(root, &self.frames), &self.frames)
+    }
+
+    /// Gets the expression the program counter points at, which is the loop or
+    /// procedure itself when its body has just finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `bodies` - The body each frame is running.
+    /// * `frames` - The program counter.
+    fn expression_at<'t>(bodies: &[&'t [Expression]], frames: &[Frame]) -> Option<&'t Expression> {
+        let depth = bodies.len() - 1;
+        match bodies[depth].get(frames[depth].index) {
+            Some(expression) => Some(expression),
+            None if depth == 0 => None,
+            None => Some(&bodies[depth - 1][frames[depth - 1].index]),
+        }
+    }
+
+    /// Finds the body each frame of the program counter is running.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The top level of the syntax tree being run.
+    /// * `frames` - The program counter.
+    fn bodies<'t>(root: &'t [Expression], frames: &[Frame]) -> Vec<&'t [Expression]> {
+        let mut bodies: Vec<&[Expression]> = vec![root];
+        for depth in 1..frames.len() {
+            match &bodies[depth - 1][frames[depth - 1].index] {
+                Expression::Loop(_loop) => bodies.push(&_loop.body),
+                Expression::Procedure(procedure) => bodies.push(&procedure.body),
+                Expression::Operator(_) => {
+                    unreachable!("a frame can only be entered by a loop or a call")
+                }
+            }
+        }
+        bodies
+    }
+
+    /// Executes the instructions in the AST from the current program counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The top level of the syntax tree being run.
+    fn execute(&mut self, root: &[Expression]) -> Result<(), RunError> {
+        if self.frames.is_empty() {
+            self.frames.push(Frame::default());
+        }
+
+        let mut bodies = Self::bodies(root, &self.frames);
+        while self.advance(&mut bodies)? {}
+
+        self.frames.clear();
+        Ok(())
+    }
+
+    /// Executes the next step of the running thread, then lets the scheduler pick
+    /// the thread that runs the step after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `bodies` - The body each frame is running.
+    ///
+    /// # Returns
+    ///
+    /// `false` once every thread has reached the end of the program.
+    fn advance(&mut self, bodies: &mut Vec<&[Expression]>) -> Result<bool, RunError> {
+        let more = self.execute_watched(bodies)?;
+        if self.threads.is_empty() {
+            return Ok(more);
+        }
+
+        // A thread that finished is dropped instead of waiting for another turn
+        if more {
+            let running = ThreadState {
+                cell_memory: std::mem::take(&mut self.cell_memory),
+                cell_ptr: self.cell_ptr,
+                storage: self.storage,
+                frames: std::mem::take(&mut self.frames),
+                loop_starts: std::mem::take(&mut self.loop_starts),
+                call_stack: std::mem::take(&mut self.call_stack),
+            };
+            self.threads.push_back(running);
+        }
+        let idx = self.scheduler.pick(self.threads.len());
+        let Some(next) = self.threads.remove(idx) else {
+            unreachable!("the scheduler picks one of the waiting threads");
+        };
+        self.cell_memory = next.cell_memory;
+        self.cell_ptr = next.cell_ptr;
+        self.storage = next.storage;
+        self.frames = next.frames;
+        self.loop_starts = next.loop_starts;
+        self.call_stack = next.call_stack;
+        *bodies = Self::bodies(bodies[0], &self.frames);
+        Ok(true)
+    }
+
+    /// Executes the next step, then checks the watchpoints and writes the trace.
+    ///
+    /// # Arguments
+    ///
+    /// * `bodies` - The body each frame is running.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the end of the program is reached.
+    fn execute_watched(&mut self, bodies: &mut Vec<&[Expression]>) -> Result<bool, RunError> {
+        if self.watchpoints.is_empty() && self.tracer.is_none() {
+            return self.execute_next(bodies);
+        }
+
+        let depth = bodies.len() - 1;
+        let token = match bodies[depth].get(self.frames[depth].index) {
+            Some(Expression::Operator(operator)) => operator.type_name,
+            Some(Expression::Loop(_)) => Token::LoopStart,
+            Some(Expression::Procedure(_)) => Token::ProcStart,
+            None => match Self::expression_at(bodies, &self.frames) {
+                Some(Expression::Procedure(_)) => Token::ProcEnd,
+                _ => Token::LoopEnd,
+            },
+        };
+        let access = Self::expression_at(bodies, &self.frames)
+            .map(|expression| Access::new(expression, self.cell_ptr, self.read_curr_cell()));
+
+        let more = self.execute_next(bodies)?;
+        if let Some(mut access) = access {
+            access.new_value = self.cell_memory[access.cell];
+            access.new_cell_ptr = self.cell_ptr;
+            self.check_watchpoints(access, true);
+
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(&TraceEvent {
+                    step: self.steps,
+                    token,
+                    access,
+                });
+            }
+        }
+        Ok(more)
+    }
+
+    /// Records or logs the watchpoints hit by a step.
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - What the step did.
+    /// * `log` - Flag indicating whether to print the hits of watchpoints set to log,
+    ///   which is skipped when a step is undone.
+    fn check_watchpoints(&mut self, access: Access, log: bool) {
+        let hits: Vec<(WatchHit, WatchAction)> = self
+            .watchpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, (watchpoint, _))| watchpoint.is_hit(&access))
+            .map(|(index, &(watchpoint, action))| {
+                let hit = WatchHit {
+                    index,
+                    watchpoint,
+                    access,
+                };
+                (hit, action)
+            })
+            .collect();
+
+        for (hit, action) in hits {
+            match action {
+                WatchAction::Break => self.watch_hits.push(hit),
+                WatchAction::Log if log => {
+                    self.flush_output();
+                    eprintln!("{} {}", "watch".yellow(), hit);
+                }
+                WatchAction::Log => {}
+            }
+        }
+    }
+
+    /// Executes the instruction the program counter points at, or tests the
+    /// condition of the loop whose body has just finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `bodies` - The body each frame is running.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the end of the program is reached.
+    fn execute_next(&mut self, bodies: &mut Vec<&[Expression]>) -> Result<bool, RunError> {
+        let depth = bodies.len() - 1;
+        match bodies[depth].get(self.frames[depth].index) {
+            Some(Expression::Operator(_op)) => {
+                self.count_step(_op.span)?;
+                self.stats.instructions += 1;
+                self.stats.commands += _op.count as u64;
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_operator(_op);
+                }
+                if _op.type_name == Token::Call {
+                    self.call(_op, bodies)?;
+                } else if _op.type_name == Token::End {
+                    self.end(bodies);
+                    return Ok(false);
+                } else {
+                    self.modify_curr_cell(_op)?;
+                    self.frames[depth].index += 1;
+                }
+            }
+            Some(Expression::Procedure(procedure)) => {
+                // Defining a procedure only records where it is, the body runs when called
+                self.count_step(procedure.span)?;
+                self.procedures
+                    .insert(self.read_curr_cell(), self.frames.clone());
+                self.frames[depth].index += 1;
+            }
+            Some(Expression::Loop(_loop)) => {
+                // Entering a loop, the body is skipped if the cell is already zero
+                self.count_step(_loop.span)?;
+                if self.read_curr_cell() == 0 {
+                    if let Some(profiler) = &mut self.profiler {
+                        profiler.record_loop(_loop, 0, Duration::ZERO);
+                    }
+                    self.frames[depth].index += 1;
+                } else {
+                    self.frames.push(Frame::default());
+                    self.loop_starts
+                        .push(self.profiler.as_ref().map(|_| Instant::now()));
+                    bodies.push(&_loop.body);
+                }
+            }
+            None if depth == 0 => return Ok(false),
+            None => {
+                let _loop = match &bodies[depth - 1][self.frames[depth - 1].index] {
+                    Expression::Loop(_loop) => _loop,
+                    Expression::Procedure(procedure) => {
+                        // The body has finished, so go back to the caller
+                        self.count_step(procedure.span)?;
+                        let Some(caller) = self.call_stack.pop() else {
+                            unreachable!("a procedure body only runs when it is called");
+                        };
+                        self.frames = caller.frames;
+                        self.loop_starts = caller.loop_starts;
+                        *bodies = Self::bodies(bodies[0], &self.frames);
+                        return Ok(true);
+                    }
+                    Expression::Operator(_) => {
+                        unreachable!("a frame can only be entered by a loop or a call")
                     }
-                    Expression::Operator(_op) => {
-                        self.modify_curr_cell(_op);
+                };
+                // The body has finished, so test the condition of its loop again
+                self.count_step(_loop.span)?;
+                self.frames[depth].iterations += 1;
+                self.stats.loop_iterations += 1;
+
+                if self.read_curr_cell() != 0 {
+                    self.frames[depth].index = 0;
+                } else {
+                    let frame = self.frames.pop().unwrap_or_default();
+                    let start = self.loop_starts.pop().flatten();
+                    bodies.pop();
+                    if let (Some(profiler), Some(start)) = (&mut self.profiler, start) {
+                        profiler.record_loop(_loop, frame.iterations, start.elapsed());
                     }
+                    self.frames[depth - 1].index += 1;
                 }
             }
+        }
+        Ok(true)
+    }
 
-            if !is_in_loop || self.read_curr_cell() == 0 {
-                break;
+    /// Counts a single step and checks the step and time limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Location of the instruction about to execute.
+    fn count_step(&mut self, span: Span) -> Result<(), RunError> {
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps >= max_steps {
+                return Err(self.limit_exceeded(Limit::Steps(max_steps), span));
+            }
+        }
+        if let Some(timeout) = self.limits.wall_timeout {
+            if self.steps.is_multiple_of(Self::TIMEOUT_CHECK_INTERVAL) && self.elapsed() >= timeout
+            {
+                return Err(self.limit_exceeded(Limit::WallTimeout(timeout), span));
             }
         }
+
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Builds the error reported when a limit is reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The limit that was reached.
+    /// * `span` - Location of the instruction that was about to execute.
+    fn limit_exceeded(&self, limit: Limit, span: Span) -> RunError {
+        RunError::LimitExceeded {
+            limit,
+            state: RunState {
+                span,
+                steps: self.steps,
+                cell_ptr: self.cell_ptr,
+                cell_value: self.cell_memory.get(self.cell_ptr).copied().unwrap_or(0),
+                output_bytes: self.stats.output_bytes,
+                elapsed: self.elapsed(),
+            },
+        }
+    }
+
+    /// Gets the wall clock time since the run started.
+    fn elapsed(&self) -> Duration {
+        self.started
+            .map(|start| start.elapsed())
+            .unwrap_or_default()
     }
 
     /// Modifies the memory tape based on the given operator.
@@ -68,13 +1032,24 @@ impl<'a> Interpreter<'a> {
     /// # Arguments
     ///
     /// * `operator` - The operator specifying the modification to perform.
-    fn modify_curr_cell(&mut self, operator: &Operator) {
+    fn modify_curr_cell(&mut self, operator: &Operator) -> Result<(), RunError> {
         match operator.type_name {
-            Token::MoveBack => {
-                self.cell_ptr -= operator.count;
-            }
+            Token::MoveBack => match self.cell_ptr.checked_sub(operator.count) {
+                Some(cell_ptr) => self.cell_ptr = cell_ptr,
+                None => {
+                    return Err(RunError::PointerUnderflow {
+                        span: operator.span,
+                    })
+                }
+            },
             Token::MoveForward => {
+                if let Some(max_cells) = self.limits.max_tape_cells {
+                    if self.cell_ptr + operator.count >= max_cells {
+                        return Err(self.limit_exceeded(Limit::TapeCells(max_cells), operator.span));
+                    }
+                }
                 self.cell_ptr += operator.count;
+                self.stats.pointer_max = self.stats.pointer_max.max(self.cell_ptr);
                 self.grow_cell_memory();
             }
             Token::Add => {
@@ -85,40 +1060,247 @@ impl<'a> Interpreter<'a> {
             }
             Token::Sub => {
                 // Val = (CURR_NUM + MAX_SIZE - COUNT) % MAX_SIZE
-                let a = Self::MAX_SIZE - operator.count;
+                let a = Self::MAX_SIZE - operator.count % Self::MAX_SIZE;
                 let b = self.read_curr_cell() as usize + a;
                 let n: usize = b % Self::MAX_SIZE;
                 self.write_to_cell(n as u8);
             }
             Token::StdOut => {
-                print!("{}", self.read_curr_cell() as char);
+                if let Some(max_bytes) = self.limits.max_output_bytes {
+                    if self.stats.output_bytes >= max_bytes {
+                        return Err(
+                            self.limit_exceeded(Limit::OutputBytes(max_bytes), operator.span)
+                        );
+                    }
+                }
+                let byte = self.read_curr_cell();
+                self.pending_output.push(byte);
+                self.stats.output_bytes += 1;
+                if byte == b'\n' {
+                    self.flush_output();
+                }
             }
             Token::StdIn => {
-                println!("Enter One Character");
                 self.read_char();
             }
+            Token::Debug => {
+                self.flush_output();
+                self.dump_tape(operator);
+            }
+            Token::Fork => self.fork(operator)?,
+            Token::Store => self.storage = self.read_curr_cell(),
+            Token::Retrieve => self.write_to_cell(self.storage),
+            Token::ShiftRight => {
+                let value = self.read_curr_cell();
+                self.write_to_cell(value.checked_shr(operator.count as u32).unwrap_or(0));
+            }
+            Token::ShiftLeft => {
+                let value = self.read_curr_cell();
+                self.write_to_cell(value.checked_shl(operator.count as u32).unwrap_or(0));
+            }
+            Token::Not => self.write_to_cell(!self.read_curr_cell()),
+            Token::Xor => self.write_to_cell(self.read_curr_cell() ^ self.storage),
+            Token::And => self.write_to_cell(self.read_curr_cell() & self.storage),
+            Token::Or => self.write_to_cell(self.read_curr_cell() | self.storage),
             _ => {
                 eprintln!("Token: {:?}, cannot modify the memory", operator);
             }
         }
+        Ok(())
+    }
+
+    /// Calls the procedure numbered by the current cell, which returns to the
+    /// instruction after the `:` once its body has finished.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - The call operator being executed.
+    /// * `bodies` - The body each frame is running.
+    fn call(
+        &mut self,
+        operator: &Operator,
+        bodies: &mut Vec<&[Expression]>,
+    ) -> Result<(), RunError> {
+        let number = self.read_curr_cell();
+        let Some(position) = self.procedures.get(&number) else {
+            return Err(RunError::UndefinedProcedure {
+                number,
+                span: operator.span,
+            });
+        };
+        if let Some(max_call_depth) = self.limits.max_call_depth {
+            if self.call_stack.len() >= max_call_depth {
+                return Err(self.limit_exceeded(Limit::CallDepth(max_call_depth), operator.span));
+            }
+        }
+
+        let mut frames = position.clone();
+        frames.push(Frame::default());
+        // The frames leading to the definition are not loops being run by the procedure
+        let loop_starts = vec![None; frames.len() - 1];
+        if let Some(frame) = self.frames.last_mut() {
+            frame.index += 1;
+        }
+        self.call_stack.push(Return {
+            frames: std::mem::replace(&mut self.frames, frames),
+            loop_starts: std::mem::replace(&mut self.loop_starts, loop_starts),
+        });
+        *bodies = Self::bodies(bodies[0], &self.frames);
+        Ok(())
+    }
+
+    /// Ends the program at a `@`, stopping every thread and any procedure being run
+    /// and leaving the program counter at the end of the top level.
+    ///
+    /// # Arguments
+    ///
+    /// * `bodies` - The body each frame is running.
+    fn end(&mut self, bodies: &mut Vec<&[Expression]>) {
+        self.threads.clear();
+        self.call_stack.clear();
+        self.loop_starts.clear();
+        bodies.truncate(1);
+        self.frames = vec![Frame {
+            index: bodies[0].len(),
+            iterations: 0,
+        }];
+    }
+
+    /// Forks a new thread with a copy of the tape, which continues after the `Y` with
+    /// the pointer one cell to the right and that cell set to one. The running thread
+    /// continues with its current cell set to zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - The fork operator being executed.
+    fn fork(&mut self, operator: &Operator) -> Result<(), RunError> {
+        if let Some(max_threads) = self.limits.max_threads {
+            if self.thread_count() >= max_threads {
+                return Err(self.limit_exceeded(Limit::Threads(max_threads), operator.span));
+            }
+        }
+        if let Some(max_cells) = self.limits.max_tape_cells {
+            if self.cell_ptr + 1 >= max_cells {
+                return Err(self.limit_exceeded(Limit::TapeCells(max_cells), operator.span));
+            }
+        }
+
+        let mut child = ThreadState {
+            cell_memory: self.cell_memory.clone(),
+            cell_ptr: self.cell_ptr + 1,
+            storage: self.storage,
+            frames: self.frames.clone(),
+            loop_starts: self.loop_starts.clone(),
+            call_stack: self.call_stack.clone(),
+        };
+        // The caller moves the running thread past the `Y` once this returns
+        if let Some(frame) = child.frames.last_mut() {
+            frame.index += 1;
+        }
+        if child.cell_ptr >= child.cell_memory.len() {
+            child.cell_memory.resize(self.grown_len(child.cell_ptr), 0);
+        }
+        child.cell_memory[child.cell_ptr] = 1;
+        self.stats.pointer_max = self.stats.pointer_max.max(child.cell_ptr);
+
+        self.threads.push_back(child);
+        self.write_to_cell(0);
+        log::debug!(
+            "forked at {}, {} threads running",
+            operator.span,
+            self.thread_count()
+        );
+        Ok(())
     }
 
     /// Reads a character from standard input and writes it to memory.
+    ///
+    /// Only the first `,` of a line prompts for it, see `read_input_line`.
     fn read_char(&mut self) {
-        let mut buffer = [0; 1];
-        match std::io::stdin().read(&mut buffer) {
-            Ok(_) => {
-                if let Some(character) = buffer[0].into() {
-                    println!("You entered: {}={}", character as char, character);
-                    self.write_to_cell(character);
-                } else {
-                    println!("Invalid character entered");
-                }
-            }
-            Err(error) => {
+        if self.pending_input.is_empty() {
+            self.flush_output();
+            println!("Enter One Character");
+
+            if let Err(error) = self.read_input_line() {
                 eprintln!("Error reading input: {}", error);
+                return;
             }
         }
+
+        match self.pending_input.pop_front() {
+            None => {
+                println!("Invalid character entered");
+            }
+            Some(character) => {
+                println!("You entered: {}={}", character as char, character);
+                self.write_to_cell(character);
+                self.stats.input_bytes += 1;
+            }
+        }
+    }
+
+    /// Reads the next line of stdin, including its newline, into the pending input.
+    ///
+    /// Input is buffered a line at a time rather than a byte at a time so the bytes
+    /// typed ahead of the `,` that consumes them are part of the interpreter's state,
+    /// and are saved in a snapshot instead of being lost when a run is checkpointed.
+    fn read_input_line(&mut self) -> std::io::Result<()> {
+        let mut line = vec![];
+        std::io::stdin().lock().read_until(b'\n', &mut line)?;
+        self.pending_input.extend(line);
+        Ok(())
+    }
+
+    /// Writes the output buffered since the last flush to stdout.
+    pub fn flush_output(&mut self) {
+        if self.pending_output.is_empty() {
+            return;
+        }
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(&self.pending_output);
+        let _ = stdout.flush();
+        self.pending_output.clear();
+    }
+
+    /// Prints the cell pointer and the cells surrounding it to stderr.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - The debug operator that requested the dump.
+    fn dump_tape(&self, operator: &Operator) {
+        eprintln!(
+            "{} {} | cell_ptr={}\n{}",
+            "#".yellow(),
+            format!("Span={}", operator.span).bold(),
+            self.cell_ptr,
+            self.render_tape()
+        );
+    }
+
+    /// Renders the cells surrounding the pointer, with a marker under the current cell.
+    ///
+    /// # Returns
+    ///
+    /// The cell indices, their values and the marker on three lines.
+    pub fn render_tape(&self) -> String {
+        let start = self.cell_ptr.saturating_sub(Self::DEBUG_WINDOW);
+        let end = (self.cell_ptr + Self::DEBUG_WINDOW + 1).min(self.cell_memory.len());
+
+        let mut cells = String::new();
+        let mut values = String::new();
+        let mut marker = String::new();
+        for idx in start..end {
+            cells.push_str(&format!("{:>4}", idx));
+            values.push_str(&format!("{:>4}", self.cell_memory[idx]));
+            marker.push_str(if idx == self.cell_ptr { "   ^" } else { "    " });
+        }
+
+        format!(
+            "  cell :{}\n  value:{}\n        {}",
+            cells,
+            values,
+            marker.trim_end()
+        )
     }
 
     /// Reads a value from the memory tape at the current pointer position.
@@ -142,9 +1324,480 @@ impl<'a> Interpreter<'a> {
     /// Grows the memory tape if the current pointer exceeds its size.
     fn grow_cell_memory(&mut self) {
         if self.cell_ptr >= self.cell_memory.len() {
-            for _ in 0..10 {
-                self.cell_memory.push(0);
+            // A folded move can jump several blocks of cells at once
+            self.cell_memory.resize(self.grown_len(self.cell_ptr), 0);
+            log::trace!("tape grew to {} cells", self.cell_memory.len());
+        }
+    }
+
+    /// Gets the length to grow a tape to so it holds `cell_ptr`, leaving room for the
+    /// next few cells without going over the tape limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell_ptr` - The cell the tape has to hold, which is within the tape limit.
+    fn grown_len(&self, cell_ptr: usize) -> usize {
+        let len = cell_ptr + 10;
+        match self.limits.max_tape_cells {
+            Some(max_cells) => len.min(max_cells),
+            None => len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Extensions, LexerOptions};
+    use crate::pipeline::Pipeline;
+
+    /// Parses `program` without optimizing it, with the given extensions enabled.
+    fn compile(program: &str, extensions: Extensions) -> Vec<Expression> {
+        let options = LexerOptions {
+            extensions,
+            ..LexerOptions::default()
+        };
+        Pipeline::compile(String::from(program), false, options).into_ast()
+    }
+
+    fn pbrain() -> Extensions {
+        Extensions {
+            pbrain: true,
+            ..Extensions::default()
+        }
+    }
+
+    #[test]
+    fn restore_accepts_snapshot_taken_inside_procedure() {
+        let ast = compile("(-):", pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        // Define, then call, which leaves the program counter in the body
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        let snapshot = interpreter.snapshot();
+        assert_eq!(snapshot.call_stack.len(), 1);
+
+        let mut restored = Interpreter::new(&ast);
+        restored.restore(snapshot).unwrap();
+        restored.run_code().unwrap();
+        assert_eq!(restored.tape()[0], 255);
+    }
+
+    #[test]
+    fn restore_rejects_procedure_body_without_caller() {
+        let ast = compile("(-):", pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        let mut snapshot = interpreter.snapshot();
+        snapshot.call_stack.clear();
+
+        let mut restored = Interpreter::new(&ast);
+        assert_eq!(restored.restore(snapshot), Err(LoadError::InvalidState));
+    }
+
+    #[test]
+    fn restore_rejects_caller_outside_procedure_body() {
+        let ast = compile("(-):", pbrain());
+        let mut snapshot = Interpreter::new(&ast).snapshot();
+        snapshot.frames = vec![Frame::default()];
+        snapshot.call_stack = vec![vec![Frame::default()]];
+
+        let mut restored = Interpreter::new(&ast);
+        assert_eq!(restored.restore(snapshot), Err(LoadError::InvalidState));
+    }
+
+    #[test]
+    fn step_back_undoes_end_inside_procedure() {
+        let extensions = Extensions {
+            pbrain: true,
+            type1: true,
+            ..Extensions::default()
+        };
+        let ast = compile("+(>+@):", extensions);
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.enable_undo_log(16);
+        for _ in 0..5 {
+            assert!(interpreter.step().unwrap());
+        }
+        let before_end = interpreter.snapshot();
+        assert_eq!(before_end.call_stack.len(), 1);
+
+        assert!(!interpreter.step().unwrap());
+        assert!(interpreter.is_finished());
+        assert_eq!(interpreter.undo_len(), 6);
+
+        assert!(interpreter.step_back());
+        assert_eq!(interpreter.snapshot(), before_end);
+        assert_eq!(interpreter.current_token(), Some(Token::End));
+
+        // Running on from the undone `@` ends the program the same way again
+        assert!(!interpreter.step().unwrap());
+        assert!(interpreter.is_finished());
+        assert!(interpreter.snapshot().call_stack.is_empty());
+        assert_eq!(&interpreter.tape()[..2], &[1, 1]);
+    }
+
+    /// Takes a snapshot of an interpreter that has not started, with the program
+    /// counter a first step would start from.
+    fn initial_state(interpreter: &Interpreter) -> Snapshot {
+        let mut snapshot = interpreter.snapshot();
+        snapshot.frames = vec![Frame::default()];
+        snapshot
+    }
+
+    #[test]
+    fn step_back_undoes_every_step() {
+        // Defines a procedure, calls it twice to read and echo a byte, then clears the cell
+        let ast = compile("++(>,.<)::[-]", pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.pending_input.extend(b"ab");
+        interpreter.enable_undo_log(usize::MAX);
+        let initial = initial_state(&interpreter);
+
+        let mut steps = 0;
+        while interpreter.step().unwrap() {
+            steps += 1;
+        }
+        assert_eq!(&interpreter.tape()[..2], &[0, b'b']);
+        assert_eq!(interpreter.pending_output, b"ab");
+        assert!(interpreter.pending_input.is_empty());
+        assert_eq!(interpreter.undo_len(), steps);
+
+        for _ in 0..steps {
+            assert!(interpreter.step_back());
+        }
+        assert!(!interpreter.step_back());
+        assert_eq!(interpreter.snapshot(), initial);
+    }
+
+    #[test]
+    fn undo_log_keeps_most_recent_steps() {
+        let ast = compile("+>++>+++", Extensions::default());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.enable_undo_log(3);
+        for _ in 0..2 {
+            interpreter.step().unwrap();
+        }
+        let after_two = interpreter.snapshot();
+        for _ in 0..3 {
+            interpreter.step().unwrap();
+        }
+        assert_eq!(interpreter.undo_len(), 3);
+
+        for _ in 0..3 {
+            assert!(interpreter.step_back());
+        }
+        assert!(!interpreter.step_back());
+        assert_eq!(interpreter.snapshot(), after_two);
+    }
+
+    #[test]
+    fn fork_clears_undo_log() {
+        let extensions = Extensions {
+            fork: true,
+            ..Extensions::default()
+        };
+        let ast = compile("+Y+", extensions);
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.enable_undo_log(16);
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.undo_len(), 1);
+
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.thread_count(), 2);
+        assert_eq!(interpreter.undo_len(), 0);
+        assert!(!interpreter.step_back());
+    }
+
+    fn fork() -> Extensions {
+        Extensions {
+            fork: true,
+            ..Extensions::default()
+        }
+    }
+
+    /// Steps through the program until every thread has finished.
+    ///
+    /// # Returns
+    ///
+    /// Everything the program wrote, which holds no newline so is never flushed.
+    fn run_to_end(interpreter: &mut Interpreter) -> String {
+        while interpreter.step().unwrap() {}
+        String::from_utf8(interpreter.pending_output.clone()).unwrap()
+    }
+
+    /// Adds 97 to the current cell, then prints it three times, so the parent
+    /// prints `aaa` and the child `bbb`.
+    const FORK_PROGRAM: &str = "Y>++++++++[<++++++++++++>-]<+...";
+
+    #[test]
+    fn fork_zeroes_parent_cell_and_starts_child_on_next_cell() {
+        let ast = compile("+Y", fork());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.step().unwrap();
+        interpreter.step().unwrap();
+        assert_eq!(interpreter.thread_count(), 2);
+
+        // The child, forked from a copy of the tape, runs first
+        assert_eq!(interpreter.cell_ptr(), 1);
+        assert_eq!(&interpreter.tape()[..2], &[1, 1]);
+        let parent = &interpreter.threads[0];
+        assert_eq!(parent.cell_ptr, 0);
+        assert_eq!(&parent.cell_memory[..2], &[0, 0]);
+    }
+
+    #[test]
+    fn round_robin_alternates_threads() {
+        let ast = compile(FORK_PROGRAM, fork());
+        let mut interpreter = Interpreter::new(&ast);
+        assert_eq!(run_to_end(&mut interpreter), "bababa");
+    }
+
+    #[test]
+    fn random_schedule_repeats_with_same_seed() {
+        let ast = compile(FORK_PROGRAM, fork());
+        let run = |seed| {
+            let mut interpreter = Interpreter::new(&ast);
+            interpreter.set_schedule(Schedule::Random(seed));
+            run_to_end(&mut interpreter)
+        };
+
+        assert_eq!(run(7), "bbbaaa");
+        assert_eq!(run(7), run(7));
+        assert_eq!(run(1), "aaabbb");
+    }
+
+    #[test]
+    fn fork_stops_at_thread_limit() {
+        let ast = compile("YY", fork());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.set_limits(Limits {
+            max_threads: Some(2),
+            ..Limits::default()
+        });
+
+        match interpreter.run_code() {
+            Err(RunError::LimitExceeded { limit, state }) => {
+                assert_eq!(limit, Limit::Threads(2));
+                assert_eq!(state.span.byte_offset, 1);
+            }
+            result => panic!("expected the thread limit, got {:?}", result),
+        }
+    }
+
+    /// Runs `program` with `limits` until it stops at one of them.
+    ///
+    /// # Returns
+    ///
+    /// The limit reached, the location it was reached at and the length of the tape.
+    fn run_until_limit(program: &str, limits: Limits) -> (Limit, Span, usize) {
+        let ast = compile(program, Extensions::default());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.set_limits(limits);
+        match interpreter.run_code() {
+            Err(RunError::LimitExceeded { limit, state }) => {
+                (limit, state.span, interpreter.tape().len())
             }
+            result => panic!("expected a limit to stop {:?}, got {:?}", program, result),
         }
     }
+
+    #[test]
+    fn step_limit_stops_before_next_step() {
+        let limits = Limits {
+            max_steps: Some(4),
+            ..Limits::default()
+        };
+        // `+` and entering the loop, then `-` and the test ending the loop, then `>`
+        let (limit, span, _) = run_until_limit("+[-]>+", limits);
+        assert_eq!(limit, Limit::Steps(4));
+        assert_eq!((span.line, span.col, span.byte_offset), (1, 5, 4));
+    }
+
+    #[test]
+    fn tape_limit_stops_move_and_caps_tape() {
+        let limits = Limits {
+            max_tape_cells: Some(12),
+            ..Limits::default()
+        };
+        let program = ">>>>>>>>>>>\n+>";
+        let (limit, span, tape_len) = run_until_limit(program, limits);
+        assert_eq!(limit, Limit::TapeCells(12));
+        assert_eq!((span.line, span.col), (2, 2));
+        assert!(tape_len <= 12, "tape grew to {} cells", tape_len);
+    }
+
+    #[test]
+    fn fork_does_not_grow_tape_past_limit() {
+        let ast = compile(">>>>>>>>>>Y", fork());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.set_limits(Limits {
+            max_tape_cells: Some(12),
+            ..Limits::default()
+        });
+        for _ in 0..11 {
+            interpreter.step().unwrap();
+        }
+        // The child runs first, on the cell after the parent's
+        assert_eq!(interpreter.cell_ptr(), 11);
+        assert_eq!(interpreter.tape().len(), 12);
+    }
+
+    #[test]
+    fn output_limit_stops_at_first_extra_byte() {
+        let limits = Limits {
+            max_output_bytes: Some(2),
+            ..Limits::default()
+        };
+        let (limit, span, _) = run_until_limit("+..+.", limits);
+        assert_eq!(limit, Limit::OutputBytes(2));
+        assert_eq!(span.byte_offset, 4);
+    }
+
+    #[test]
+    fn procedure_runs_every_time_it_is_called() {
+        let ast = compile("+(>+<)::>+", pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.run_code().unwrap();
+        assert_eq!(&interpreter.tape()[..2], &[1, 3]);
+        assert!(interpreter.snapshot().call_stack.is_empty());
+    }
+
+    #[test]
+    fn calling_undefined_procedure_is_an_error() {
+        let ast = compile("(-)+:", pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        assert_eq!(
+            interpreter.run_code(),
+            Err(RunError::UndefinedProcedure {
+                number: 1,
+                span: Span {
+                    line: 1,
+                    col: 5,
+                    byte_offset: 4,
+                    len: 1
+                }
+            })
+        );
+    }
+
+    /// Procedure 1 counts cell 1 down to zero by calling itself, adding one to cell 2
+    /// on every call.
+    const RECURSIVE_PROGRAM: &str = "+(>>+<-[<:>]<)>+++<:";
+
+    #[test]
+    fn procedure_can_call_itself() {
+        let ast = compile(RECURSIVE_PROGRAM, pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.run_code().unwrap();
+        assert_eq!(&interpreter.tape()[..3], &[1, 0, 3]);
+        assert_eq!(interpreter.cell_ptr(), 0);
+    }
+
+    #[test]
+    fn recursion_stops_at_call_depth_limit() {
+        let ast = compile(RECURSIVE_PROGRAM, pbrain());
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.set_limits(Limits {
+            max_call_depth: Some(2),
+            ..Limits::default()
+        });
+        match interpreter.run_code() {
+            Err(RunError::LimitExceeded { limit, state }) => {
+                assert_eq!(limit, Limit::CallDepth(2));
+                // The `:` inside the procedure, not the first call
+                assert_eq!(state.span.byte_offset, 9);
+            }
+            result => panic!("expected the call depth limit, got {:?}", result),
+        }
+    }
+
+    fn type1() -> Extensions {
+        Extensions {
+            type1: true,
+            ..Extensions::default()
+        }
+    }
+
+    /// Runs a program using the Type I extension.
+    ///
+    /// # Returns
+    ///
+    /// The first four cells of the tape and the storage register.
+    fn run_type1(program: &str, optimize: bool) -> ([u8; 4], u8) {
+        let options = LexerOptions {
+            extensions: type1(),
+            ..LexerOptions::default()
+        };
+        let ast = Pipeline::compile(String::from(program), optimize, options).into_ast();
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.run_code().unwrap();
+        let mut cells = [0; 4];
+        cells.copy_from_slice(&interpreter.tape()[..4]);
+        (cells, interpreter.storage)
+    }
+
+    #[test]
+    fn storage_keeps_value_across_cells() {
+        assert_eq!(run_type1("+++$>!>!", false), ([3, 3, 3, 0], 3));
+        // Storing again replaces the value, retrieving overwrites the cell
+        assert_eq!(run_type1("+++$>+$<!", false), ([1, 1, 0, 0], 1));
+    }
+
+    #[test]
+    fn bitwise_operators_combine_cell_with_storage() {
+        let six = "++++++$";
+        assert_eq!(
+            run_type1(&format!("{}>+++++++++++^", six), false).0[1],
+            11 ^ 6
+        );
+        assert_eq!(
+            run_type1(&format!("{}>+++++++++++&", six), false).0[1],
+            11 & 6
+        );
+        assert_eq!(
+            run_type1(&format!("{}>+++++++++++|", six), false).0[1],
+            11 | 6
+        );
+        assert_eq!(run_type1("+++~", false).0[0], !3);
+    }
+
+    #[test]
+    fn shifts_drop_bits_past_the_cell() {
+        assert_eq!(run_type1("+++}", false).0[0], 1);
+        assert_eq!(run_type1("+++{{", false).0[0], 12);
+        for optimize in [false, true] {
+            assert_eq!(run_type1("+++{{{{{{", optimize).0[0], 192);
+            assert_eq!(run_type1("-{{{{{{{{{", optimize).0[0], 0);
+            assert_eq!(run_type1("-}}}}}}}}}", optimize).0[0], 0);
+        }
+    }
+
+    #[test]
+    fn end_stops_program_immediately() {
+        assert_eq!(run_type1("+@+", false).0[0], 1);
+        // Ends from inside a loop without testing its condition again
+        assert_eq!(run_type1("+++[>+@<-]>+", false).0, [3, 1, 0, 0]);
+    }
+
+    #[test]
+    fn end_in_one_thread_stops_every_thread() {
+        let extensions = Extensions {
+            fork: true,
+            type1: true,
+            ..Extensions::default()
+        };
+        // The child enters the loop and ends the program before the parent, which
+        // skips the loop, reaches its `.`
+        let ast = compile("Y[@].", extensions);
+        let mut interpreter = Interpreter::new(&ast);
+        assert_eq!(run_to_end(&mut interpreter), "");
+        assert_eq!(interpreter.thread_count(), 1);
+        assert!(interpreter.is_finished());
+
+        let ast = compile("Y[-].", fork());
+        let mut interpreter = Interpreter::new(&ast);
+        assert_eq!(run_to_end(&mut interpreter), "\0\0");
+    }
 }
@@ -1,110 +1,658 @@
+use std::path::PathBuf;
+
 use colored::Colorize;
 
-use crate::grammar::Token;
+use crate::dialect::DialectTable;
+use crate::grammar::{Comment, Span, SpannedToken, Token};
+use crate::preprocessor::{MacroError, Preprocessor, SourceMap};
+
+pub use crate::dialect::Dialect;
+
+/// The ways in which comments can be written in a program.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum CommentStyle {
+    /// Every character that is not a command is ignored, as in standard Brainfuck
+    #[default]
+    Ignore,
+    /// The first character that is not a command or whitespace comments out the rest of the line
+    Line,
+    /// `//` comments out the rest of the line and `/* */` comments out a block,
+    /// any other character that is not a command is ignored
+    CStyle,
+}
+
+impl std::str::FromStr for CommentStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        match style {
+            "ignore" => Ok(CommentStyle::Ignore),
+            "line" => Ok(CommentStyle::Line),
+            "c" => Ok(CommentStyle::CStyle),
+            _ => Err(format!("Unknown comment style '{}'", style)),
+        }
+    }
+}
+
+/// Extensions to the language that add commands to it, all disabled by default.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Extensions {
+    /// Brainfork's `Y`, which forks a new thread of execution
+    pub fork: bool,
+    /// pbrain's `(` and `)`, which define a procedure, and `:`, which calls one
+    pub pbrain: bool,
+    /// Extended Brainfuck Type I's `@` end, the `$` and `!` storage register commands,
+    /// the `}` and `{` shifts and the `~`, `^`, `&` and `|` bitwise operators
+    pub type1: bool,
+}
+
+impl Extensions {
+    /// Enables an extension by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the extension, `fork`, `pbrain` or `type1`.
+    ///
+    /// # Returns
+    ///
+    /// An error if there is no extension called `name`.
+    pub fn enable(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "fork" => self.fork = true,
+            "pbrain" => self.pbrain = true,
+            "type1" => self.type1 = true,
+            _ => return Err(format!("Unknown extension '{}'", name)),
+        }
+        Ok(())
+    }
+}
+
+/// Options controlling how the program text is tokenized.
+#[derive(Debug, Clone, Default)]
+pub struct LexerOptions {
+    /// The language the program is written in
+    pub dialect: Dialect,
+    /// Treat `#` as a `Token::Debug` instruction instead of a comment
+    pub debug_symbol: bool,
+    /// How comments are recognised
+    pub comment_style: CommentStyle,
+    /// Commands added to the language
+    pub extensions: Extensions,
+    /// Expand the macros, repetitions and includes of the program before tokenizing it,
+    /// which reads `{` and `}` as a repetition even with the `type1` extension
+    pub macros: bool,
+    /// Directory the `#include` paths of the program are relative to
+    pub include_dir: PathBuf,
+}
+
+/// Errors found while tokenizing a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// A `]` was found with no `[` left to match it, or inside a procedure opened after it
+    UnmatchedLoopEnd { span: Span },
+    /// The program ended with `count` loops still open
+    UnclosedLoops { count: usize },
+    /// A `)` was found with no `(` left to match it, or inside a loop opened after it
+    UnmatchedProcedureEnd { span: Span },
+    /// The program ended with `count` procedure definitions still open
+    UnclosedProcedures { count: usize },
+    /// The program ended inside the `/* */` comment starting at `span`
+    UnclosedBlockComment { span: Span },
+    /// The Ook! word at `span` is the last one and has no second word to pair with
+    UnpairedOokWord { span: Span },
+    /// The macros of the program could not be expanded
+    Macro(MacroError),
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexError::UnmatchedLoopEnd { .. } => write!(f, "Not enough matches for ']'"),
+            LexError::UnclosedLoops { count } => {
+                write!(f, "An Excess of {} '[' brackets were found", count)
+            }
+            LexError::UnmatchedProcedureEnd { .. } => write!(f, "Not enough matches for ')'"),
+            LexError::UnclosedProcedures { count } => {
+                write!(f, "An Excess of {} '(' brackets were found", count)
+            }
+            LexError::UnclosedBlockComment { .. } => write!(f, "Block comment is never closed"),
+            LexError::UnpairedOokWord { .. } => {
+                write!(f, "Ook! word has no second word to pair with")
+            }
+            LexError::Macro(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
 
 /// Struct representing a lexer for a custom language.
 #[derive(Debug)]
 pub struct Lexer {
-    // Vector to store tokens
-    tokens_: Vec<Token>,
+    // Vector to store tokens along with their location
+    tokens_: Vec<SpannedToken>,
+    // Vector to store the comments found between tokens
+    comments_: Vec<Comment>,
     // // Line number in the program
     line_num_: usize,
     // // Character index in the current line
     line_idx_: usize,
-    // Stack to track '[' and ']' pairs
-    brace_stack_: i32,
-    // Pointer simulation value
-    ptr_sim_: i32,
+    // Stack to track '[' and ']' pairs, and '(' and ')' pairs
+    brace_stack_: Vec<Token>,
+    // Options the lexer was created with
+    options_: LexerOptions,
 }
 
 impl Lexer {
     /// Constructor to create a new Lexer instance.
     ///
+    /// Panics with a formatted message pointing at the error if the program cannot be tokenized.
+    ///
     /// # Arguments
     ///
     /// * `program` - The program string to be tokenized.
+    /// * `options` - Options controlling which characters are treated as tokens.
     ///
     /// # Returns
     ///
     /// A new instance of `Lexer`.
-    pub fn new(program: String) -> Self {
-        let mut lex = Lexer {
+    pub fn new(program: String, options: LexerOptions) -> Self {
+        let mut lex = Self::empty(options);
+
+        // Tokenize the provided program
+        if let Err(error) = lex.tokenize(&program) {
+            lex.report(&program, error);
+        }
+        lex
+    }
+
+    /// Constructor to create a new Lexer instance, returning an error instead of
+    /// panicking if the program cannot be tokenized.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be tokenized.
+    /// * `options` - Options controlling which characters are treated as tokens.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Lexer`, or the first error found.
+    pub fn try_new(program: &str, options: LexerOptions) -> Result<Self, LexError> {
+        let mut lex = Self::empty(options);
+        lex.tokenize(program)?;
+        Ok(lex)
+    }
+
+    /// Creates a lexer that has not tokenized anything yet.
+    fn empty(options: LexerOptions) -> Self {
+        Lexer {
             // lexer_index_: 0,
             tokens_: vec![],
+            comments_: vec![],
             line_num_: 1,
             line_idx_: 0,
-            brace_stack_: 0,
-            ptr_sim_: 0,
-        };
+            brace_stack_: vec![],
+            options_: options,
+        }
+    }
 
-        // Tokenize the provided program
-        lex.tokenize(&program);
-        lex
+    /// Tokenization function, which expands the macros of the program first if enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be tokenized.
+    fn tokenize(&mut self, program: &str) -> Result<(), LexError> {
+        if !self.options_.macros {
+            return self.tokenize_expanded(program);
+        }
+
+        let expansion = Preprocessor::new(&self.options_.include_dir)
+            .expand(program)
+            .map_err(LexError::Macro)?;
+        let result = self.tokenize_expanded(&expansion.text);
+
+        // Locations found in the expanded program are moved back to where they came from
+        let source_map = &expansion.source_map;
+        for token in &mut self.tokens_ {
+            token.span = source_map.lookup(token.span);
+        }
+        for comment in &mut self.comments_ {
+            comment.span = source_map.lookup(comment.span);
+        }
+        result.map_err(|error| Self::map_error(error, source_map))
     }
 
-    /// Tokenization function.
+    /// Moves the location of an error found in an expanded program back to where it came from.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error found in the expanded program.
+    /// * `source_map` - Where each part of the expanded program came from.
+    fn map_error(error: LexError, source_map: &SourceMap) -> LexError {
+        match error {
+            LexError::UnmatchedLoopEnd { span } => LexError::UnmatchedLoopEnd {
+                span: source_map.lookup(span),
+            },
+            LexError::UnmatchedProcedureEnd { span } => LexError::UnmatchedProcedureEnd {
+                span: source_map.lookup(span),
+            },
+            LexError::UnclosedBlockComment { span } => LexError::UnclosedBlockComment {
+                span: source_map.lookup(span),
+            },
+            LexError::UnpairedOokWord { span } => LexError::UnpairedOokWord {
+                span: source_map.lookup(span),
+            },
+            _ => error,
+        }
+    }
+
+    /// Tokenizes a program whose macros have been expanded.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be tokenized.
+    fn tokenize_expanded(&mut self, program: &str) -> Result<(), LexError> {
+        log::debug!(
+            "tokenizing {} bytes of {} with {:?} comments",
+            program.len(),
+            self.options_.dialect.name(),
+            self.options_.comment_style
+        );
+        match self.options_.dialect.clone() {
+            Dialect::Brainfuck => self.tokenize_brainfuck(program)?,
+            Dialect::Ook => self.tokenize_ook(program)?,
+            Dialect::Table(table) => self.tokenize_table(program, &table)?,
+        }
+
+        // Check for unbalanced '[' and '(' brackets
+        let open_loops = self
+            .brace_stack_
+            .iter()
+            .filter(|&&token| token == Token::LoopStart)
+            .count();
+        if open_loops > 0 {
+            return Err(LexError::UnclosedLoops { count: open_loops });
+        }
+        if !self.brace_stack_.is_empty() {
+            return Err(LexError::UnclosedProcedures {
+                count: self.brace_stack_.len(),
+            });
+        }
+
+        log::debug!(
+            "found {} tokens and {} comments on {} lines",
+            self.tokens_.len(),
+            self.comments_.len(),
+            self.line_num_
+        );
+        Ok(())
+    }
+
+    /// Tokenizes a program written with one character per command.
     ///
     /// # Arguments
     ///
     /// * `program` - The program string to be tokenized.
-    fn tokenize(&mut self, program: &String) {
+    fn tokenize_brainfuck(&mut self, program: &str) -> Result<(), LexError> {
+        // Byte offset of the start of the current line
+        let mut line_offset = 0;
+        // Location of the '/*' that opened the block comment being skipped
+        let mut block_comment: Option<Span> = None;
+        // Start and end of the run of ignored characters being collected as a comment
+        let mut ignored: Option<(Span, usize)> = None;
+
         // Iterate through characters in the program
-        for (line_num, line) in program.lines().enumerate() {
+        for (line_num, line) in program.split_inclusive('\n').enumerate() {
             self.line_num_ = line_num + 1;
-            for (char_index, curr_char) in line.chars().enumerate() {
+            let mut chars = line.char_indices().enumerate().peekable();
+            while let Some((char_index, (byte_idx, curr_char))) = chars.next() {
                 self.line_idx_ = char_index;
-                match curr_char {
-                    '>' => {
-                        self.ptr_sim_ += 1;
-                        self.tokens_.push(Token::MoveForward);
-                    }
-                    '<' => {
-                        self.ptr_sim_ -= 1;
-                        if self.ptr_sim_ < 0 {
-                            self.throw_run_err(line, char_index, "Index runs out of bounds");
-                        }
-                        self.tokens_.push(Token::MoveBack);
-                    }
-                    '+' => self.tokens_.push(Token::Add),
-                    '-' => self.tokens_.push(Token::Sub),
-                    '.' => self.tokens_.push(Token::StdOut),
-                    ',' => self.tokens_.push(Token::StdIn),
-                    '[' => {
-                        self.brace_stack_ += 1;
-                        self.tokens_.push(Token::LoopStart);
-                    }
-                    ']' => {
-                        self.brace_stack_ -= 1;
-                        if self.brace_stack_ < 0 {
-                            self.throw_run_err(line, char_index, "Not enough matches for ']'");
-                        }
-                        self.tokens_.push(Token::LoopEnd);
+                let next_char = chars.peek().map(|&(_, (_, next_char))| next_char);
+
+                // Skip everything until the end of the block comment
+                if let Some(start) = block_comment {
+                    if curr_char == '*' && next_char == Some('/') {
+                        chars.next();
+                        block_comment = None;
+                        self.push_comment(program, start, line_offset + byte_idx + 2);
                     }
-                    _ => {
-                        if curr_char.is_whitespace() {
+                    continue;
+                }
+
+                let span = Span {
+                    line: self.line_num_,
+                    col: char_index + 1,
+                    byte_offset: line_offset + byte_idx,
+                    len: curr_char.len_utf8(),
+                };
+                let end_of_line = line_offset + line.len();
+                let token = match curr_char {
+                    '>' => Token::MoveForward,
+                    '<' => Token::MoveBack,
+                    '+' => Token::Add,
+                    '-' => Token::Sub,
+                    '.' => Token::StdOut,
+                    ',' => Token::StdIn,
+                    '[' => Token::LoopStart,
+                    ']' => Token::LoopEnd,
+                    '#' if self.options_.debug_symbol => Token::Debug,
+                    'Y' if self.options_.extensions.fork => Token::Fork,
+                    '(' if self.options_.extensions.pbrain => Token::ProcStart,
+                    ')' if self.options_.extensions.pbrain => Token::ProcEnd,
+                    ':' if self.options_.extensions.pbrain => Token::Call,
+                    '@' if self.options_.extensions.type1 => Token::End,
+                    '$' if self.options_.extensions.type1 => Token::Store,
+                    '!' if self.options_.extensions.type1 => Token::Retrieve,
+                    '}' if self.options_.extensions.type1 => Token::ShiftRight,
+                    '{' if self.options_.extensions.type1 => Token::ShiftLeft,
+                    '~' if self.options_.extensions.type1 => Token::Not,
+                    '^' if self.options_.extensions.type1 => Token::Xor,
+                    '&' if self.options_.extensions.type1 => Token::And,
+                    '|' if self.options_.extensions.type1 => Token::Or,
+                    _ => match self.options_.comment_style {
+                        CommentStyle::Ignore => {
+                            Self::extend_ignored(&mut ignored, span, curr_char);
                             continue;
                         }
+                        CommentStyle::Line => {
+                            if curr_char.is_whitespace() {
+                                continue;
+                            }
 
-                        /*
-                           If comments are seen then stop reading
-                           the current line and move to the next
-                        */
-                        break;
+                            /*
+                               If comments are seen then stop reading
+                               the current line and move to the next
+                            */
+                            self.push_comment(program, span, end_of_line);
+                            break;
+                        }
+                        CommentStyle::CStyle => match (curr_char, next_char) {
+                            ('/', Some('/')) => {
+                                self.flush_ignored(program, &mut ignored);
+                                self.push_comment(program, span, end_of_line);
+                                break;
+                            }
+                            ('/', Some('*')) => {
+                                chars.next();
+                                self.flush_ignored(program, &mut ignored);
+                                block_comment = Some(span);
+                                continue;
+                            }
+                            _ => {
+                                Self::extend_ignored(&mut ignored, span, curr_char);
+                                continue;
+                            }
+                        },
+                    },
+                };
+                self.flush_ignored(program, &mut ignored);
+                self.push_token(token, span)?;
+            }
+            self.flush_ignored(program, &mut ignored);
+            line_offset += line.len();
+        }
+
+        // Check for a block comment that is never closed
+        if let Some(span) = block_comment {
+            return Err(LexError::UnclosedBlockComment { span });
+        }
+        Ok(())
+    }
+
+    /// Tokenizes an Ook! program, where every command is a pair of the words `Ook.`,
+    /// `Ook?` and `Ook!` and any other text is a comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be tokenized.
+    fn tokenize_ook(&mut self, program: &str) -> Result<(), LexError> {
+        // Byte offset of the start of the current line
+        let mut line_offset = 0;
+        // The first word of the pair being read, with its location
+        let mut first: Option<(Span, char)> = None;
+        // Start and end of the run of ignored characters being collected as a comment
+        let mut ignored: Option<(Span, usize)> = None;
+
+        for (line_num, line) in program.split_inclusive('\n').enumerate() {
+            self.line_num_ = line_num + 1;
+            let mut chars = line.char_indices().enumerate();
+            while let Some((char_index, (byte_idx, curr_char))) = chars.next() {
+                self.line_idx_ = char_index;
+                let span = Span {
+                    line: self.line_num_,
+                    col: char_index + 1,
+                    byte_offset: line_offset + byte_idx,
+                    len: curr_char.len_utf8(),
+                };
+
+                let rest = &line[byte_idx..];
+                let punctuation = match rest
+                    .strip_prefix("Ook")
+                    .and_then(|rest| rest.chars().next())
+                {
+                    Some(punctuation @ ('.' | '?' | '!')) => punctuation,
+                    _ => {
+                        Self::extend_ignored(&mut ignored, span, curr_char);
+                        continue;
                     }
+                };
+                // Skip the rest of the word
+                chars.nth(2);
+                self.flush_ignored(program, &mut ignored);
+
+                let Some((start, first_punctuation)) = first.take() else {
+                    first = Some((span, punctuation));
+                    continue;
+                };
+                let span = Span {
+                    len: span.byte_offset + 4 - start.byte_offset,
+                    ..start
+                };
+                let token = match (first_punctuation, punctuation) {
+                    ('.', '?') => Token::MoveForward,
+                    ('?', '.') => Token::MoveBack,
+                    ('.', '.') => Token::Add,
+                    ('!', '!') => Token::Sub,
+                    ('!', '.') => Token::StdOut,
+                    ('.', '!') => Token::StdIn,
+                    ('!', '?') => Token::LoopStart,
+                    ('?', '!') => Token::LoopEnd,
+                    // `Ook? Ook?` is not a command
+                    _ => continue,
+                };
+                self.push_token(token, span)?;
+            }
+            self.flush_ignored(program, &mut ignored);
+            line_offset += line.len();
+        }
+
+        if let Some((span, _)) = first {
+            return Err(LexError::UnpairedOokWord { span });
+        }
+        Ok(())
+    }
+
+    /// Tokenizes a program written in a dialect defined by a table of spellings, taking
+    /// the longest spelling found at each position and treating any other text as a
+    /// comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be tokenized.
+    /// * `table` - The spelling of every command.
+    fn tokenize_table(&mut self, program: &str, table: &DialectTable) -> Result<(), LexError> {
+        // Start and end of the run of ignored characters being collected as a comment
+        let mut ignored: Option<(Span, usize)> = None;
+        let mut byte_offset = 0;
+        self.line_idx_ = 0;
+
+        while let Some(curr_char) = program[byte_offset..].chars().next() {
+            let span = Span {
+                line: self.line_num_,
+                col: self.line_idx_ + 1,
+                byte_offset,
+                len: curr_char.len_utf8(),
+            };
+
+            let len = match table.longest_match(&program[byte_offset..]) {
+                Some((token, len)) if !self.is_enabled(token) => {
+                    Self::extend_ignored(&mut ignored, Span { len, ..span }, curr_char);
+                    len
+                }
+                Some((token, len)) => {
+                    self.flush_ignored(program, &mut ignored);
+                    self.push_token(token, Span { len, ..span })?;
+                    len
+                }
+                None => {
+                    Self::extend_ignored(&mut ignored, span, curr_char);
+                    span.len
+                }
+            };
+
+            // Spellings can cover several lines, so follow every character they contain
+            for matched in program[byte_offset..byte_offset + len].chars() {
+                if matched == Self::new_line() {
+                    self.flush_ignored(program, &mut ignored);
+                    self.line_num_ += 1;
+                    self.line_idx_ = 0;
+                } else {
+                    self.line_idx_ += 1;
+                }
+            }
+            byte_offset += len;
+        }
+        self.flush_ignored(program, &mut ignored);
+        Ok(())
+    }
+
+    /// Checks whether the options allow a command that is not part of standard Brainfuck.
+    fn is_enabled(&self, token: Token) -> bool {
+        match token {
+            Token::Debug => self.options_.debug_symbol,
+            Token::Fork => self.options_.extensions.fork,
+            Token::ProcStart | Token::ProcEnd | Token::Call => self.options_.extensions.pbrain,
+            Token::End
+            | Token::Store
+            | Token::Retrieve
+            | Token::ShiftRight
+            | Token::ShiftLeft
+            | Token::Not
+            | Token::Xor
+            | Token::And
+            | Token::Or => self.options_.extensions.type1,
+            _ => true,
+        }
+    }
+
+    /// Adds a token, keeping track of the loops and procedure definitions that are open.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The command found.
+    /// * `span` - Location of the command.
+    fn push_token(&mut self, token: Token, span: Span) -> Result<(), LexError> {
+        match token {
+            Token::LoopStart | Token::ProcStart => self.brace_stack_.push(token),
+            Token::LoopEnd | Token::ProcEnd => {
+                let (start, error) = match token {
+                    Token::LoopEnd => (Token::LoopStart, LexError::UnmatchedLoopEnd { span }),
+                    _ => (Token::ProcStart, LexError::UnmatchedProcedureEnd { span }),
+                };
+                if self.brace_stack_.pop() != Some(start) {
+                    return Err(error);
                 }
             }
+            _ => {}
+        }
+        self.tokens_.push(SpannedToken { token, span });
+        Ok(())
+    }
+
+    /// Prints a tokenizing error with the part of the program it was found in.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string that was tokenized.
+    /// * `error` - The error to report.
+    fn report(&mut self, program: &str, error: LexError) {
+        let message = error.to_string();
+        let span = match error {
+            LexError::UnmatchedLoopEnd { span }
+            | LexError::UnmatchedProcedureEnd { span }
+            | LexError::UnclosedBlockComment { span }
+            | LexError::UnpairedOokWord { span } => Some(span),
+            LexError::Macro(error) => Some(error.span()),
+            LexError::UnclosedLoops { .. } | LexError::UnclosedProcedures { .. } => None,
+        };
+        match span {
+            Some(span) => {
+                self.line_num_ = span.line;
+                self.line_idx_ = span.col - 1;
+                let line = program.split_inclusive('\n').nth(span.line - 1);
+                self.throw_run_err(line.unwrap_or_default(), span.col - 1, &message);
+            }
+            None => {
+                let last_char = program.chars().count().saturating_sub(1);
+                self.throw_run_err(program, last_char, &message);
+            }
+        }
+    }
+
+    /// Adds a character to the run of ignored characters that make up a comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `ignored` - Start and end of the current run, if one has started.
+    /// * `span` - Location of the ignored character.
+    /// * `curr_char` - The ignored character.
+    fn extend_ignored(ignored: &mut Option<(Span, usize)>, span: Span, curr_char: char) {
+        // Whitespace only separates the words of a comment, it never starts one
+        if curr_char.is_whitespace() {
+            return;
+        }
+
+        let end = span.byte_offset + span.len;
+        match ignored {
+            Some((_, run_end)) => *run_end = end,
+            None => *ignored = Some((span, end)),
         }
+    }
 
-        // Check for unbalanced '[' brackets
-        if self.brace_stack_ > 0 {
-            self.throw_run_err(
-                program,
-                program.len() - 1,
-                &format!("An Excess of {} '[' brackets were found", self.brace_stack_),
-            );
+    /// Records the current run of ignored characters as a comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string being tokenized.
+    /// * `ignored` - Start and end of the current run, if one has started.
+    fn flush_ignored(&mut self, program: &str, ignored: &mut Option<(Span, usize)>) {
+        if let Some((start, end)) = ignored.take() {
+            self.push_comment(program, start, end);
         }
     }
 
+    /// Records the text between `start` and the byte offset `end` as a comment.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string being tokenized.
+    /// * `start` - Location of the first character of the comment.
+    /// * `end` - Byte offset just past the end of the comment.
+    fn push_comment(&mut self, program: &str, start: Span, end: usize) {
+        let text = program[start.byte_offset..end].trim_end();
+        if text.is_empty() {
+            return;
+        }
+
+        self.comments_.push(Comment {
+            text: text.to_string(),
+            span: Span {
+                len: text.len(),
+                ..start
+            },
+        });
+    }
+
     /// Function to represent a newline character.
     ///
     /// # Returns
@@ -120,10 +668,19 @@ impl Lexer {
     /// # Returns
     ///
     /// A reference to the vector of tokens.
-    pub fn tokens(&self) -> &Vec<Token> {
+    pub fn tokens(&self) -> &Vec<SpannedToken> {
         &self.tokens_
     }
 
+    /// Getter function to retrieve the comments found between tokens.
+    ///
+    /// # Returns
+    ///
+    /// The comments in the order they appear in the program.
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments_
+    }
+
     /// Function to handle and print runtime errors.
     ///
     /// # Arguments
@@ -156,19 +713,25 @@ impl Lexer {
     ///
     /// # Arguments
     ///
-    /// * `program` - The original program string.
-    /// * `lexer_idx` - The index in the lexer where the error occurred.
+    /// * `line` - The original program string.
+    /// * `lexer_idx` - The character index in the line where the error occurred.
     ///
     /// # Returns
     ///
-    /// A tuple containing the error substring and the offset from the start.
+    /// A tuple containing the error substring and its offset in characters from the start.
     fn extract_err_line(line: &str, lexer_idx: usize) -> (&str, usize) {
+        // Indices count characters, which are sliced by their byte offsets
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        if chars.is_empty() {
+            return ("", 0);
+        }
+        let lexer_idx = lexer_idx.min(chars.len() - 1);
         let mut l_ptr = lexer_idx;
         let mut r_ptr = lexer_idx;
 
         // Move left to find the start of the line or newline character
         for _ in 1..10 {
-            if l_ptr as i32 - 1 < 0 || line.chars().nth(l_ptr - 1).unwrap() == Self::new_line() {
+            if l_ptr == 0 || chars[l_ptr - 1].1 == Self::new_line() {
                 break;
             }
             l_ptr -= 1;
@@ -176,13 +739,312 @@ impl Lexer {
 
         // Move right to find the end of the line or newline character
         for _ in 1..10 {
-            if r_ptr + 1 >= line.len() || line.chars().nth(r_ptr + 1).unwrap() == Self::new_line() {
+            if r_ptr + 1 >= chars.len() || chars[r_ptr + 1].1 == Self::new_line() {
                 break;
             }
             r_ptr += 1;
         }
 
         // Return the substring of the line and the offset from the start
-        (&line[l_ptr..=r_ptr], lexer_idx - l_ptr)
+        let (end_offset, end_char) = chars[r_ptr];
+        (
+            &line[chars[l_ptr].0..end_offset + end_char.len_utf8()],
+            lexer_idx - l_ptr,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_line_is_sliced_by_characters() {
+        assert_eq!(Lexer::extract_err_line("naïve ]", 6), ("naïve ]", 6));
+        assert_eq!(Lexer::extract_err_line("café]\n+", 4), ("café]", 4));
+    }
+
+    #[test]
+    fn error_line_shows_nine_characters_each_side() {
+        let line = "ééééééééééééé]ééééééééééééé";
+        let (excerpt, offset) = Lexer::extract_err_line(line, 13);
+        assert_eq!(excerpt, "ééééééééé]ééééééééé");
+        assert_eq!(offset, 9);
+    }
+
+    #[test]
+    fn error_index_past_the_end_points_at_last_character() {
+        assert_eq!(Lexer::extract_err_line("[é", 3), ("[é", 1));
+        assert_eq!(Lexer::extract_err_line("", 0), ("", 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough matches for ']'")]
+    fn error_on_non_ascii_line_is_reported() {
+        Lexer::new(String::from("« naïve » ]"), LexerOptions::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "An Excess of 1 '[' brackets were found")]
+    fn unclosed_loop_after_non_ascii_text_is_reported() {
+        Lexer::new(String::from("[ ünïcode"), LexerOptions::default());
+    }
+
+    #[test]
+    fn non_ascii_text_is_ignored() {
+        let lexer = Lexer::new(String::from("« + » → ."), LexerOptions::default());
+        let tokens: Vec<Token> = lexer.tokens().iter().map(|token| token.token).collect();
+        assert_eq!(tokens, [Token::Add, Token::StdOut]);
+    }
+
+    #[test]
+    fn tokens_point_at_their_line_and_column() {
+        let lexer = Lexer::new(
+            String::from("+ comment\n  >.\n\n,"),
+            LexerOptions::default(),
+        );
+        let spans: Vec<(Token, Span)> = lexer
+            .tokens()
+            .iter()
+            .map(|token| (token.token, token.span))
+            .collect();
+        let span = |line, col, byte_offset| Span {
+            line,
+            col,
+            byte_offset,
+            len: 1,
+        };
+        assert_eq!(
+            spans,
+            [
+                (Token::Add, span(1, 1, 0)),
+                (Token::MoveForward, span(2, 3, 12)),
+                (Token::StdOut, span(2, 4, 13)),
+                (Token::StdIn, span(4, 1, 16)),
+            ]
+        );
+    }
+
+    #[test]
+    fn column_counts_characters_not_bytes() {
+        let lexer = Lexer::new(String::from("é→+"), LexerOptions::default());
+        let span = lexer.tokens()[0].span;
+        assert_eq!((span.line, span.col, span.byte_offset), (1, 3, 5));
+    }
+
+    /// Tokenizes `program` with the given options, returning only the commands.
+    fn tokens(program: &str, options: LexerOptions) -> Vec<Token> {
+        let lexer = Lexer::try_new(program, options).unwrap();
+        lexer.tokens().iter().map(|token| token.token).collect()
+    }
+
+    fn ook() -> LexerOptions {
+        LexerOptions {
+            dialect: Dialect::Ook,
+            ..LexerOptions::default()
+        }
+    }
+
+    #[test]
+    fn ook_reads_every_pair() {
+        let pairs = [
+            ("Ook. Ook?", Token::MoveForward),
+            ("Ook? Ook.", Token::MoveBack),
+            ("Ook. Ook.", Token::Add),
+            ("Ook! Ook!", Token::Sub),
+            ("Ook! Ook.", Token::StdOut),
+            ("Ook. Ook!", Token::StdIn),
+            ("Ook! Ook?", Token::LoopStart),
+            ("Ook? Ook!", Token::LoopEnd),
+        ];
+        for (program, token) in pairs {
+            let program = match token {
+                Token::LoopStart => format!("{} Ook? Ook!", program),
+                Token::LoopEnd => format!("Ook! Ook? {}", program),
+                _ => program.to_string(),
+            };
+            let lexer = Lexer::try_new(&program, ook()).unwrap();
+            let found = lexer
+                .tokens()
+                .iter()
+                .find(|found| found.token == token)
+                .unwrap_or_else(|| panic!("{:?} has no {:?}", program, token));
+            assert_eq!(found.span.len, 9, "{:?}", program);
+        }
+    }
+
+    #[test]
+    fn ook_question_pair_is_not_a_command() {
+        assert_eq!(tokens("Ook? Ook? Ook. Ook.", ook()), [Token::Add]);
+    }
+
+    #[test]
+    fn ook_trailing_word_is_an_error() {
+        let error = Lexer::try_new("Ook. Ook.\nOok! Ook! Ook.", ook()).unwrap_err();
+        let LexError::UnpairedOokWord { span } = error else {
+            panic!("expected an unpaired word, got {:?}", error);
+        };
+        assert_eq!((span.line, span.col, span.byte_offset), (2, 11, 20));
+    }
+
+    #[test]
+    fn ook_words_are_separated_by_any_whitespace() {
+        let program = "Ook.\tOok?  Ook.\n\n   Ook.\r\nOok!Ook!";
+        assert_eq!(
+            tokens(program, ook()),
+            [Token::MoveForward, Token::Add, Token::Sub]
+        );
+
+        // A pair split over two lines is located at its first word
+        let lexer = Lexer::try_new(program, ook()).unwrap();
+        let add = lexer.tokens()[1].span;
+        assert_eq!((add.line, add.col, add.len), (1, 12, 13));
+    }
+
+    #[test]
+    fn ook_keeps_other_text_as_comments() {
+        let lexer = Lexer::try_new("Hello Ook. Ook! world", ook()).unwrap();
+        let comments: Vec<&str> = lexer
+            .comments()
+            .iter()
+            .map(|comment| comment.text.trim())
+            .collect();
+        assert_eq!(comments, ["Hello", "world"]);
+    }
+
+    fn pbrain() -> LexerOptions {
+        let mut options = LexerOptions::default();
+        options.extensions.pbrain = true;
+        options
+    }
+
+    #[test]
+    fn unclosed_procedure_is_an_error() {
+        assert_eq!(
+            Lexer::try_new("(+(-)", pbrain()).unwrap_err(),
+            LexError::UnclosedProcedures { count: 1 }
+        );
+    }
+
+    #[test]
+    fn unmatched_procedure_end_is_an_error() {
+        let error = Lexer::try_new("+\n-)", pbrain()).unwrap_err();
+        let LexError::UnmatchedProcedureEnd { span } = error else {
+            panic!("expected an unmatched ')', got {:?}", error);
+        };
+        assert_eq!((span.line, span.col), (2, 2));
+    }
+
+    #[test]
+    fn procedures_and_loops_cannot_overlap() {
+        let error = Lexer::try_new("([)]", pbrain()).unwrap_err();
+        assert!(
+            matches!(error, LexError::UnmatchedProcedureEnd { span } if span.byte_offset == 2),
+            "{:?}",
+            error
+        );
+        let error = Lexer::try_new("[(])", pbrain()).unwrap_err();
+        assert!(
+            matches!(error, LexError::UnmatchedLoopEnd { span } if span.byte_offset == 2),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn parentheses_are_comments_without_pbrain() {
+        assert_eq!(tokens("(+):", LexerOptions::default()), [Token::Add]);
+    }
+
+    fn comment_style(style: CommentStyle) -> LexerOptions {
+        LexerOptions {
+            comment_style: style,
+            ..LexerOptions::default()
+        }
+    }
+
+    /// Tokenizes `program`, returning the text of every comment.
+    fn comments(program: &str, options: LexerOptions) -> Vec<String> {
+        let lexer = Lexer::try_new(program, options).unwrap();
+        lexer
+            .comments()
+            .iter()
+            .map(|comment| comment.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn ignore_style_keeps_commands_after_prose() {
+        let options = comment_style(CommentStyle::Ignore);
+        assert_eq!(
+            tokens("add one + then take one - ; done.", options.clone()),
+            [Token::Add, Token::Sub, Token::StdOut]
+        );
+        assert_eq!(comments("add one + then", options), ["add one", "then"]);
+    }
+
+    #[test]
+    fn line_style_drops_rest_of_line() {
+        let options = comment_style(CommentStyle::Line);
+        let program = "+ ; add, then print.\n  - # take [one]\n>";
+        assert_eq!(
+            tokens(program, options.clone()),
+            [Token::Add, Token::Sub, Token::MoveForward]
+        );
+        assert_eq!(
+            comments(program, options),
+            ["; add, then print.", "# take [one]"]
+        );
+    }
+
+    #[test]
+    fn line_style_reads_debug_symbol_as_command() {
+        let options = LexerOptions {
+            debug_symbol: true,
+            ..comment_style(CommentStyle::Line)
+        };
+        assert_eq!(tokens("+# dump ; -", options), [Token::Add, Token::Debug]);
+    }
+
+    #[test]
+    fn c_style_skips_line_and_block_comments() {
+        let options = comment_style(CommentStyle::CStyle);
+        let program = "+ // add [one]\n/* take\n - */ > prose, <";
+        assert_eq!(
+            tokens(program, options.clone()),
+            [
+                Token::Add,
+                Token::MoveForward,
+                Token::StdIn,
+                Token::MoveBack
+            ]
+        );
+        assert_eq!(
+            comments(program, options),
+            ["// add [one]", "/* take\n - */", "prose"]
+        );
+    }
+
+    #[test]
+    fn c_style_block_comments_do_not_nest() {
+        let options = comment_style(CommentStyle::CStyle);
+        assert_eq!(tokens("/* a /* b */ + */", options), [Token::Add]);
+    }
+
+    #[test]
+    fn c_style_unterminated_block_comment_is_an_error() {
+        let options = comment_style(CommentStyle::CStyle);
+        let error = Lexer::try_new("+\n  /* never\n closed", options).unwrap_err();
+        assert_eq!(
+            error,
+            LexError::UnclosedBlockComment {
+                span: Span {
+                    line: 2,
+                    col: 3,
+                    byte_offset: 4,
+                    len: 1
+                }
+            }
+        );
     }
 }
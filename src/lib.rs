@@ -0,0 +1,29 @@
+//! Lexer, parser, optimiser and interpreter for Brainfuck programs.
+//!
+//! Every token and syntax tree node carries a [`grammar::Span`] so tools built on
+//! top of the pipeline can map what they observe back to the original source.
+
+pub mod analysis;
+mod binary;
+pub mod compiled;
+pub mod config;
+pub mod diagnostic;
+pub mod dialect;
+pub mod dump;
+pub mod error;
+pub mod formatter;
+pub mod grammar;
+pub mod interpreter;
+pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod minifier;
+pub mod parser;
+pub mod pipeline;
+pub mod preprocessor;
+pub mod profiler;
+pub mod schedule;
+pub mod snapshot;
+pub mod stats;
+pub mod trace;
+pub mod watch;
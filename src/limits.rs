@@ -0,0 +1,46 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Resource limits enforced while a program runs.
+///
+/// Every limit is optional; `None` leaves that resource unbounded.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Maximum number of steps, where each operator and each loop condition test is one step
+    pub max_steps: Option<u64>,
+    /// Maximum number of cells the tape may grow to. Every thread forked by `Y` has its
+    /// own copy of the tape, each held to this limit, so bound memory with `max_threads` too
+    pub max_tape_cells: Option<usize>,
+    /// Maximum number of bytes the program may write to stdout
+    pub max_output_bytes: Option<usize>,
+    /// Maximum wall clock time the program may run for
+    pub wall_timeout: Option<Duration>,
+    /// Maximum number of threads running at once, counting the first
+    pub max_threads: Option<usize>,
+    /// Maximum number of procedure calls a thread may be running at once
+    pub max_call_depth: Option<usize>,
+}
+
+/// Identifies which limit was exceeded, along with the configured value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    TapeCells(usize),
+    OutputBytes(usize),
+    WallTimeout(Duration),
+    Threads(usize),
+    CallDepth(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps(max) => write!(f, "step limit of {}", max),
+            Limit::TapeCells(max) => write!(f, "tape limit of {} cells", max),
+            Limit::OutputBytes(max) => write!(f, "output limit of {} bytes", max),
+            Limit::WallTimeout(max) => write!(f, "timeout of {:?}", max),
+            Limit::Threads(max) => write!(f, "thread limit of {}", max),
+            Limit::CallDepth(max) => write!(f, "call depth limit of {}", max),
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+
+use crate::analysis::{LoopEntry, LoopInfo, LoopShape, PointerAnalysis};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::grammar::{Expression, Loop, Span, Token};
+
+/// The checks performed by the linter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// A loop that is reached while the current cell is always zero
+    UnreachableLoop,
+    /// A loop whose body can never change the cell it tests
+    InfiniteLoop,
+    /// Adjacent operators that cancel each other out
+    NoOp,
+    /// A loop that moves the pointer on every iteration
+    PointerDrift,
+    /// Input that is overwritten or discarded before it is used
+    UnusedInput,
+}
+
+impl LintKind {
+    /// Every lint, in the order of their codes
+    pub const ALL: [LintKind; 5] = [
+        LintKind::UnreachableLoop,
+        LintKind::InfiniteLoop,
+        LintKind::NoOp,
+        LintKind::PointerDrift,
+        LintKind::UnusedInput,
+    ];
+
+    /// Gets the code printed alongside the lint.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LintKind::UnreachableLoop => "BF001",
+            LintKind::InfiniteLoop => "BF002",
+            LintKind::NoOp => "BF003",
+            LintKind::PointerDrift => "BF004",
+            LintKind::UnusedInput => "BF005",
+        }
+    }
+
+    /// Gets the name used to configure the lint in `brainfk.toml`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintKind::UnreachableLoop => "unreachable-loop",
+            LintKind::InfiniteLoop => "infinite-loop",
+            LintKind::NoOp => "no-op",
+            LintKind::PointerDrift => "pointer-drift",
+            LintKind::UnusedInput => "unused-input",
+        }
+    }
+
+    /// Finds a lint by the name used in `brainfk.toml`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// How a lint is reported.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LintLevel {
+    /// The lint is not reported
+    Allow,
+    /// The lint is reported as a warning
+    Warn,
+    /// The lint is reported as an error
+    Deny,
+}
+
+impl std::str::FromStr for LintLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "allow" => Ok(LintLevel::Allow),
+            "warn" => Ok(LintLevel::Warn),
+            "deny" | "error" => Ok(LintLevel::Deny),
+            _ => Err(format!("Unknown lint level '{}'", level)),
+        }
+    }
+}
+
+/// Reports likely mistakes in a program using the syntax tree and the pointer analysis.
+#[derive(Debug)]
+pub struct Linter<'a> {
+    /// Level of every configured lint, the others use the severity chosen by the check
+    levels: &'a HashMap<LintKind, LintLevel>,
+    /// Lints found so far
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    /// Lints a program.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The unoptimised abstract syntax tree (AST) of the program.
+    /// * `levels` - Level of every configured lint, the others use the severity chosen by the check.
+    ///
+    /// # Returns
+    ///
+    /// The lints found, in source order.
+    pub fn lint(ast: &[Expression], levels: &'a HashMap<LintKind, LintLevel>) -> Vec<Diagnostic> {
+        let mut linter = Linter {
+            levels,
+            diagnostics: vec![],
+        };
+        let analysis = PointerAnalysis::analyse(ast);
+
+        linter.check_loops(ast, analysis.loops(), true);
+        linter.check_block(ast, analysis.loops());
+        linter.check_unused_input(ast, analysis.loops(), true);
+
+        linter
+            .diagnostics
+            .sort_by_key(|diagnostic| diagnostic.span.byte_offset);
+        linter.diagnostics
+    }
+
+    /// Records a lint, applying its configured level.
+    fn report(&mut self, kind: LintKind, severity: Severity, span: Span, message: String) {
+        let severity = match self.levels.get(&kind) {
+            Some(LintLevel::Allow) => return,
+            Some(LintLevel::Warn) => Severity::Warning,
+            Some(LintLevel::Deny) => Severity::Error,
+            None => severity,
+        };
+
+        let diagnostic = match severity {
+            Severity::Warning => Diagnostic::warning(span, message),
+            Severity::Error => Diagnostic::error(span, message),
+        };
+        self.diagnostics.push(diagnostic.with_code(kind.code()));
+    }
+
+    /// Checks every loop for being unreachable, never terminating or drifting the pointer.
+    ///
+    /// # Arguments
+    ///
+    /// * `expressions` - The block containing the loops.
+    /// * `loops` - What the pointer analysis found out about each loop it reached.
+    /// * `at_start` - Whether the block is the start of the program.
+    fn check_loops(&mut self, expressions: &[Expression], loops: &[LoopInfo], at_start: bool) {
+        for (idx, expression) in expressions.iter().enumerate() {
+            let _loop = match expression {
+                Expression::Loop(_loop) => _loop,
+                Expression::Procedure(procedure) => {
+                    self.check_loops(&procedure.body, loops, false);
+                    continue;
+                }
+                Expression::Operator(_) => continue,
+            };
+            let info = Self::loop_info(loops, _loop);
+
+            match info.map(|info| info.entry) {
+                // A loop at the very start of a program is the usual way of writing a comment
+                Some(LoopEntry::Never) if !(at_start && idx == 0) => self.report(
+                    LintKind::UnreachableLoop,
+                    Severity::Warning,
+                    _loop.span,
+                    String::from("Loop never runs as the current cell is always zero here"),
+                ),
+                Some(entry) if entry != LoopEntry::Never && Self::is_stuck(_loop) => {
+                    let (severity, message) = match entry {
+                        LoopEntry::Always => (Severity::Error, "Loop never terminates"),
+                        _ => (Severity::Warning, "Loop never terminates once entered"),
+                    };
+                    self.report(
+                        LintKind::InfiniteLoop,
+                        severity,
+                        _loop.span,
+                        String::from(message),
+                    );
+                }
+                _ => {}
+            }
+
+            if Self::is_dead(loops, _loop) {
+                continue;
+            }
+
+            match PointerAnalysis::loop_shape(&_loop.body) {
+                LoopShape::Balanced => {}
+                LoopShape::UnboundedLeft => self.report(
+                    LintKind::PointerDrift,
+                    Severity::Warning,
+                    _loop.span,
+                    String::from("Loop moves the pointer left on every iteration"),
+                ),
+                LoopShape::UnboundedRight => self.report(
+                    LintKind::PointerDrift,
+                    Severity::Warning,
+                    _loop.span,
+                    String::from("Loop moves the pointer right on every iteration"),
+                ),
+                LoopShape::Unknown => {}
+            }
+
+            self.check_loops(&_loop.body, loops, false);
+        }
+    }
+
+    /// Finds what the pointer analysis found out about a loop.
+    fn loop_info(loops: &[LoopInfo], _loop: &Loop) -> Option<LoopInfo> {
+        loops
+            .binary_search_by_key(&_loop.span.byte_offset, |info| info.span.byte_offset)
+            .ok()
+            .map(|found| loops[found])
+    }
+
+    /// Checks whether the body of a loop never runs, in which case it is not linted.
+    fn is_dead(loops: &[LoopInfo], _loop: &Loop) -> bool {
+        Self::loop_info(loops, _loop).is_none_or(|info| info.entry == LoopEntry::Never)
+    }
+
+    /// Checks whether a loop body can never change the cell the loop tests.
+    fn is_stuck(_loop: &Loop) -> bool {
+        let mut offset: i64 = 0;
+        let mut delta: usize = 0;
+        for expression in &_loop.body {
+            let Expression::Operator(_op) = expression else {
+                // A nested loop could write to any cell
+                return false;
+            };
+            match _op.type_name {
+                Token::MoveForward => offset += _op.count as i64,
+                Token::MoveBack => offset -= _op.count as i64,
+                Token::Add if offset == 0 => delta += _op.count,
+                Token::Sub if offset == 0 => delta += 256 - _op.count % 256,
+                Token::StdIn
+                | Token::Fork
+                | Token::Retrieve
+                | Token::ShiftRight
+                | Token::ShiftLeft
+                | Token::Not
+                | Token::Xor
+                | Token::And
+                | Token::Or
+                    if offset == 0 =>
+                {
+                    return false
+                }
+                // A procedure could write to any cell, and `@` ends the program
+                Token::Call | Token::End => return false,
+                _ => {}
+            }
+        }
+        offset == 0 && delta.is_multiple_of(256)
+    }
+
+    /// Checks a block and the blocks nested in it for operators that cancel out.
+    fn check_block(&mut self, expressions: &[Expression], loops: &[LoopInfo]) {
+        let mut idx = 0;
+        while idx < expressions.len() {
+            let Some(pair) = Self::inverse_pair(&expressions[idx]) else {
+                match &expressions[idx] {
+                    Expression::Loop(_loop) if !Self::is_dead(loops, _loop) => {
+                        self.check_block(&_loop.body, loops)
+                    }
+                    Expression::Procedure(procedure) => self.check_block(&procedure.body, loops),
+                    _ => {}
+                }
+                idx += 1;
+                continue;
+            };
+
+            // Collect the run of operators made up of the same pair
+            let mut net: i64 = 0;
+            let mut seen = (false, false);
+            let mut span = expressions[idx].span();
+            let start = idx;
+            while let Some(Expression::Operator(_op)) = expressions.get(idx) {
+                if _op.type_name == pair.0 {
+                    net += _op.count as i64;
+                    seen.0 = true;
+                } else if _op.type_name == pair.1 {
+                    net -= _op.count as i64;
+                    seen.1 = true;
+                } else {
+                    break;
+                }
+                span = span.merge(_op.span);
+                idx += 1;
+            }
+
+            if !(seen.0 && seen.1) {
+                continue;
+            }
+            let is_cell_op = pair.0 == Token::Add;
+            let remaining = if is_cell_op { net.rem_euclid(256) } else { net };
+            let message = if remaining == 0 {
+                String::from("Operators cancel each other out and have no effect")
+            } else {
+                let (token, count) = if remaining > 0 {
+                    (pair.0, remaining)
+                } else {
+                    (pair.1, -remaining)
+                };
+                format!(
+                    "{} operators partly cancel out and can be written as {}",
+                    idx - start,
+                    (token as u8 as char).to_string().repeat(count as usize)
+                )
+            };
+            self.report(LintKind::NoOp, Severity::Warning, span, message);
+        }
+    }
+
+    /// Gets the pair of operators that cancel each other out, starting with `expression`.
+    fn inverse_pair(expression: &Expression) -> Option<(Token, Token)> {
+        let Expression::Operator(_op) = expression else {
+            return None;
+        };
+        match _op.type_name {
+            Token::Add | Token::Sub => Some((Token::Add, Token::Sub)),
+            Token::MoveForward | Token::MoveBack => Some((Token::MoveForward, Token::MoveBack)),
+            _ => None,
+        }
+    }
+
+    /// Checks a block for input that is overwritten before it is read.
+    ///
+    /// # Arguments
+    ///
+    /// * `expressions` - The block to check.
+    /// * `loops` - What the pointer analysis found out about each loop it reached.
+    /// * `is_program` - Whether the block is the whole program, so pending input is never read.
+    fn check_unused_input(
+        &mut self,
+        expressions: &[Expression],
+        loops: &[LoopInfo],
+        is_program: bool,
+    ) {
+        // Input that has not been read yet, by pointer offset since the last loop
+        let mut pending: HashMap<i64, Span> = HashMap::new();
+        let mut offset: i64 = 0;
+
+        for expression in expressions {
+            match expression {
+                Expression::Loop(_loop) => {
+                    // The loop reads the current cell and its body may read any other
+                    pending.clear();
+                    if !Self::is_dead(loops, _loop) {
+                        self.check_unused_input(&_loop.body, loops, false);
+                    }
+                    offset = 0;
+                }
+                Expression::Procedure(procedure) => {
+                    // Defining the procedure reads its number from the current cell
+                    pending.remove(&offset);
+                    self.check_unused_input(&procedure.body, loops, false);
+                }
+                Expression::Operator(_op) => match _op.type_name {
+                    Token::MoveForward => offset += _op.count as i64,
+                    Token::MoveBack => offset -= _op.count as i64,
+                    Token::StdIn => {
+                        if let Some(span) = pending.insert(offset, _op.span) {
+                            self.report(
+                                LintKind::UnusedInput,
+                                Severity::Warning,
+                                span,
+                                String::from("Input is overwritten before it is used"),
+                            );
+                        }
+                    }
+                    Token::Debug | Token::Fork => pending.clear(),
+                    // The procedure may read any cell and leave the pointer anywhere
+                    Token::Call => {
+                        pending.clear();
+                        offset = 0;
+                    }
+                    _ => {
+                        pending.remove(&offset);
+                    }
+                },
+            }
+        }
+
+        if is_program {
+            let mut unread: Vec<Span> = pending.into_values().collect();
+            unread.sort_by_key(|span| span.byte_offset);
+            for span in unread {
+                self.report(
+                    LintKind::UnusedInput,
+                    Severity::Warning,
+                    span,
+                    String::from("Input is never used"),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::LexerOptions;
+    use crate::parser::Parser;
+
+    /// Lints `program` with the given lint levels.
+    fn lint(program: &str, levels: &HashMap<LintKind, LintLevel>) -> Vec<Diagnostic> {
+        let mut parser = Parser::new(program.to_string(), false, LexerOptions::default());
+        parser.generate_syntax_tree();
+        Linter::lint(&parser.into_ast(), levels)
+    }
+
+    #[test]
+    fn lints_carry_their_code() {
+        let diagnostics = lint("+-", &HashMap::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some(LintKind::NoOp.code()));
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn configured_level_overrides_severity() {
+        let levels = HashMap::from([(LintKind::NoOp, LintLevel::Deny)]);
+        let diagnostics = lint("+-", &levels);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].code, Some("BF003"));
+
+        let levels = HashMap::from([(LintKind::NoOp, LintLevel::Allow)]);
+        assert!(lint("+-", &levels).is_empty());
+    }
+
+    /// Lints `program` with the default levels, returning the code, severity and
+    /// location of every lint.
+    fn found(program: &str) -> Vec<(&'static str, Severity, usize, usize)> {
+        lint(program, &HashMap::new())
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.code.unwrap_or_default(),
+                    diagnostic.severity,
+                    diagnostic.span.byte_offset,
+                    diagnostic.span.len,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unreachable_loop_after_clearing_loop() {
+        assert_eq!(found("+[-][>+<-]"), [("BF001", Severity::Warning, 4, 6)]);
+    }
+
+    #[test]
+    fn comment_loop_at_start_is_not_unreachable() {
+        assert!(found("[ comment ]+").is_empty());
+    }
+
+    #[test]
+    fn infinite_loop_that_never_changes_its_cell() {
+        assert_eq!(found("+[>+<]"), [("BF002", Severity::Error, 1, 5)]);
+        // Only a warning when the loop may be skipped
+        assert_eq!(found(",[>+<]"), [("BF002", Severity::Warning, 1, 5)]);
+    }
+
+    #[test]
+    fn no_op_covers_cancelling_run() {
+        assert_eq!(found("+><-"), [("BF003", Severity::Warning, 1, 2)]);
+        let diagnostics = lint("+++--", &HashMap::new());
+        assert_eq!(
+            diagnostics[0].message,
+            "5 operators partly cancel out and can be written as +"
+        );
+        assert_eq!(
+            (diagnostics[0].span.byte_offset, diagnostics[0].span.len),
+            (0, 5)
+        );
+    }
+
+    #[test]
+    fn pointer_drift_in_unbalanced_loop() {
+        assert_eq!(found("+[>+]"), [("BF004", Severity::Warning, 1, 4)]);
+        assert_eq!(found(">>+[<]"), [("BF004", Severity::Warning, 3, 3)]);
+    }
+
+    #[test]
+    fn unused_input_overwritten_or_never_read() {
+        let diagnostics = lint(",,", &HashMap::new());
+        let messages: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.message.as_str(), diagnostic.span.byte_offset))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("Input is overwritten before it is used", 0),
+                ("Input is never used", 1)
+            ]
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.code == Some("BF005")));
+        assert!(found(",.").is_empty());
+    }
+
+    #[test]
+    fn config_file_sets_lint_levels() {
+        let config =
+            crate::config::Config::parse("[lints]\npointer-drift = \"deny\"\nno-op = \"allow\"\n")
+                .unwrap();
+        let diagnostics = lint("+><[>+]", &config.lints);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Some("BF004"));
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+}
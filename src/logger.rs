@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+
+use brainfk::trace;
+use colored::Colorize;
+use log::{Level, LevelFilter, Metadata, Record};
+
+use crate::cli::Args;
+
+/// Writes log records to stderr, and the trace records logged by the interpreter to
+/// stderr or a file.
+pub struct Logger {
+    /// Most detailed level of the regular messages written
+    level: LevelFilter,
+    /// Where trace lines are written, or `None` when tracing is disabled
+    trace_output: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Logger {
+    /// Installs the logger as the destination of the `log` macros, configured by the
+    /// command line. Exits if the trace file cannot be created.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parsed command line.
+    pub fn init(args: &Args) {
+        let trace_output: Option<Box<dyn Write + Send>> = match (&args.trace, &args.trace_path) {
+            (None, _) => None,
+            (Some(_), None) => Some(Box::new(io::stderr())),
+            (Some(_), Some(path)) => match File::create(path) {
+                Ok(file) => Some(Box::new(BufWriter::new(file))),
+                Err(why) => {
+                    eprintln!("{}: couldn't create {}: {}", "Error".red(), path, why);
+                    std::process::exit(1);
+                }
+            },
+        };
+
+        let level = level_filter(args.quiet, args.verbosity);
+        // Trace records are logged at the most detailed level whatever the verbosity
+        let max_level = match trace_output {
+            Some(_) => LevelFilter::Trace,
+            None => level,
+        };
+
+        let logger = Logger {
+            level,
+            trace_output: trace_output.map(Mutex::new),
+        };
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(max_level);
+        }
+    }
+}
+
+/// Maps the `-q` and `-v` flags to the most detailed level of regular messages written.
+///
+/// # Arguments
+///
+/// * `quiet` - Whether `-q` was given, which overrides any `-v`.
+/// * `verbosity` - Number of times `-v` was given.
+///
+/// # Returns
+///
+/// `Error` when quiet, otherwise `Warn` raised one level for every `-v` up to `Trace`.
+fn level_filter(quiet: bool, verbosity: u8) -> LevelFilter {
+    match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match metadata.target() {
+            trace::TARGET => self.trace_output.is_some(),
+            _ => metadata.level() <= self.level,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.target() == trace::TARGET {
+            if let Some(output) = &self.trace_output {
+                if let Ok(mut output) = output.lock() {
+                    let _ = writeln!(output, "{}", record.args());
+                }
+            }
+            return;
+        }
+
+        let level = match record.level() {
+            Level::Error => "ERROR".red(),
+            Level::Warn => "WARN ".yellow(),
+            Level::Info => "INFO ".green(),
+            Level::Debug => "DEBUG".blue(),
+            Level::Trace => "TRACE".dimmed(),
+        };
+        eprintln!("{} {}: {}", level, record.target(), record.args());
+    }
+
+    fn flush(&self) {
+        if let Some(output) = &self.trace_output {
+            if let Ok(mut output) = output.lock() {
+                let _ = output.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_raises_level() {
+        assert_eq!(level_filter(false, 0), LevelFilter::Warn);
+        assert_eq!(level_filter(false, 1), LevelFilter::Info);
+        assert_eq!(level_filter(false, 2), LevelFilter::Debug);
+        assert_eq!(level_filter(false, 3), LevelFilter::Trace);
+        assert_eq!(level_filter(false, 9), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn quiet_overrides_verbosity() {
+        assert_eq!(level_filter(true, 0), LevelFilter::Error);
+        assert_eq!(level_filter(true, 3), LevelFilter::Error);
+    }
+}
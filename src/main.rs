@@ -1,89 +1,368 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::Path;
 
 use colored::Colorize;
 
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
+use brainfk::analysis::PointerAnalysis;
+use brainfk::compiled::{self, CompiledProgram};
+use brainfk::config::Config;
+use brainfk::diagnostic::Severity;
+use brainfk::dump::Dumper;
+use brainfk::error::RunError;
+use brainfk::formatter::Formatter;
+use brainfk::grammar::{Expression, Token};
+use brainfk::interpreter::Interpreter;
+use brainfk::lexer::{Dialect, Lexer, LexerOptions};
+use brainfk::lint::Linter;
+use brainfk::minifier::Minifier;
+use brainfk::pipeline::Pipeline;
+use brainfk::snapshot::Snapshot;
+use brainfk::stats::StatsFormat;
+use brainfk::watch::{WatchAction, Watchpoint};
 
-mod grammar;
-mod interpreter;
-mod lexer;
-mod parser;
+use crate::cli::{Args, Command};
+use crate::debugger::Debugger;
+use crate::logger::Logger;
+use crate::repl::Repl;
 
-/// Represents a Brainfuck program with its content and optimization flag.
+mod cli;
+mod debugger;
+mod logger;
+mod repl;
+
+/// Represents a Brainfuck program with its content, optimization flag and lexer options.
 struct Program {
     content: String,
     should_optimise: bool,
+    lexer_options: LexerOptions,
+    /// Set when the file holds a program saved by `compile` instead of source
+    compiled: Option<CompiledProgram>,
 }
 
 /// Main entry point of the program. Reads a Brainfuck program from a file, parses it,
 /// optimizes it, and executes it using an interpreter.
 fn main() {
     // Read the program from file
+    let args = Args::parse();
+    Logger::init(&args);
+    if args.command == Command::Repl {
+        Repl::new(&args).run();
+        return;
+    }
+
     let Program {
         content,
         should_optimise,
-    } = read_file();
-
-    // Init a parser that takes the program and converts it to a token stream
-    let mut parser_optimised = Parser::new(content.clone(), should_optimise);
-    let mut parser_unoptimised = Parser::new(content.clone(), should_optimise);
-
-    // Generates an abstract syntax tree for the program
-    parser_optimised.generate_syntax_tree();
-    parser_unoptimised.generate_syntax_tree();
-
-    let op_inst = parser_optimised.get_num_of_instr();
-    let unop_inst = parser_unoptimised.get_num_of_instr();
-    let percentage_diff = (unop_inst as f32 / op_inst as f32) * 100.0;
-    println!(
-        r#"
-| Number of instructions to execute:
-| Optimised: {}
-| Unoptimised: {}
-| Persentage diff in token size : {}%
-    "#,
-        op_inst, unop_inst, percentage_diff
-    );
+        lexer_options,
+        compiled,
+    } = read_file(&args);
+
+    if compiled.is_some() && !matches!(args.command, Command::Run | Command::Profile) {
+        eprintln!(
+            "{}: only `run` and `profile` accept compiled programs",
+            "Error".red()
+        );
+        std::process::exit(2);
+    }
+
+    match args.command {
+        Command::Lint => {
+            lint(&args, &content, lexer_options);
+            return;
+        }
+        Command::Fmt => {
+            if lexer_options.dialect != Dialect::Brainfuck {
+                eprintln!("{}: `fmt` only formats Brainfuck programs", "Error".red());
+                std::process::exit(2);
+            }
+            if lexer_options.macros {
+                eprintln!(
+                    "{}: `fmt` can't format programs that use macros",
+                    "Error".red()
+                );
+                std::process::exit(2);
+            }
+            format(&args, &content, lexer_options);
+            return;
+        }
+        Command::Translate => {
+            translate(&args, &content, lexer_options);
+            return;
+        }
+        Command::Minify => {
+            minify(&content, lexer_options);
+            return;
+        }
+        Command::Dump => {
+            let pipeline = Pipeline::compile(content, should_optimise, lexer_options);
+            print!("{}", Dumper::dump(pipeline.ast(), args.dump_format));
+            return;
+        }
+        Command::Compile => {
+            compile(&args, content, should_optimise, lexer_options);
+            return;
+        }
+        Command::Debug => {
+            let pipeline = Pipeline::compile(content.clone(), should_optimise, lexer_options);
+            Debugger::new(pipeline.ast(), &content, &args).run();
+            return;
+        }
+        Command::Run | Command::Profile => {}
+        Command::Repl => unreachable!("the REPL does not read a program"),
+    }
+
+    let pipeline;
+    let syntax_tree: &[Expression] = match &compiled {
+        Some(program) => &program.ast,
+        None => {
+            pipeline = Pipeline::compile(content.clone(), should_optimise, lexer_options);
+            if let Some(format) = args.stats {
+                print_compile_stats(&pipeline, format);
+            }
+            pipeline.ast()
+        }
+    };
+
+    // Rejects programs that provably move the pointer off the tape
+    if args.check_pointer {
+        check_pointer(syntax_tree, &content);
+    }
 
     // Creates the interpreter to run the code
-    let mut interpreter = create_interpreter(&mut parser_optimised);
+    let mut interpreter = Interpreter::new(syntax_tree);
+    interpreter.set_limits(args.limits);
+    interpreter.set_schedule(args.schedule);
+    if args.command == Command::Profile {
+        interpreter.enable_profiling();
+    }
+    for &cell in &args.trace_cells {
+        interpreter.add_watchpoint(Watchpoint::Change(cell), WatchAction::Log);
+    }
+    if let Some(options) = &args.trace {
+        interpreter.enable_trace(options.clone());
+    }
+    if let Some(path) = &args.resume_path {
+        resume(&mut interpreter, path);
+    }
 
     // Executes the code
-    interpreter.run_code();
-}
+    let result = interpreter.run_code();
+    log::logger().flush();
 
-/// Creates an interpreter for the given parser and returns it.
-fn create_interpreter(parser: &mut Parser) -> Interpreter {
-    match parser.get_ast() {
-        Some(syntax_tree) => {
-            return Interpreter::new(syntax_tree);
+    if let Some(profiler) = interpreter.profiler() {
+        eprintln!("\n{}", profiler.report(&content));
+        if args.annotate {
+            eprintln!("{}", profiler.annotate(&content));
         }
-        None => {
-            eprintln!("{}", "Tree has not been generated yet".red());
-            std::process::exit(255);
+    }
+
+    if let Some(format) = args.stats {
+        eprintln!("\n{}", interpreter.stats().render(format));
+    }
+
+    if let Err(error) = result {
+        eprintln!("\n{}: {}", "Error".red(), error);
+        if let (RunError::LimitExceeded { .. }, Some(path)) = (error, &args.checkpoint_path) {
+            checkpoint(&interpreter, path);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Restores the interpreter from the snapshot saved in `path`, exiting if it cannot be read.
+fn resume(interpreter: &mut Interpreter, path: &str) {
+    let restored = fs::read(path)
+        .map_err(|why| why.to_string())
+        .and_then(|bytes| Snapshot::decode(&bytes).map_err(|why| why.to_string()))
+        .and_then(|snapshot| interpreter.restore(snapshot).map_err(|why| why.to_string()));
+
+    if let Err(why) = restored {
+        eprintln!("{}: couldn't resume from {}: {}", "Error".red(), path, why);
+        std::process::exit(1);
+    }
+}
+
+/// Saves a snapshot of the interpreter to `path` so the run can be resumed later.
+fn checkpoint(interpreter: &Interpreter, path: &str) {
+    if interpreter.thread_count() > 1 {
+        eprintln!(
+            "{}: couldn't save a checkpoint while {} threads are running",
+            "Error".red(),
+            interpreter.thread_count()
+        );
+        return;
+    }
+    match fs::write(path, interpreter.snapshot().encode()) {
+        Ok(()) => eprintln!(
+            "Saved checkpoint to {}, continue with `--resume {}`",
+            path, path
+        ),
+        Err(why) => panic!("couldn't write {}: {}", path, why),
+    }
+}
+
+/// Prints the node counts of the compiled program and what each pass removed to stderr.
+fn print_compile_stats(pipeline: &Pipeline, format: StatsFormat) {
+    match format {
+        StatsFormat::Human => eprintln!("{}\n", pipeline.report()),
+        StatsFormat::Json => eprintln!("{}", pipeline.to_json()),
+    }
+}
+
+/// Runs the pointer analysis on the parsed program, printing what it finds and
+/// exiting if an underflow is certain to happen.
+fn check_pointer(syntax_tree: &[Expression], content: &str) {
+    let analysis = PointerAnalysis::analyse(syntax_tree);
+    for diagnostic in analysis.diagnostics() {
+        eprintln!("{}\n", diagnostic.render(content));
+    }
+
+    if analysis
+        .diagnostics()
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        std::process::exit(1);
+    }
+}
+
+/// Loads the `brainfk.toml` given on the command line or found next to the program,
+/// exiting if it cannot be read.
+fn load_config(args: &Args) -> Config {
+    match &args.config_path {
+        Some(path) => Config::load(Path::new(path)),
+        None => Config::find(Path::new(&args.file_path)),
+    }
+    .unwrap_or_else(|why| {
+        eprintln!("{}: {}", "Error".red(), why);
+        std::process::exit(2);
+    })
+}
+
+/// Lints the program, printing every lint found and exiting if any are errors.
+fn lint(args: &Args, content: &str, lexer_options: LexerOptions) {
+    let config = load_config(args);
+
+    // Lints run on the unoptimised tree so every source command is still visible
+    let pipeline = Pipeline::compile(content.to_string(), false, lexer_options);
+    let diagnostics = Linter::lint(pipeline.unoptimised_ast(), &config.lints);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{}\n", diagnostic.render(content));
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    eprintln!(
+        "{}: {} warning(s), {} error(s)",
+        args.file_path,
+        diagnostics.len() - errors,
+        errors
+    );
+
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Formats the program in place, or with `--check` exits with an error if it is not formatted.
+fn format(args: &Args, content: &str, lexer_options: LexerOptions) {
+    let mut options = load_config(args).format;
+    if let Some(width) = args.width {
+        options.max_width = width;
+    }
+
+    let lexer = Lexer::new(content.to_string(), lexer_options);
+    let formatted = Formatter::format(&lexer, content, options);
+    if formatted == content {
+        return;
+    }
+
+    if args.check {
+        eprintln!("{}: {} is not formatted", "Error".red(), args.file_path);
+        std::process::exit(1);
+    }
+    if let Err(why) = fs::write(&args.file_path, formatted) {
+        panic!("couldn't write {}: {}", args.file_path, why);
+    }
+}
+
+/// Prints the shortest equivalent program to stdout.
+fn minify(content: &str, lexer_options: LexerOptions) {
+    let pipeline = Pipeline::compile(content.to_string(), true, lexer_options);
+    println!("{}", Minifier::minify(pipeline.ast()));
+}
+
+/// Prints the program in the dialect given by `--to`, or saves it to `--output`.
+fn translate(args: &Args, content: &str, lexer_options: LexerOptions) {
+    let Some(target) = &args.target_dialect else {
+        eprintln!(
+            "{}: `translate` needs a dialect to write, given by `--to`",
+            "Error".red()
+        );
+        std::process::exit(2);
+    };
+
+    let lexer = Lexer::new(content.to_string(), lexer_options);
+    let tokens: Vec<Token> = lexer.tokens().iter().map(|token| token.token).collect();
+    let translated = target.write(&tokens).unwrap_or_else(|why| {
+        eprintln!("{}: {}", "Error".red(), why);
+        std::process::exit(1);
+    });
+
+    match &args.output_path {
+        Some(path) => {
+            if let Err(why) = fs::write(path, translated) {
+                panic!("couldn't write {}: {}", path, why);
+            }
         }
+        None => print!("{}", translated),
     }
 }
 
-/// Reads a Brainfuck program from a file and returns a `Program` struct containing
-/// the program content and optimization flag.
-fn read_file() -> Program {
-    // let args: Vec<String> = env::args().collect();
-    //
-    // if args.len() < 2 {
-    //     eprintln!("Usage: {} <filename> <flag[-0]>", args[0]);
-    //     std::process::exit(404);
-    // }
-    //
-    // let file_path = &args[1];
-    // let optimisation = args.len() > 2;
-
-    let file_path = String::from("resources/program.bfk");
-    let optimisation = true;
-
-    let mut file = match File::open(&file_path) {
+/// Parses the program and saves it next to the source, or to `--output`.
+fn compile(args: &Args, content: String, should_optimise: bool, lexer_options: LexerOptions) {
+    let program = CompiledProgram {
+        optimised: should_optimise,
+        ast: Pipeline::compile(content, should_optimise, lexer_options).into_ast(),
+    };
+
+    let output_path = match &args.output_path {
+        Some(path) => path.into(),
+        None => Path::new(&args.file_path).with_extension(compiled::EXTENSION),
+    };
+    if let Err(why) = fs::write(&output_path, program.encode()) {
+        panic!("couldn't write {}: {}", output_path.display(), why);
+    }
+}
+
+/// Reads a Brainfuck program from the file given on the command line and returns a
+/// `Program` struct containing the program content, optimization flag and lexer options.
+fn read_file(args: &Args) -> Program {
+    let file_path = &args.file_path;
+
+    if Path::new(file_path).extension() == Some(compiled::EXTENSION.as_ref()) {
+        let bytes = match fs::read(file_path) {
+            Ok(bytes) => bytes,
+            Err(why) => panic!("couldn't read {}: {}", file_path, why),
+        };
+        let program = CompiledProgram::decode(&bytes).unwrap_or_else(|why| {
+            eprintln!("{}: {}: {}", "Error".red(), file_path, why);
+            std::process::exit(1);
+        });
+
+        return Program {
+            content: String::new(),
+            should_optimise: program.optimised,
+            lexer_options: args.lexer_options.clone(),
+            compiled: Some(program),
+        };
+    }
+
+    let mut file = match File::open(file_path) {
         Ok(file) => file,
         Err(why) => panic!("couldn't open {}: {}", file_path, why),
     };
@@ -96,6 +375,8 @@ fn read_file() -> Program {
 
     Program {
         content: prog,
-        should_optimise: optimisation,
+        should_optimise: args.should_optimise,
+        lexer_options: args.lexer_options.clone(),
+        compiled: None,
     }
 }
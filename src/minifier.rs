@@ -0,0 +1,47 @@
+use crate::grammar::Expression;
+
+/// Serializes a syntax tree back into plain Brainfuck source.
+pub struct Minifier;
+
+impl Minifier {
+    /// Writes out the commands of a syntax tree with no comments or whitespace.
+    ///
+    /// The tree is expected to have been optimized already, so the output is only as
+    /// short as the passes that produced it made it.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The abstract syntax tree (AST) to serialize.
+    ///
+    /// # Returns
+    ///
+    /// The program source.
+    pub fn minify(ast: &[Expression]) -> String {
+        let mut source = String::new();
+        Self::write(ast, &mut source);
+        source
+    }
+
+    /// Appends the commands of `ast` to `source`.
+    fn write(ast: &[Expression], source: &mut String) {
+        for expression in ast {
+            match expression {
+                Expression::Loop(_loop) => {
+                    source.push('[');
+                    Self::write(&_loop.body, source);
+                    source.push(']');
+                }
+                Expression::Procedure(procedure) => {
+                    source.push('(');
+                    Self::write(&procedure.body, source);
+                    source.push(')');
+                }
+                Expression::Operator(operator) => {
+                    // Tokens are numbered by the character they are written as
+                    let command = operator.type_name as u8 as char;
+                    source.extend(std::iter::repeat_n(command, operator.count));
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,10 @@
-use crate::grammar::{Expression, Operator, Token};
-use crate::lexer::Lexer;
-use colored::Colorize;
+use crate::analysis::{LoopEntry, PointerAnalysis};
+use crate::grammar::{Expression, Loop, Operator, Procedure, Span, Token};
+use crate::lexer::{LexError, Lexer, LexerOptions};
+use std::collections::HashSet;
+
+/// An optimization pass that rewrites the syntax tree in place.
+pub type Pass = fn(&mut Vec<Expression>);
 
 /// Struct representing a parser for the custom language.
 #[derive(Debug)]
@@ -24,13 +28,42 @@ impl Parser {
     ///
     /// * `program` - The program string to be parsed.
     /// * `optimize` - A boolean indicating whether to optimize the AST.
+    /// * `lexer_options` - Options passed on to the lexer.
     ///
     /// # Returns
     ///
     /// A new instance of `Parser`.
-    pub fn new(program: String, optimize: bool) -> Self {
+    pub fn new(program: String, optimize: bool, lexer_options: LexerOptions) -> Self {
+        Self::from_lexer(Lexer::new(program, lexer_options), optimize)
+    }
+
+    /// Constructs a new `Parser` instance, returning an error instead of panicking
+    /// if the program cannot be tokenized.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program string to be parsed.
+    /// * `optimize` - A boolean indicating whether to optimize the AST.
+    /// * `lexer_options` - Options passed on to the lexer.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Parser`, or the error found by the lexer.
+    pub fn try_new(
+        program: &str,
+        optimize: bool,
+        lexer_options: LexerOptions,
+    ) -> Result<Self, LexError> {
+        Ok(Self::from_lexer(
+            Lexer::try_new(program, lexer_options)?,
+            optimize,
+        ))
+    }
+
+    /// Constructs a `Parser` reading the tokens of `lexer`.
+    fn from_lexer(lexer: Lexer, optimize: bool) -> Self {
         Parser {
-            lexer: Lexer::new(program),
+            lexer,
             parser_index: 0,
             should_optimize: optimize,
             syntax_tree: None,
@@ -45,11 +78,16 @@ impl Parser {
     /// The AST represented as a vector of `Expression`.
     pub fn generate_syntax_tree(&mut self) {
         let mut ast = self.parse_to_ast();
+        log::debug!(
+            "parsed {} tokens into {} nodes",
+            self.lexer.tokens().len(),
+            Parser::count_instructions(&ast)
+        );
         if self.should_optimize {
             Self::optimize_ast(&mut ast);
         }
+        self.num_of_instr = Parser::count_instructions(&ast);
         self.syntax_tree = Some(ast);
-        self.num_of_instr = Parser::count_instructions(self.get_ast());
     }
 
     /// Gets the number of instructions in the program.
@@ -70,6 +108,15 @@ impl Parser {
         self.syntax_tree.as_ref()
     }
 
+    /// Takes the abstract syntax tree (AST) out of the parser.
+    ///
+    /// # Returns
+    ///
+    /// The AST, or an empty tree if it has not been generated yet.
+    pub fn into_ast(self) -> Vec<Expression> {
+        self.syntax_tree.unwrap_or_default()
+    }
+
     /// Parses the tokens into an abstract syntax tree (AST).
     ///
     /// # Returns
@@ -82,14 +129,32 @@ impl Parser {
             let token = self.lexer.tokens()[self.parser_index];
             self.parser_index += 1;
 
-            expressions.push(match token {
-                Token::LoopStart => Expression::Loop(self.parse_to_ast()),
-                Token::LoopEnd => {
+            expressions.push(match token.token {
+                Token::LoopStart => {
+                    let body = self.parse_to_ast();
+                    // The matching ']' is the last token consumed by the nested call
+                    let loop_end = self.lexer.tokens()[self.parser_index - 1];
+                    Expression::Loop(Loop {
+                        body,
+                        span: token.span.merge(loop_end.span),
+                    })
+                }
+                Token::ProcStart => {
+                    let body = self.parse_to_ast();
+                    // The matching ')' is the last token consumed by the nested call
+                    let proc_end = self.lexer.tokens()[self.parser_index - 1];
+                    Expression::Procedure(Procedure {
+                        body,
+                        span: token.span.merge(proc_end.span),
+                    })
+                }
+                Token::LoopEnd | Token::ProcEnd => {
                     return expressions;
                 }
                 _ => Expression::Operator(Box::new(Operator {
-                    type_name: token,
+                    type_name: token.token,
                     count: 1,
+                    span: token.span,
                 })),
             });
         }
@@ -97,12 +162,43 @@ impl Parser {
         expressions
     }
 
-    /// Optimizes the abstract syntax tree (AST) by removing redundant operations.
+    /// The optimization passes in the order they run, along with their names.
+    ///
+    /// `cancel-operators` runs again at the end because removing a loop can leave
+    /// operators that cancel out next to each other.
+    pub const PASSES: [(&'static str, Pass); 4] = [
+        ("fold-operators", Self::fold_operators),
+        ("cancel-operators", Self::cancel_operators),
+        ("remove-dead-loops", Self::remove_dead_loops),
+        ("cancel-operators", Self::cancel_operators),
+    ];
+
+    /// The optimization passes that stay correct when the program starts on a tape
+    /// that is not zeroed, such as a line run in the REPL after earlier ones.
+    ///
+    /// `remove-dead-loops` is left out because it assumes every cell starts at zero.
+    pub const RESUMABLE_PASSES: [(&'static str, Pass); 2] = [
+        ("fold-operators", Self::fold_operators),
+        ("cancel-operators", Self::cancel_operators),
+    ];
+
+    /// Optimizes the abstract syntax tree (AST) by running every optimization pass.
     ///
     /// # Arguments
     ///
     /// * `ast` - A mutable reference to the AST.
-    fn optimize_ast(ast: &mut Vec<Expression>) {
+    pub fn optimize_ast(ast: &mut Vec<Expression>) {
+        for (_, pass) in Self::PASSES {
+            pass(ast);
+        }
+    }
+
+    /// Groups runs of the same operator into a single operator with a count.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - A mutable reference to the AST.
+    pub fn fold_operators(ast: &mut Vec<Expression>) {
         let mut prev: Option<&mut Operator> = None;
 
         // The index of optimized out AST nodes to remove
@@ -112,23 +208,27 @@ impl Parser {
             match expression {
                 Expression::Loop(_loop) => {
                     // Optimize the expressions contained in the loop
-                    Self::optimize_ast(_loop);
+                    Self::fold_operators(&mut _loop.body);
+                    prev = None;
+                }
+                Expression::Procedure(procedure) => {
+                    Self::fold_operators(&mut procedure.body);
                     prev = None;
                 }
                 Expression::Operator(new_op) => {
                     match &mut prev {
                         Some(old_op) => {
-                            if new_op.type_name != Token::StdOut && new_op.type_name != Token::StdIn
-                            {
-                                // Groups non - Std(in/out) tokens
+                            if new_op.type_name.is_foldable() {
+                                // Groups non - Std(in/out) and non - debug tokens
                                 if old_op.type_name == new_op.type_name {
-                                    old_op.count += 1;
+                                    old_op.count += new_op.count;
+                                    old_op.span = old_op.span.merge(new_op.span);
                                     nodes_idx.push(idx);
                                     continue;
                                 }
                             }
 
-                            // Replace the prev operation if the new one differs or is STD(IN/OUT)
+                            // Replace the prev operation if the new one differs or is STD(IN/OUT) or debug
                             prev = Some(new_op);
                         }
                         None => {
@@ -145,6 +245,125 @@ impl Parser {
         })
     }
 
+    /// Combines neighbouring operators that act in opposite directions, such as `+-`
+    /// or `<>`, into the single operator with the same effect, dropping it entirely
+    /// when they cancel out. Changes to a cell are taken modulo the cell size.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - A mutable reference to the AST.
+    pub fn cancel_operators(ast: &mut Vec<Expression>) {
+        let mut optimized: Vec<Expression> = Vec::with_capacity(ast.len());
+
+        for expression in ast.drain(..) {
+            match expression {
+                Expression::Loop(mut _loop) => {
+                    Self::cancel_operators(&mut _loop.body);
+                    optimized.push(Expression::Loop(_loop));
+                }
+                Expression::Procedure(mut procedure) => {
+                    Self::cancel_operators(&mut procedure.body);
+                    optimized.push(Expression::Procedure(procedure));
+                }
+                Expression::Operator(new_op) => {
+                    let Some((axis, mut net)) = Self::signed_count(&new_op) else {
+                        optimized.push(Expression::Operator(new_op));
+                        continue;
+                    };
+                    let mut span = new_op.span;
+
+                    // Combine with the previous operator if it acts on the same thing
+                    if let Some(Expression::Operator(old_op)) = optimized.last() {
+                        if let Some((old_axis, old_net)) = Self::signed_count(old_op) {
+                            if old_axis == axis {
+                                net += old_net;
+                                span = old_op.span.merge(span);
+                                optimized.pop();
+                            }
+                        }
+                    }
+
+                    if let Some(operator) = Self::operator_from_signed(axis, net, span) {
+                        optimized.push(Expression::Operator(Box::new(operator)));
+                    }
+                }
+            }
+        }
+
+        *ast = optimized;
+    }
+
+    /// Removes loops that provably never run because the current cell is zero when they are reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - A mutable reference to the AST.
+    pub fn remove_dead_loops(ast: &mut Vec<Expression>) {
+        let dead: HashSet<Span> = PointerAnalysis::analyse(ast)
+            .loops()
+            .iter()
+            .filter(|info| info.entry == LoopEntry::Never)
+            .map(|info| info.span)
+            .collect();
+
+        Self::remove_loops(ast, &dead);
+    }
+
+    /// Removes every loop whose span is in `dead`.
+    fn remove_loops(ast: &mut Vec<Expression>, dead: &HashSet<Span>) {
+        ast.retain_mut(|expression| match expression {
+            Expression::Loop(_loop) => {
+                Self::remove_loops(&mut _loop.body, dead);
+                !dead.contains(&_loop.span)
+            }
+            Expression::Procedure(procedure) => {
+                Self::remove_loops(&mut procedure.body, dead);
+                true
+            }
+            Expression::Operator(_) => true,
+        });
+    }
+
+    /// Gets what an operator acts on, represented by the operator moving in the
+    /// positive direction, and by how much as a signed count.
+    fn signed_count(operator: &Operator) -> Option<(Token, i64)> {
+        let count = operator.count as i64;
+        match operator.type_name {
+            Token::Add => Some((Token::Add, count)),
+            Token::Sub => Some((Token::Add, -count)),
+            Token::MoveForward => Some((Token::MoveForward, count)),
+            Token::MoveBack => Some((Token::MoveForward, -count)),
+            _ => None,
+        }
+    }
+
+    /// Builds the shortest operator acting on `axis` by a signed count.
+    ///
+    /// # Returns
+    ///
+    /// The operator, or `None` if the count has no effect.
+    fn operator_from_signed(axis: Token, net: i64, span: Span) -> Option<Operator> {
+        let (type_name, count) = match axis {
+            Token::Add => {
+                // A cell wraps around, so take whichever direction is shorter
+                let net = net.rem_euclid(256);
+                if net <= 128 {
+                    (Token::Add, net)
+                } else {
+                    (Token::Sub, 256 - net)
+                }
+            }
+            _ if net < 0 => (Token::MoveBack, -net),
+            _ => (Token::MoveForward, net),
+        };
+
+        (count != 0).then_some(Operator {
+            type_name,
+            count: count as usize,
+            span,
+        })
+    }
+
     /// Counts the number of instructions in the AST.
     ///
     /// # Arguments
@@ -154,26 +373,156 @@ impl Parser {
     /// # Returns
     ///
     /// The number of instructions.
-    fn count_instructions(ast_tree: Option<&Vec<Expression>>) -> usize {
+    pub fn count_instructions(ast_tree: &[Expression]) -> usize {
         let mut count: usize = 0;
-        match ast_tree {
-            Some(tree) => {
-                for node in tree {
-                    match node {
-                        Expression::Loop(_loop) => {
-                            // + 1 is to count the loop itself
-                            count += Self::count_instructions(Some(_loop)) + 1;
-                        }
-                        Expression::Operator(_op) => {
-                            count += 1;
-                        }
-                    }
+        for node in ast_tree {
+            match node {
+                Expression::Loop(_loop) => {
+                    // + 1 is to count the loop itself
+                    count += Self::count_instructions(&_loop.body) + 1;
+                }
+                Expression::Procedure(procedure) => {
+                    count += Self::count_instructions(&procedure.body) + 1;
+                }
+                Expression::Operator(_op) => {
+                    count += 1;
                 }
-            }
-            None => {
-                eprintln!("{}", "Tree has not been generated yet".red());
             }
         }
         count
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minifier::Minifier;
+
+    /// Parses `program` without running any pass.
+    fn parse(program: &str) -> Vec<Expression> {
+        let mut parser = Parser::new(program.to_string(), false, LexerOptions::default());
+        parser.generate_syntax_tree();
+        parser.into_ast()
+    }
+
+    /// Runs `pass` over the parsed `program` and writes the result back out.
+    fn run_pass(program: &str, pass: Pass) -> String {
+        let mut ast = parse(program);
+        pass(&mut ast);
+        Minifier::minify(&ast)
+    }
+
+    #[test]
+    fn fold_operators_groups_runs() {
+        let mut ast = parse("+++>>..");
+        Parser::fold_operators(&mut ast);
+        let counts: Vec<(Token, usize)> = ast
+            .iter()
+            .map(|expression| match expression {
+                Expression::Operator(operator) => (operator.type_name, operator.count),
+                _ => panic!("expected an operator"),
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (Token::Add, 3),
+                (Token::MoveForward, 2),
+                (Token::StdOut, 1),
+                (Token::StdOut, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn loops_span_from_open_to_close_bracket() {
+        let ast = parse("+\n [-\n>]");
+        let Expression::Loop(_loop) = &ast[1] else {
+            panic!("expected a loop");
+        };
+        assert_eq!(
+            _loop.span,
+            Span {
+                line: 2,
+                col: 2,
+                byte_offset: 3,
+                len: 5
+            }
+        );
+        assert_eq!(_loop.body[1].span().line, 3);
+        assert_eq!(_loop.body[1].span().col, 1);
+    }
+
+    #[test]
+    fn folded_run_spans_every_command_in_it() {
+        let mut ast = parse("++ +\n+>");
+        Parser::fold_operators(&mut ast);
+        assert_eq!(
+            ast[0].span(),
+            Span {
+                line: 1,
+                col: 1,
+                byte_offset: 0,
+                len: 6
+            }
+        );
+        assert_eq!(
+            ast[1].span(),
+            Span {
+                line: 2,
+                col: 2,
+                byte_offset: 6,
+                len: 1
+            }
+        );
+    }
+
+    #[test]
+    fn cancel_operators_combines_opposites() {
+        assert_eq!(run_pass("++-", Parser::cancel_operators), "+");
+        assert_eq!(run_pass("><", Parser::cancel_operators), "");
+        assert_eq!(run_pass("<<>", Parser::cancel_operators), "<");
+        assert_eq!(run_pass("[+-]", Parser::cancel_operators), "[]");
+    }
+
+    #[test]
+    fn cancel_operators_keeps_different_axes() {
+        assert_eq!(run_pass("+>-<", Parser::cancel_operators), "+>-<");
+        assert_eq!(run_pass("+.-", Parser::cancel_operators), "+.-");
+    }
+
+    #[test]
+    fn cancel_operators_wraps_cell_changes() {
+        let program = "+".repeat(200);
+        assert_eq!(run_pass(&program, Parser::cancel_operators), "-".repeat(56));
+        let program = "+".repeat(256);
+        assert_eq!(run_pass(&program, Parser::cancel_operators), "");
+    }
+
+    #[test]
+    fn remove_dead_loops_removes_loop_at_start() {
+        assert_eq!(run_pass("[-]+.", Parser::remove_dead_loops), "+.");
+    }
+
+    #[test]
+    fn remove_dead_loops_removes_loop_after_loop() {
+        assert_eq!(run_pass("+[-][>+<]", Parser::remove_dead_loops), "+[-]");
+    }
+
+    #[test]
+    fn remove_dead_loops_keeps_loops_that_may_run() {
+        assert_eq!(run_pass("+[-]", Parser::remove_dead_loops), "+[-]");
+        assert_eq!(run_pass(",[-]", Parser::remove_dead_loops), ",[-]");
+        assert_eq!(
+            run_pass("+[>[-]<-]", Parser::remove_dead_loops),
+            "+[>[-]<-]"
+        );
+    }
+
+    #[test]
+    fn every_pass_runs_in_order() {
+        let mut ast = parse("[-]+><+-[-]");
+        Parser::optimize_ast(&mut ast);
+        assert_eq!(Minifier::minify(&ast), "+[-]");
+    }
+}
@@ -0,0 +1,267 @@
+use crate::grammar::Expression;
+use crate::lexer::{LexError, LexerOptions};
+use crate::parser::{Parser, Pass};
+
+/// How much a single optimization pass shrank the syntax tree.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PassReport {
+    /// Name of the pass
+    pub name: &'static str,
+    /// Number of nodes before the pass ran
+    pub nodes_before: usize,
+    /// Number of nodes after the pass ran
+    pub nodes_after: usize,
+}
+
+/// Parses a program once and runs the optimization passes over a copy of the tree,
+/// keeping both trees and a record of what every pass did.
+#[derive(Debug)]
+pub struct Pipeline {
+    /// The tree as parsed, with one node per source command
+    unoptimised: Vec<Expression>,
+    /// The tree after every pass, or `None` if optimization is disabled
+    optimised: Option<Vec<Expression>>,
+    /// What each pass did, in the order they ran
+    passes: Vec<PassReport>,
+}
+
+impl Pipeline {
+    /// Parses the program and, if requested, optimizes it.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program source.
+    /// * `optimize` - Whether to run the optimization passes.
+    /// * `lexer_options` - Options passed on to the lexer.
+    ///
+    /// # Returns
+    ///
+    /// The compiled program.
+    pub fn compile(program: String, optimize: bool, lexer_options: LexerOptions) -> Self {
+        Self::from_parser(
+            Parser::new(program, false, lexer_options),
+            optimize,
+            &Parser::PASSES,
+        )
+    }
+
+    /// Parses the program and, if requested, optimizes it, returning an error instead
+    /// of panicking if the program cannot be tokenized.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program source.
+    /// * `optimize` - Whether to run the optimization passes.
+    /// * `lexer_options` - Options passed on to the lexer.
+    ///
+    /// # Returns
+    ///
+    /// The compiled program, or the error found by the lexer.
+    pub fn try_compile(
+        program: &str,
+        optimize: bool,
+        lexer_options: LexerOptions,
+    ) -> Result<Self, LexError> {
+        let parser = Parser::try_new(program, false, lexer_options)?;
+        Ok(Self::from_parser(parser, optimize, &Parser::PASSES))
+    }
+
+    /// Parses a program that runs on the tape left by earlier ones and, if requested,
+    /// optimizes it with only the passes that do not assume the tape starts zeroed,
+    /// returning an error instead of panicking if the program cannot be tokenized.
+    ///
+    /// # Arguments
+    ///
+    /// * `program` - The program source.
+    /// * `optimize` - Whether to run the optimization passes.
+    /// * `lexer_options` - Options passed on to the lexer.
+    ///
+    /// # Returns
+    ///
+    /// The compiled program, or the error found by the lexer.
+    pub fn try_compile_resumed(
+        program: &str,
+        optimize: bool,
+        lexer_options: LexerOptions,
+    ) -> Result<Self, LexError> {
+        let parser = Parser::try_new(program, false, lexer_options)?;
+        Ok(Self::from_parser(
+            parser,
+            optimize,
+            &Parser::RESUMABLE_PASSES,
+        ))
+    }
+
+    /// Generates the tree with an unoptimizing `parser` and runs the passes over a copy.
+    fn from_parser(mut parser: Parser, optimize: bool, passes: &[(&'static str, Pass)]) -> Self {
+        parser.generate_syntax_tree();
+
+        let mut pipeline = Pipeline {
+            unoptimised: parser.into_ast(),
+            optimised: None,
+            passes: vec![],
+        };
+
+        if optimize {
+            let mut ast = pipeline.unoptimised.clone();
+            for &(name, pass) in passes {
+                let nodes_before = Parser::count_instructions(&ast);
+                pass(&mut ast);
+                let report = PassReport {
+                    name,
+                    nodes_before,
+                    nodes_after: Parser::count_instructions(&ast),
+                };
+                log::debug!(
+                    "pass {}: {} -> {} nodes",
+                    name,
+                    report.nodes_before,
+                    report.nodes_after
+                );
+                pipeline.passes.push(report);
+            }
+            pipeline.optimised = Some(ast);
+        }
+
+        log::info!(
+            "compiled {} nodes{}",
+            Parser::count_instructions(&pipeline.unoptimised),
+            match &pipeline.optimised {
+                Some(ast) => format!(", {} after optimization", Parser::count_instructions(ast)),
+                None => String::from(" without optimization"),
+            }
+        );
+
+        pipeline
+    }
+
+    /// Gets the tree to execute, which is the optimized one if optimization is enabled.
+    pub fn ast(&self) -> &[Expression] {
+        self.optimised.as_deref().unwrap_or(&self.unoptimised)
+    }
+
+    /// Gets the tree as it was parsed, before any pass ran.
+    pub fn unoptimised_ast(&self) -> &[Expression] {
+        &self.unoptimised
+    }
+
+    /// Takes the tree to execute out of the pipeline.
+    pub fn into_ast(self) -> Vec<Expression> {
+        self.optimised.unwrap_or(self.unoptimised)
+    }
+
+    /// Gets what each optimization pass did, in the order they ran.
+    pub fn passes(&self) -> &[PassReport] {
+        &self.passes
+    }
+
+    /// Renders a table of the node counts before and after optimization and what each pass removed.
+    pub fn report(&self) -> String {
+        let before = Parser::count_instructions(&self.unoptimised);
+        let after = Parser::count_instructions(self.ast());
+
+        let mut report = format!(
+            "| Compilation:\n| Nodes parsed    : {}\n| Nodes optimised : {} ({:.2}% of parsed)\n",
+            before,
+            after,
+            Self::percentage(after, before)
+        );
+        for pass in &self.passes {
+            report.push_str(&format!(
+                "|   {:<18} {:>8} -> {:<8} (-{})\n",
+                pass.name,
+                pass.nodes_before,
+                pass.nodes_after,
+                pass.nodes_before - pass.nodes_after
+            ));
+        }
+        report.pop();
+        report
+    }
+
+    /// Renders the node counts and passes as a JSON object on a single line.
+    pub fn to_json(&self) -> String {
+        let passes: Vec<String> = self
+            .passes
+            .iter()
+            .map(|pass| {
+                format!(
+                    r#"{{"name": "{}", "nodes_before": {}, "nodes_after": {}}}"#,
+                    pass.name, pass.nodes_before, pass.nodes_after
+                )
+            })
+            .collect();
+
+        format!(
+            r#"{{"nodes_parsed": {}, "nodes_optimised": {}, "passes": [{}]}}"#,
+            Parser::count_instructions(&self.unoptimised),
+            Parser::count_instructions(self.ast()),
+            passes.join(", ")
+        )
+    }
+
+    fn percentage(part: usize, whole: usize) -> f64 {
+        if whole == 0 {
+            return 100.0;
+        }
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_removes_loop_at_start() {
+        let pipeline = Pipeline::compile(String::from("[-]+"), true, LexerOptions::default());
+        assert_eq!(pipeline.ast().len(), 1);
+    }
+
+    #[test]
+    fn resumed_compile_keeps_loop_at_start() {
+        let pipeline =
+            Pipeline::try_compile_resumed("[-]+", true, LexerOptions::default()).unwrap();
+        assert!(matches!(pipeline.ast()[0], Expression::Loop(_)));
+        assert!(pipeline
+            .passes()
+            .iter()
+            .all(|pass| pass.name != "remove-dead-loops"));
+    }
+
+    /// A dead loop, a run that folds and cancels, and moves that cancel.
+    const PROGRAM: &str = "[-]+++->><.";
+
+    #[test]
+    fn report_lists_what_each_pass_removed() {
+        let pipeline = Pipeline::compile(String::from(PROGRAM), true, LexerOptions::default());
+        assert_eq!(
+            pipeline.report(),
+            "| Compilation:
+| Nodes parsed    : 10
+| Nodes optimised : 3 (30.00% of parsed)
+|   fold-operators           10 -> 7        (-3)
+|   cancel-operators          7 -> 5        (-2)
+|   remove-dead-loops         5 -> 3        (-2)
+|   cancel-operators          3 -> 3        (-0)"
+        );
+    }
+
+    #[test]
+    fn json_lists_node_counts_of_each_pass() {
+        let pipeline = Pipeline::compile(String::from(PROGRAM), true, LexerOptions::default());
+        assert_eq!(
+            pipeline.to_json(),
+            r#"{"nodes_parsed": 10, "nodes_optimised": 3, "passes": [{"name": "fold-operators", "nodes_before": 10, "nodes_after": 7}, {"name": "cancel-operators", "nodes_before": 7, "nodes_after": 5}, {"name": "remove-dead-loops", "nodes_before": 5, "nodes_after": 3}, {"name": "cancel-operators", "nodes_before": 3, "nodes_after": 3}]}"#
+        );
+    }
+
+    #[test]
+    fn unoptimised_report_has_no_passes() {
+        let pipeline = Pipeline::compile(String::from(PROGRAM), false, LexerOptions::default());
+        assert!(pipeline.passes().is_empty());
+        assert!(pipeline
+            .report()
+            .ends_with("| Nodes optimised : 10 (100.00% of parsed)"));
+    }
+}
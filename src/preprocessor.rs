@@ -0,0 +1,1000 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::grammar::Span;
+
+/// Extension, without the leading dot, of programs that are always preprocessed.
+pub const EXTENSION: &str = "bfm";
+
+/// Errors found while expanding the macros of a program.
+///
+/// Every span points into the program itself, at the directive or call that failed,
+/// or at the call or include it was expanded from when it failed inside one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroError {
+    /// `%name` was used without a `#define` or `#const` of that name before it
+    UnknownMacro { name: String, span: Span },
+    /// A macro was called with a different number of arguments than it has parameters
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// The program ended inside the arguments of the call starting at `span`
+    UnclosedArguments { span: Span },
+    /// The program ended inside the repetition starting at `span`
+    UnclosedRepetition { span: Span },
+    /// The repetition at `span` has no `*` followed by a number or a constant
+    InvalidCount { span: Span },
+    /// The `#define`, `#const` or `#include` at `span` could not be read
+    InvalidDirective { span: Span },
+    /// The value given to the constant `name` is not a number
+    InvalidConstant { name: String, span: Span },
+    /// The file included at `span` could not be read
+    Include {
+        path: String,
+        reason: String,
+        span: Span,
+    },
+    /// The file included at `span` is already being included
+    IncludeCycle { path: String, span: Span },
+    /// Macros and includes were nested deeper than the preprocessor allows
+    TooDeep { span: Span },
+    /// The expanded program grew larger than the preprocessor allows
+    TooLarge { span: Span },
+}
+
+impl MacroError {
+    /// Gets the location in the program the error was found at.
+    pub fn span(&self) -> Span {
+        match self {
+            MacroError::UnknownMacro { span, .. }
+            | MacroError::ArgumentCount { span, .. }
+            | MacroError::UnclosedArguments { span }
+            | MacroError::UnclosedRepetition { span }
+            | MacroError::InvalidCount { span }
+            | MacroError::InvalidDirective { span }
+            | MacroError::InvalidConstant { span, .. }
+            | MacroError::Include { span, .. }
+            | MacroError::IncludeCycle { span, .. }
+            | MacroError::TooDeep { span }
+            | MacroError::TooLarge { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacroError::UnknownMacro { name, .. } => write!(f, "Unknown macro '{}'", name),
+            MacroError::ArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Macro '{}' takes {} argument(s) but {} were given",
+                name, expected, found
+            ),
+            MacroError::UnclosedArguments { .. } => write!(f, "Macro arguments are never closed"),
+            MacroError::UnclosedRepetition { .. } => write!(f, "Repetition is never closed"),
+            MacroError::InvalidCount { .. } => {
+                write!(f, "Repetition needs a number or a constant after '*'")
+            }
+            MacroError::InvalidDirective { .. } => write!(f, "Invalid directive"),
+            MacroError::InvalidConstant { name, .. } => {
+                write!(f, "Constant '{}' is not a number", name)
+            }
+            MacroError::Include { path, reason, .. } => {
+                write!(f, "Couldn't include {}: {}", path, reason)
+            }
+            MacroError::IncludeCycle { path, .. } => write!(f, "{} includes itself", path),
+            MacroError::TooDeep { .. } => write!(
+                f,
+                "Macros and includes are nested more than {} deep, one may expand to itself",
+                Preprocessor::MAX_DEPTH
+            ),
+            MacroError::TooLarge { .. } => write!(
+                f,
+                "Expanded program is larger than {} bytes",
+                Preprocessor::MAX_EXPANSION_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MacroError {}
+
+/// Where a piece of the expanded program came from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Origin {
+    /// Copied from the program, starting at this byte offset
+    Source(usize),
+    /// Produced by the macro call or include at this location in the program
+    Expansion(Span),
+}
+
+impl Origin {
+    /// Gets the origin of the text `offset` bytes further on.
+    fn advance(self, offset: usize) -> Origin {
+        match self {
+            Origin::Source(start) => Origin::Source(start + offset),
+            Origin::Expansion(_) => self,
+        }
+    }
+}
+
+/// A run of the expanded program that came from the same place.
+#[derive(Debug, Copy, Clone)]
+struct Segment {
+    /// Byte offset of the start of the run in the expanded program
+    start: usize,
+    /// Where the start of the run came from
+    origin: Origin,
+}
+
+/// Maps locations in an expanded program back to the program that was written.
+///
+/// Code copied from the program maps to where it was written, even when a
+/// repetition copies it many times, and code produced by a macro or an include maps
+/// to the call or `#include` it came from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The program as written
+    source: String,
+    /// Byte offset of the start of every line of the program
+    line_starts: Vec<usize>,
+    /// The runs of the expanded program, in order
+    segments: Vec<Segment>,
+}
+
+impl SourceMap {
+    /// Creates a map for `source` that has no expanded program yet.
+    fn new(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        SourceMap {
+            source: source.to_string(),
+            line_starts,
+            segments: vec![],
+        }
+    }
+
+    /// Finds where a part of the expanded program came from.
+    ///
+    /// # Arguments
+    ///
+    /// * `span` - Location in the expanded program.
+    ///
+    /// # Returns
+    ///
+    /// The location in the program as written.
+    pub fn lookup(&self, span: Span) -> Span {
+        let first = self.locate(span.byte_offset);
+        let last = self.locate(span.byte_offset + span.len.max(1) - 1);
+        first.merge(last)
+    }
+
+    /// Finds where a single byte of the expanded program came from.
+    fn locate(&self, offset: usize) -> Span {
+        let idx = self
+            .segments
+            .partition_point(|segment| segment.start <= offset);
+        match idx.checked_sub(1).map(|idx| self.segments[idx]) {
+            Some(Segment {
+                start,
+                origin: Origin::Source(source_start),
+            }) => {
+                // The last byte of a span can be in the middle of a character
+                let mut offset = source_start + offset - start;
+                while !self.source.is_char_boundary(offset) {
+                    offset -= 1;
+                }
+                let len = self.source[offset..]
+                    .chars()
+                    .next()
+                    .map_or(1, char::len_utf8);
+                self.span(offset, len)
+            }
+            Some(Segment {
+                origin: Origin::Expansion(span),
+                ..
+            }) => span,
+            None => Span::default(),
+        }
+    }
+
+    /// Creates the span of `len` bytes of the program starting at `offset`.
+    fn span(&self, offset: usize, len: usize) -> Span {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        Span {
+            line,
+            col: self.source[line_start..offset].chars().count() + 1,
+            byte_offset: offset,
+            len,
+        }
+    }
+}
+
+/// A program with every macro, repetition and include expanded.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    /// The expanded program, ready to be tokenized
+    pub text: String,
+    /// Where each part of `text` came from
+    pub source_map: SourceMap,
+}
+
+/// Something that `%name` can expand to.
+#[derive(Debug, Clone)]
+enum Definition {
+    /// A number given by `#const`
+    Constant(usize),
+    /// Code given by `#define`, with the names of its parameters
+    Macro { params: Vec<String>, body: String },
+}
+
+/// The expanded program as it is being written.
+#[derive(Debug, Default)]
+struct Output {
+    text: String,
+    segments: Vec<Segment>,
+}
+
+impl Output {
+    /// Appends text, recording where it came from.
+    fn push_str(&mut self, text: &str, origin: Origin) {
+        if text.is_empty() {
+            return;
+        }
+
+        let continues = match (self.segments.last(), origin) {
+            (
+                Some(Segment {
+                    start,
+                    origin: Origin::Source(last),
+                }),
+                Origin::Source(offset),
+            ) => last + (self.text.len() - start) == offset,
+            (Some(segment), Origin::Expansion(_)) => segment.origin == origin,
+            _ => false,
+        };
+        if !continues {
+            self.segments.push(Segment {
+                start: self.text.len(),
+                origin,
+            });
+        }
+        self.text.push_str(text);
+    }
+}
+
+/// Expands the macros of a program before it is tokenized.
+///
+/// Directives take up a whole line and are removed from the program:
+///
+/// * `#const NAME 10` defines a constant.
+/// * `#define NAME body` and `#define NAME(a, b) body` define a macro, whose body
+///   continues onto the next line when a line ends with `\`.
+/// * `#include "path"` expands another file in place, with its path relative to
+///   the file including it.
+///
+/// Anywhere else `%NAME` expands a constant or a macro without parameters,
+/// `%NAME(x; y)` calls a macro with its arguments separated by `;`, since `,` is a
+/// command, and inside a body `%a` expands the argument given for the parameter `a`.
+/// `{+>*10}` repeats the code before the `*` as many times as the number or
+/// `%NAME` constant after it.
+#[derive(Debug)]
+pub struct Preprocessor {
+    /// Directory the `#include` paths of the program are relative to
+    include_dir: PathBuf,
+    /// Every constant and macro defined so far, by name
+    definitions: HashMap<String, Definition>,
+    /// Canonical paths of the files being included, innermost last
+    includes: Vec<PathBuf>,
+    /// Where the expanded program came from
+    source_map: SourceMap,
+}
+
+impl Preprocessor {
+    /// Deepest nesting of macro calls and includes, which stops a macro that
+    /// expands to itself
+    const MAX_DEPTH: usize = 64;
+    /// Largest expanded program, which stops nested repetitions from exhausting memory
+    const MAX_EXPANSION_LEN: usize = 1 << 24;
+
+    /// Creates a preprocessor with no macros defined.
+    ///
+    /// # Arguments
+    ///
+    /// * `include_dir` - Directory the `#include` paths of the program are relative to.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Preprocessor`.
+    pub fn new(include_dir: &Path) -> Self {
+        Preprocessor {
+            include_dir: include_dir.to_path_buf(),
+            definitions: HashMap::new(),
+            includes: vec![],
+            source_map: SourceMap::default(),
+        }
+    }
+
+    /// Expands every directive, macro call and repetition in a program.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The program as written.
+    ///
+    /// # Returns
+    ///
+    /// The expanded program with its source map, or the first error found.
+    pub fn expand(mut self, source: &str) -> Result<Expansion, MacroError> {
+        self.source_map = SourceMap::new(source);
+        let mut output = Output::default();
+        let include_dir = self.include_dir.clone();
+        self.expand_file(source, Origin::Source(0), &include_dir, 0, &mut output)?;
+
+        log::debug!(
+            "expanded {} bytes of source into {} bytes with {} macros",
+            source.len(),
+            output.text.len(),
+            self.definitions.len()
+        );
+        self.source_map.segments = output.segments;
+        Ok(Expansion {
+            text: output.text,
+            source_map: self.source_map,
+        })
+    }
+
+    /// Expands a file, running its directives and expanding the rest of its lines.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - Contents of the file.
+    /// * `origin` - Where the start of the file came from.
+    /// * `dir` - Directory the `#include` paths of the file are relative to.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    fn expand_file(
+        &mut self,
+        text: &str,
+        origin: Origin,
+        dir: &Path,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<(), MacroError> {
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let mut offset = 0;
+        let mut idx = 0;
+        while idx < lines.len() {
+            let line_origin = origin.advance(offset);
+            let start = offset;
+            offset += lines[idx].len();
+            if Self::directive(lines[idx]).is_none() {
+                self.expand_text(lines[idx], line_origin, None, depth, output)?;
+                idx += 1;
+                continue;
+            }
+
+            // A line ending with `\` continues the directive onto the next one
+            let mut directive = String::from(lines[idx].trim_end());
+            idx += 1;
+            while directive.ends_with('\\') && idx < lines.len() {
+                directive.pop();
+                directive.push('\n');
+                directive.push_str(lines[idx].trim_end());
+                offset += lines[idx].len();
+                idx += 1;
+            }
+            let span = self.locate(line_origin, text[start..offset].trim_end().len());
+            self.run_directive(&directive, span, origin, dir, depth, output)?;
+        }
+        Ok(())
+    }
+
+    /// Splits a line into the name of its directive and the rest of it.
+    ///
+    /// # Returns
+    ///
+    /// The name and arguments, or `None` if the line is not a directive.
+    fn directive(line: &str) -> Option<(&str, &str)> {
+        let line = line.trim().strip_prefix('#')?;
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        matches!(name, "define" | "const" | "include").then_some((name, rest.trim()))
+    }
+
+    /// Runs a `#define`, `#const` or `#include` directive.
+    ///
+    /// # Arguments
+    ///
+    /// * `directive` - The directive, with any continued lines joined.
+    /// * `span` - Location of the directive.
+    /// * `origin` - Where the file containing the directive came from.
+    /// * `dir` - Directory the `#include` paths of the file are relative to.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    fn run_directive(
+        &mut self,
+        directive: &str,
+        span: Span,
+        origin: Origin,
+        dir: &Path,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<(), MacroError> {
+        let invalid = MacroError::InvalidDirective { span };
+        let Some((name, rest)) = Self::directive(directive) else {
+            return Err(invalid);
+        };
+
+        match name {
+            "const" => {
+                let (constant, value) = rest
+                    .split_once(char::is_whitespace)
+                    .filter(|(constant, _)| Self::is_identifier(constant))
+                    .ok_or(invalid)?;
+                let value = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| MacroError::InvalidConstant {
+                        name: constant.to_string(),
+                        span,
+                    })?;
+                self.definitions
+                    .insert(constant.to_string(), Definition::Constant(value));
+            }
+            "define" => {
+                let name_len = Self::identifier_len(rest);
+                let (name, mut body) = rest.split_at(name_len);
+                if name.is_empty() {
+                    return Err(invalid);
+                }
+                let mut params = vec![];
+                if let Some(list) = body.strip_prefix('(') {
+                    let (list, after) = list.split_once(')').ok_or(invalid.clone())?;
+                    if !list.trim().is_empty() {
+                        for param in list.split(',').map(str::trim) {
+                            if !Self::is_identifier(param) {
+                                return Err(invalid);
+                            }
+                            params.push(param.to_string());
+                        }
+                    }
+                    body = after;
+                }
+                self.definitions.insert(
+                    name.to_string(),
+                    Definition::Macro {
+                        params,
+                        body: body.trim().to_string(),
+                    },
+                );
+            }
+            _ => {
+                let path = rest
+                    .strip_prefix('"')
+                    .and_then(|rest| rest.strip_suffix('"'))
+                    .ok_or(invalid)?;
+                self.include(path, span, origin, dir, depth, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands the file included by an `#include` directive.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file, relative to `dir`.
+    /// * `span` - Location of the directive.
+    /// * `origin` - Where the file containing the directive came from.
+    /// * `dir` - Directory the `#include` paths of the file are relative to.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    fn include(
+        &mut self,
+        path: &str,
+        span: Span,
+        origin: Origin,
+        dir: &Path,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<(), MacroError> {
+        if depth >= Self::MAX_DEPTH {
+            return Err(MacroError::TooDeep { span });
+        }
+        let error = |reason: std::io::Error| MacroError::Include {
+            path: path.to_string(),
+            reason: reason.to_string(),
+            span,
+        };
+        let full_path = dir.join(path).canonicalize().map_err(error)?;
+        if self.includes.contains(&full_path) {
+            return Err(MacroError::IncludeCycle {
+                path: path.to_string(),
+                span,
+            });
+        }
+        let text = fs::read_to_string(&full_path).map_err(error)?;
+        log::debug!("including {}", full_path.display());
+
+        // Everything in an included file maps to the outermost `#include`
+        let origin = match origin {
+            Origin::Source(_) => Origin::Expansion(span),
+            Origin::Expansion(_) => origin,
+        };
+        let include_dir = full_path.parent().unwrap_or(dir).to_path_buf();
+        self.includes.push(full_path);
+        self.expand_file(&text, origin, &include_dir, depth + 1, output)?;
+        self.includes.pop();
+        Ok(())
+    }
+
+    /// Expands the macro calls, arguments and repetitions in a piece of code.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The code to expand.
+    /// * `origin` - Where the start of the code came from.
+    /// * `args` - The arguments of the macro whose body is being expanded, by parameter.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    fn expand_text(
+        &mut self,
+        text: &str,
+        origin: Origin,
+        args: Option<&HashMap<String, String>>,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<(), MacroError> {
+        let mut idx = 0;
+        while let Some(curr_char) = text[idx..].chars().next() {
+            let name_len = match curr_char {
+                '%' => Self::identifier_len(&text[idx + 1..]),
+                _ => 0,
+            };
+            match curr_char {
+                '%' if name_len > 0 => {
+                    idx = self.expand_call(text, idx, name_len, origin, args, depth, output)?;
+                }
+                '{' => idx = self.expand_repetition(text, idx, origin, args, depth, output)?,
+                _ => {
+                    let len = curr_char.len_utf8();
+                    output.push_str(&text[idx..idx + len], origin.advance(idx));
+                    idx += len;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands the `%NAME` or `%NAME(x; y)` starting at `start`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The code being expanded.
+    /// * `start` - Byte offset of the `%` in `text`.
+    /// * `name_len` - Length of the name after the `%`.
+    /// * `origin` - Where the start of `text` came from.
+    /// * `args` - The arguments of the macro whose body is being expanded, by parameter.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset in `text` just past the call.
+    #[allow(clippy::too_many_arguments)]
+    fn expand_call(
+        &mut self,
+        text: &str,
+        start: usize,
+        name_len: usize,
+        origin: Origin,
+        args: Option<&HashMap<String, String>>,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<usize, MacroError> {
+        let name_end = start + 1 + name_len;
+        let name = &text[start + 1..name_end];
+        let (call_args, end) = if text[name_end..].starts_with('(') {
+            let close = Self::find_close(text, name_end, '(', ')').ok_or_else(|| {
+                MacroError::UnclosedArguments {
+                    span: self.locate(origin.advance(start), name_end + 1 - start),
+                }
+            })?;
+            (Self::split_args(&text[name_end + 1..close]), close + 1)
+        } else {
+            (vec![], name_end)
+        };
+        let span = self.locate(origin.advance(start), end - start);
+        let call_origin = match origin {
+            Origin::Source(_) => Origin::Expansion(span),
+            Origin::Expansion(_) => origin,
+        };
+
+        // A parameter of the macro being expanded hides any macro with the same name
+        if let Some(value) = args.and_then(|args| args.get(name)) {
+            if end == name_end {
+                output.push_str(value, call_origin);
+                return Ok(end);
+            }
+        }
+
+        let argument_count = |expected: usize| MacroError::ArgumentCount {
+            name: name.to_string(),
+            expected,
+            found: call_args.len(),
+            span,
+        };
+        match self.definitions.get(name).cloned() {
+            Some(Definition::Constant(value)) => {
+                if !call_args.is_empty() {
+                    return Err(argument_count(0));
+                }
+                output.push_str(&value.to_string(), call_origin);
+            }
+            Some(Definition::Macro { params, body }) => {
+                if call_args.len() != params.len() {
+                    return Err(argument_count(params.len()));
+                }
+                if depth >= Self::MAX_DEPTH {
+                    return Err(MacroError::TooDeep { span });
+                }
+
+                // Arguments are expanded where the call is, before they are bound
+                let mut bound = HashMap::new();
+                for (param, (offset, arg)) in params.into_iter().zip(call_args) {
+                    let mut expanded = Output::default();
+                    let arg_origin = origin.advance(name_end + 1 + offset);
+                    self.expand_text(arg, arg_origin, args, depth, &mut expanded)?;
+                    bound.insert(param, expanded.text);
+                }
+                self.expand_text(&body, call_origin, Some(&bound), depth + 1, output)?;
+            }
+            None => {
+                return Err(MacroError::UnknownMacro {
+                    name: name.to_string(),
+                    span,
+                })
+            }
+        }
+        Ok(end)
+    }
+
+    /// Expands the `{code*count}` starting at `start`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The code being expanded.
+    /// * `start` - Byte offset of the `{` in `text`.
+    /// * `origin` - Where the start of `text` came from.
+    /// * `args` - The arguments of the macro whose body is being expanded, by parameter.
+    /// * `depth` - Number of macro calls and includes being expanded.
+    /// * `output` - The expanded program to append to.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset in `text` just past the repetition.
+    fn expand_repetition(
+        &mut self,
+        text: &str,
+        start: usize,
+        origin: Origin,
+        args: Option<&HashMap<String, String>>,
+        depth: usize,
+        output: &mut Output,
+    ) -> Result<usize, MacroError> {
+        let Some(close) = Self::find_close(text, start, '{', '}') else {
+            return Err(MacroError::UnclosedRepetition {
+                span: self.locate(origin.advance(start), 1),
+            });
+        };
+        let span = self.locate(origin.advance(start), close + 1 - start);
+        let inner = &text[start + 1..close];
+        let (body, count) = inner
+            .rsplit_once('*')
+            .ok_or(MacroError::InvalidCount { span })?;
+
+        let count = count.trim();
+        let value = match count.strip_prefix('%') {
+            Some(name) => match (
+                args.and_then(|args| args.get(name)),
+                self.definitions.get(name),
+            ) {
+                (Some(value), _) => value.trim().parse().ok(),
+                (None, Some(Definition::Constant(value))) => Some(*value),
+                _ => None,
+            },
+            None => count.parse().ok(),
+        };
+        let count: usize = value.ok_or(MacroError::InvalidCount { span })?;
+        if count > Self::MAX_EXPANSION_LEN {
+            return Err(MacroError::TooLarge { span });
+        }
+
+        for _ in 0..count {
+            self.expand_text(body, origin.advance(start + 1), args, depth, output)?;
+            if output.text.len() > Self::MAX_EXPANSION_LEN {
+                return Err(MacroError::TooLarge { span });
+            }
+        }
+        Ok(close + 1)
+    }
+
+    /// Finds the bracket closing the one at `open_idx`, skipping nested pairs.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset of the closing bracket, or `None` if it is never closed.
+    fn find_close(text: &str, open_idx: usize, open: char, close: char) -> Option<usize> {
+        let mut depth = 0;
+        for (idx, curr_char) in text[open_idx..].char_indices() {
+            if curr_char == open {
+                depth += 1;
+            } else if curr_char == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(open_idx + idx);
+                }
+            }
+        }
+        None
+    }
+
+    /// Splits the arguments of a call at every `;` that is not inside brackets.
+    ///
+    /// # Returns
+    ///
+    /// The byte offset and text of every argument, none if there is only whitespace.
+    fn split_args(list: &str) -> Vec<(usize, &str)> {
+        if list.trim().is_empty() {
+            return vec![];
+        }
+
+        let mut args = vec![];
+        let mut depth = 0;
+        let mut arg_start = 0;
+        for (idx, curr_char) in list.char_indices() {
+            match curr_char {
+                '(' | '{' => depth += 1,
+                ')' | '}' => depth -= 1,
+                ';' if depth == 0 => {
+                    args.push((arg_start, &list[arg_start..idx]));
+                    arg_start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        args.push((arg_start, &list[arg_start..]));
+        args
+    }
+
+    /// Gets the length of the name at the start of `text`, made of letters, digits
+    /// and `_` and not starting with a digit.
+    fn identifier_len(text: &str) -> usize {
+        if text.starts_with(|curr_char: char| curr_char.is_ascii_digit()) {
+            return 0;
+        }
+        text.find(|curr_char: char| !(curr_char.is_ascii_alphanumeric() || curr_char == '_'))
+            .unwrap_or(text.len())
+    }
+
+    /// Checks whether the whole of `text` is a name.
+    fn is_identifier(text: &str) -> bool {
+        !text.is_empty() && Self::identifier_len(text) == text.len()
+    }
+
+    /// Creates the span of `len` bytes starting where `origin` points.
+    fn locate(&self, origin: Origin, len: usize) -> Span {
+        match origin {
+            Origin::Source(offset) => self.source_map.span(offset, len),
+            Origin::Expansion(span) => span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands `source` with includes relative to `dir`, panicking on errors.
+    fn expand_in(dir: &Path, source: &str) -> Expansion {
+        Preprocessor::new(dir).expand(source).unwrap()
+    }
+
+    /// Expands `source`, panicking on errors.
+    fn expand(source: &str) -> Expansion {
+        expand_in(Path::new(""), source)
+    }
+
+    /// Expands `source`, panicking if it succeeds.
+    fn expand_err(source: &str) -> MacroError {
+        Preprocessor::new(Path::new("")).expand(source).unwrap_err()
+    }
+
+    /// Creates an empty directory for the include files of a test.
+    fn include_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "brainfk-preprocessor-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Creates the span of a single byte in the program.
+    fn span(line: usize, col: usize, byte_offset: usize, len: usize) -> Span {
+        Span {
+            line,
+            col,
+            byte_offset,
+            len,
+        }
+    }
+
+    #[test]
+    fn text_without_macros_is_unchanged() {
+        assert_eq!(expand("+[->+<] comment %").text, "+[->+<] comment %");
+    }
+
+    #[test]
+    fn repetition_repeats_body() {
+        assert_eq!(expand("{+*3}>").text, "+++>");
+        assert_eq!(expand("{{+*2}>*2}").text, "++>++>");
+        assert_eq!(expand("{-*0}.").text, ".");
+    }
+
+    #[test]
+    fn repetition_count_can_be_constant() {
+        assert_eq!(expand("#const N 3\n{-*%N}").text, "---");
+    }
+
+    #[test]
+    fn constant_expands_to_its_value() {
+        assert_eq!(expand("#const N 42\n%N").text, "42");
+    }
+
+    #[test]
+    fn macro_without_parameters() {
+        assert_eq!(expand("#define CLEAR [-]\n+%CLEAR>").text, "+[-]>");
+    }
+
+    #[test]
+    fn macro_with_parameters() {
+        let source = "#define MOVE(n, c) {>*%n}%c\n%MOVE(2; +)%MOVE(1;-)";
+        assert_eq!(expand(source).text, ">> +>-");
+    }
+
+    #[test]
+    fn arguments_are_expanded_at_the_call() {
+        let source = "#const A 2\n#define TWICE(x) %x%x\n#define ADD(n) %TWICE({+*%n})\n%ADD(%A)";
+        assert_eq!(expand(source).text, "++++");
+    }
+
+    #[test]
+    fn definition_continues_onto_next_line() {
+        let source = "#define PAIR \\\n  +>\n%PAIR%PAIR";
+        assert_eq!(expand(source).text, "+>+>");
+    }
+
+    #[test]
+    fn unknown_macro_points_at_call() {
+        assert_eq!(
+            expand_err("++\n %NOPE."),
+            MacroError::UnknownMacro {
+                name: String::from("NOPE"),
+                span: span(2, 2, 4, 5),
+            }
+        );
+    }
+
+    #[test]
+    fn wrong_argument_count() {
+        let error = expand_err("#define M(a, b) %a%b\n%M(+)");
+        assert!(matches!(
+            error,
+            MacroError::ArgumentCount {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn invalid_repetitions() {
+        assert!(matches!(
+            expand_err("{+*x}"),
+            MacroError::InvalidCount { .. }
+        ));
+        assert!(matches!(
+            expand_err("{+"),
+            MacroError::UnclosedRepetition { .. }
+        ));
+        assert!(matches!(
+            expand_err("#const N x"),
+            MacroError::InvalidConstant { .. }
+        ));
+    }
+
+    #[test]
+    fn recursive_macro_is_too_deep() {
+        assert!(matches!(
+            expand_err("#define LOOP +%LOOP\n%LOOP"),
+            MacroError::TooDeep { .. }
+        ));
+    }
+
+    #[test]
+    fn include_expands_file_and_its_definitions() {
+        let dir = include_dir("include");
+        fs::write(dir.join("lib.bfm"), "#define INC +\n>\n").unwrap();
+
+        let expansion = expand_in(&dir, "#include \"lib.bfm\"\n%INC.");
+        assert_eq!(expansion.text, ">\n+.");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycle_is_rejected() {
+        let dir = include_dir("cycle");
+        fs::write(dir.join("a.bfm"), "#include \"b.bfm\"\n").unwrap();
+        fs::write(dir.join("b.bfm"), "#include \"a.bfm\"\n").unwrap();
+
+        let error = Preprocessor::new(&dir)
+            .expand("+\n#include \"a.bfm\"\n")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MacroError::IncludeCycle {
+                path: String::from("a.bfm"),
+                span: span(2, 1, 2, 16),
+            }
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        assert!(matches!(
+            expand_err("#include \"missing.bfm\""),
+            MacroError::Include { .. }
+        ));
+    }
+
+    #[test]
+    fn source_map_points_at_written_code() {
+        let source = "#define M <<\n+{>*2}%M";
+        let expansion = expand(source);
+        assert_eq!(expansion.text, "+>><<");
+        let map = &expansion.source_map;
+
+        // Code copied from the program, even by a repetition, keeps its location
+        assert_eq!(map.lookup(span(1, 1, 0, 1)), span(2, 1, 13, 1));
+        assert_eq!(map.lookup(span(1, 2, 1, 1)), span(2, 3, 15, 1));
+        assert_eq!(map.lookup(span(1, 3, 2, 1)), span(2, 3, 15, 1));
+        // Code produced by a macro points at the call
+        assert_eq!(map.lookup(span(1, 4, 3, 1)), span(2, 7, 19, 2));
+        // A span covering both is merged
+        assert_eq!(map.lookup(span(1, 1, 0, 5)), span(2, 1, 13, 8));
+    }
+
+    #[test]
+    fn source_map_handles_multibyte_characters() {
+        let expansion = expand("é{+*2}");
+        let map = &expansion.source_map;
+        assert_eq!(map.lookup(span(1, 1, 0, 2)), span(1, 1, 0, 2));
+        assert_eq!(map.lookup(span(1, 2, 2, 1)), span(1, 3, 3, 1));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::grammar::{Loop, Operator, Span, Token};
+
+/// Execution counts collected for a single node of the syntax tree.
+#[derive(Debug, Clone)]
+pub struct NodeProfile {
+    /// The operator type, or `None` if the node is a loop
+    pub token: Option<Token>,
+    /// Location of the node in the source
+    pub span: Span,
+    /// Number of times the node was executed (entered, for loops)
+    pub hits: u64,
+    /// Number of source commands executed, counting each folded operator separately
+    pub commands: u64,
+    /// Number of times the body of a loop was run
+    pub iterations: u64,
+    /// Total time spent inside a loop, including nested loops
+    pub time: Duration,
+}
+
+/// Collects per node hit counts and loop timings while a program runs.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    /// Profiles keyed by the span of the node they belong to
+    nodes: HashMap<Span, NodeProfile>,
+}
+
+impl Profiler {
+    /// Number of source characters shown for each entry of the report
+    const SNIPPET_LEN: usize = 32;
+    /// Maximum number of entries in each section of the report
+    const REPORT_LEN: usize = 10;
+
+    /// Constructs a new, empty `Profiler`.
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Records a single execution of an operator.
+    ///
+    /// # Arguments
+    ///
+    /// * `operator` - The operator that was executed.
+    pub fn record_operator(&mut self, operator: &Operator) {
+        let node = self.node(operator.span, Some(operator.type_name));
+        node.hits += 1;
+        node.commands += operator.count as u64;
+    }
+
+    /// Records a single run of a loop, from reaching `[` to leaving it.
+    ///
+    /// # Arguments
+    ///
+    /// * `_loop` - The loop that was executed.
+    /// * `iterations` - How many times the loop body ran.
+    /// * `time` - How long the loop took, including nested loops.
+    pub fn record_loop(&mut self, _loop: &Loop, iterations: u64, time: Duration) {
+        let node = self.node(_loop.span, None);
+        node.hits += 1;
+        node.iterations += iterations;
+        // Every iteration plus the final exit test the condition on '['
+        node.commands += iterations + 1;
+        node.time += time;
+    }
+
+    /// Gets the loops that were executed, ordered by the time spent in them.
+    ///
+    /// # Returns
+    ///
+    /// The loop profiles, most expensive first.
+    pub fn hot_loops(&self) -> Vec<&NodeProfile> {
+        let mut loops: Vec<&NodeProfile> = self
+            .nodes
+            .values()
+            .filter(|node| node.token.is_none())
+            .collect();
+        loops.sort_by(|a, b| b.time.cmp(&a.time).then(b.iterations.cmp(&a.iterations)));
+        loops
+    }
+
+    /// Gets the operators that were executed, ordered by how often they ran.
+    ///
+    /// # Returns
+    ///
+    /// The operator profiles, most executed first.
+    pub fn hot_operators(&self) -> Vec<&NodeProfile> {
+        let mut operators: Vec<&NodeProfile> = self
+            .nodes
+            .values()
+            .filter(|node| node.token.is_some())
+            .collect();
+        operators.sort_by(|a, b| {
+            b.hits
+                .cmp(&a.hits)
+                .then(a.span.byte_offset.cmp(&b.span.byte_offset))
+        });
+        operators
+    }
+
+    /// Renders a ranked report of the hottest loops and operators.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The program the profile was collected for.
+    ///
+    /// # Returns
+    ///
+    /// The report as a printable string.
+    pub fn report(&self, source: &str) -> String {
+        let hot_loops = self.hot_loops();
+        let total_time: Duration = hot_loops
+            .iter()
+            .filter(|node| !self.is_nested(node))
+            .map(|node| node.time)
+            .sum();
+
+        let mut report = String::from("| Hot loops (inclusive time):\n");
+        report.push_str(&format!(
+            "| {:>3}  {:<10} {:>8} {:>12} {:>12} {:>7}  {}\n",
+            "#", "span", "entries", "iterations", "time", "%", "source"
+        ));
+        for (rank, node) in hot_loops.iter().take(Self::REPORT_LEN).enumerate() {
+            let percentage = if total_time.is_zero() {
+                0.0
+            } else {
+                node.time.as_secs_f64() / total_time.as_secs_f64() * 100.0
+            };
+            report.push_str(&format!(
+                "| {:>3}  {:<10} {:>8} {:>12} {:>12} {:>6.2}%  {}\n",
+                rank + 1,
+                node.span.to_string(),
+                node.hits,
+                node.iterations,
+                format!("{:.2?}", node.time),
+                percentage,
+                Self::snippet(source, node.span)
+            ));
+        }
+
+        report.push_str("|\n| Hot instructions:\n");
+        report.push_str(&format!(
+            "| {:>3}  {:<10} {:>5} {:>12} {:>12}  {}\n",
+            "#", "span", "op", "hits", "commands", "source"
+        ));
+        for (rank, node) in self
+            .hot_operators()
+            .iter()
+            .take(Self::REPORT_LEN)
+            .enumerate()
+        {
+            report.push_str(&format!(
+                "| {:>3}  {:<10} {:>5} {:>12} {:>12}  {}\n",
+                rank + 1,
+                node.span.to_string(),
+                node.token.map(|token| token as u8 as char).unwrap_or(' '),
+                node.hits,
+                node.commands,
+                Self::snippet(source, node.span)
+            ));
+        }
+
+        report
+    }
+
+    /// Annotates every line of the source with the number of commands it executed.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The program the profile was collected for.
+    ///
+    /// # Returns
+    ///
+    /// The annotated source as a printable string.
+    pub fn annotate(&self, source: &str) -> String {
+        let mut line_counts: HashMap<usize, u64> = HashMap::new();
+        for node in self.nodes.values() {
+            *line_counts.entry(node.span.line).or_insert(0) += node.commands;
+        }
+
+        source
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| match line_counts.get(&(idx + 1)) {
+                Some(count) => format!("{:>12} | {}", count, line),
+                None => format!("{:>12} | {}", "", line),
+            })
+            .map(|line| format!("{}\n", line.trim_end()))
+            .collect()
+    }
+
+    /// Gets the profile for a node, creating it if it has not been seen yet.
+    fn node(&mut self, span: Span, token: Option<Token>) -> &mut NodeProfile {
+        self.nodes.entry(span).or_insert_with(|| NodeProfile {
+            token,
+            span,
+            hits: 0,
+            commands: 0,
+            iterations: 0,
+            time: Duration::ZERO,
+        })
+    }
+
+    /// Checks whether a loop is contained in another profiled loop.
+    fn is_nested(&self, node: &NodeProfile) -> bool {
+        let start = node.span.byte_offset;
+        self.nodes.values().any(|other| {
+            other.token.is_none()
+                && other.span != node.span
+                && other.span.byte_offset <= start
+                && start < other.span.byte_offset + other.span.len
+        })
+    }
+
+    /// Extracts a single line excerpt of the source covered by a span.
+    fn snippet(source: &str, span: Span) -> String {
+        let text = source
+            .get(span.byte_offset..span.byte_offset + span.len)
+            .unwrap_or_default();
+        let collapsed: String = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+        if collapsed.chars().count() > Self::SNIPPET_LEN {
+            let truncated: String = collapsed.chars().take(Self::SNIPPET_LEN).collect();
+            format!("{}...", truncated)
+        } else {
+            collapsed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Expression;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::LexerOptions;
+    use crate::pipeline::Pipeline;
+
+    /// Two nested loops, the inner one running three times for each of the two outer iterations.
+    const NESTED_LOOPS: &str = "++\n[>+++\n[>+<-]<-]";
+
+    /// Parses `program` without optimizing it, so every command is its own node.
+    fn compile(program: &str) -> Vec<Expression> {
+        Pipeline::compile(String::from(program), false, LexerOptions::default()).into_ast()
+    }
+
+    /// Runs `ast` to the end with profiling enabled.
+    fn run_profiled(ast: &[Expression]) -> Interpreter<'_> {
+        let mut interpreter = Interpreter::new(ast);
+        interpreter.enable_profiling();
+        interpreter.run_code().unwrap();
+        interpreter
+    }
+
+    #[test]
+    fn counts_loop_entries_and_iterations() {
+        let ast = compile(NESTED_LOOPS);
+        let interpreter = run_profiled(&ast);
+        let mut loops: Vec<(usize, u64, u64)> = interpreter
+            .profiler()
+            .unwrap()
+            .hot_loops()
+            .iter()
+            .map(|node| (node.span.line, node.hits, node.iterations))
+            .collect();
+        loops.sort();
+        assert_eq!(loops, [(2, 1, 2), (3, 2, 6)]);
+    }
+
+    #[test]
+    fn hot_operators_are_ranked_by_hits() {
+        let ast = compile(NESTED_LOOPS);
+        let interpreter = run_profiled(&ast);
+        let hot: Vec<(Option<Token>, u64)> = interpreter
+            .profiler()
+            .unwrap()
+            .hot_operators()
+            .iter()
+            .take(5)
+            .map(|node| (node.token, node.hits))
+            .collect();
+        assert_eq!(
+            hot,
+            [
+                (Some(Token::MoveForward), 6),
+                (Some(Token::Add), 6),
+                (Some(Token::MoveBack), 6),
+                (Some(Token::Sub), 6),
+                (Some(Token::MoveForward), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_lists_loops_and_instructions() {
+        let ast = compile(NESTED_LOOPS);
+        let interpreter = run_profiled(&ast);
+        let report = interpreter.profiler().unwrap().report(NESTED_LOOPS);
+        assert!(
+            report.starts_with("| Hot loops (inclusive time):\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("[>+++ [>+<-]<-]"), "{}", report);
+        assert!(report.contains("| Hot instructions:\n"), "{}", report);
+        assert!(
+            report.contains("|   1  3:2            >            6            6  >"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn annotate_counts_commands_per_line() {
+        let ast = compile(NESTED_LOOPS);
+        let interpreter = run_profiled(&ast);
+        let annotated = interpreter.profiler().unwrap().annotate(NESTED_LOOPS);
+        assert_eq!(
+            annotated,
+            "           2 | ++\n          11 | [>+++\n          36 | [>+<-]<-]\n"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use brainfk::grammar::Expression;
+use brainfk::interpreter::Interpreter;
+use brainfk::lexer::{LexError, LexerOptions};
+use brainfk::pipeline::Pipeline;
+use colored::Colorize;
+
+use crate::cli::Args;
+
+/// Prompt shown when waiting for a new line of code.
+const PROMPT: &str = "bf> ";
+/// Prompt shown while a loop or block comment is still open.
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// Interactive session where every line is run against the same tape.
+pub struct Repl {
+    /// Runs each line, keeping the tape and pointer between them
+    interpreter: Interpreter<'static>,
+    /// Flag indicating whether to optimize each line before running it
+    should_optimise: bool,
+    /// Options controlling how each line is tokenized
+    lexer_options: LexerOptions,
+    /// Lines entered since the last complete program, joined by newlines
+    buffer: String,
+    /// Every expression run since the session started or was reset, kept so the
+    /// procedures defined by earlier lines can still be called
+    program: Vec<Expression>,
+}
+
+impl Repl {
+    /// Creates a session with an empty tape, using the options given on the command line.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The parsed command line.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `Repl`.
+    pub fn new(args: &Args) -> Self {
+        let mut interpreter = Interpreter::new(&[]);
+        interpreter.set_limits(args.limits);
+        interpreter.set_schedule(args.schedule);
+
+        Repl {
+            interpreter,
+            should_optimise: args.should_optimise,
+            lexer_options: args.lexer_options.clone(),
+            buffer: String::new(),
+            program: vec![],
+        }
+    }
+
+    /// Reads and runs lines from stdin until `:quit` or the end of input.
+    pub fn run(&mut self) {
+        println!("Brainfuck REPL, enter :help for the list of commands");
+
+        let stdin = io::stdin();
+        let mut line = String::new();
+        loop {
+            let prompt = if self.buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            print!("{}", prompt);
+            let _ = io::stdout().flush();
+
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) => {
+                    println!();
+                    break;
+                }
+                Ok(_) => {}
+                Err(why) => {
+                    eprintln!("{}: couldn't read input: {}", "Error".red(), why);
+                    break;
+                }
+            }
+
+            let line = line.trim_end_matches(['\n', '\r']);
+            // A `:` followed by a name is a command, otherwise it may be a pbrain call
+            let mut start = line.trim_start().chars();
+            let is_command =
+                start.next() == Some(':') && start.next().is_some_and(|c| c.is_ascii_alphabetic());
+            if self.buffer.is_empty() && is_command {
+                if !self.command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            self.buffer.push_str(line);
+            self.buffer.push('\n');
+            match Pipeline::try_compile_resumed(
+                &self.buffer,
+                self.should_optimise,
+                self.lexer_options.clone(),
+            ) {
+                // Keep reading until the loop, procedure or comment is closed
+                Err(
+                    LexError::UnclosedLoops { .. }
+                    | LexError::UnclosedProcedures { .. }
+                    | LexError::UnclosedBlockComment { .. },
+                ) => {}
+                Err(error) => {
+                    eprintln!("{}: {}", "Error".red(), error);
+                    self.buffer.clear();
+                }
+                Ok(pipeline) => {
+                    self.buffer.clear();
+                    self.execute(pipeline.into_ast());
+                }
+            }
+        }
+    }
+
+    /// Runs a REPL command.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The line entered, starting with `:`.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the session should end.
+    fn command(&mut self, command: &str) -> bool {
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+
+        match name {
+            ":tape" => println!("{}", self.interpreter.render_tape()),
+            ":ptr" => {
+                let cell_ptr = self.interpreter.cell_ptr();
+                println!(
+                    "cell_ptr={} value={}",
+                    cell_ptr,
+                    self.interpreter.tape()[cell_ptr]
+                );
+            }
+            ":reset" => {
+                self.interpreter.reset();
+                self.program.clear();
+                println!("Tape cleared");
+            }
+            ":load" if !argument.is_empty() => match fs::read_to_string(argument) {
+                Ok(program) => {
+                    match Pipeline::try_compile_resumed(
+                        &program,
+                        self.should_optimise,
+                        self.lexer_options.clone(),
+                    ) {
+                        Ok(pipeline) => self.execute(pipeline.into_ast()),
+                        Err(error) => eprintln!("{}: {}: {}", "Error".red(), argument, error),
+                    }
+                }
+                Err(why) => eprintln!("{}: couldn't read {}: {}", "Error".red(), argument, why),
+            },
+            ":quit" | ":q" => return false,
+            ":help" => println!(
+                r#"Enter Brainfuck code to run it on the current tape. Lines are joined
+until every loop is closed.
+
+Commands:
+    :tape        Show the cells around the pointer
+    :ptr         Show the pointer and the value of the current cell
+    :reset       Clear the tape, forget every procedure and move the pointer
+                 to the first cell
+    :load FILE   Run the program in FILE on the current tape
+    :quit, :q    Leave the REPL"#
+            ),
+            _ => eprintln!(
+                "{}: Unknown command '{}', enter :help for the list of commands",
+                "Error".red(),
+                command
+            ),
+        }
+        true
+    }
+
+    /// Runs a syntax tree on the current tape after the code run before it, printing
+    /// any error it stops with.
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax_tree` - The expressions to execute.
+    fn execute(&mut self, syntax_tree: Vec<Expression>) {
+        let output_bytes = self.interpreter.stats().output_bytes;
+        let start = self.program.len();
+        self.program.extend(syntax_tree);
+        let result = self.interpreter.run_expressions(&self.program, start);
+
+        // Start the next prompt on its own line if the program printed anything
+        if self.interpreter.stats().output_bytes != output_bytes {
+            println!();
+        }
+        if let Err(error) = result {
+            eprintln!("{}: {}", "Error".red(), error);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/// How the interpreter picks the thread that runs each step once a program has
+/// forked with `Y`.
+///
+/// Every thread writes to the same stdout in the order its steps run, so with the
+/// same schedule and input a program always prints the same output.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum Schedule {
+    /// Threads take turns running a single step, in the order they were forked
+    #[default]
+    RoundRobin,
+    /// A thread picked by a pseudo-random generator runs each step, repeatable
+    /// by running with the same seed
+    Random(u64),
+}
+
+impl std::str::FromStr for Schedule {
+    type Err = String;
+
+    /// Parses `round-robin`, or `random` which starts from the seed zero.
+    fn from_str(schedule: &str) -> Result<Self, Self::Err> {
+        match schedule {
+            "round-robin" => Ok(Schedule::RoundRobin),
+            "random" => Ok(Schedule::Random(0)),
+            _ => Err(format!("Unknown schedule '{}'", schedule)),
+        }
+    }
+}
+
+/// Picks the threads that run each step according to a schedule.
+#[derive(Debug, Clone)]
+pub(crate) struct Scheduler {
+    schedule: Schedule,
+    /// State of the generator used by `Schedule::Random`
+    state: u64,
+}
+
+impl Scheduler {
+    /// Creates a scheduler that has not picked any threads yet.
+    pub(crate) fn new(schedule: Schedule) -> Self {
+        let state = match schedule {
+            Schedule::RoundRobin => 0,
+            Schedule::Random(seed) => seed,
+        };
+        Scheduler { schedule, state }
+    }
+
+    /// Picks the thread to run next.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - Number of threads waiting to run, oldest first; must not be zero.
+    ///
+    /// # Returns
+    ///
+    /// The index of the picked thread.
+    pub(crate) fn pick(&mut self, count: usize) -> usize {
+        match self.schedule {
+            Schedule::RoundRobin => 0,
+            Schedule::Random(_) => (self.next_random() % count as u64) as usize,
+        }
+    }
+
+    /// Advances the generator, using SplitMix64 which works from any seed.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,323 @@
+use std::time::Duration;
+
+use crate::binary::{write_bytes, write_header, write_varint, Reader};
+use crate::grammar::Expression;
+use crate::stats::ExecutionStats;
+
+pub use crate::binary::LoadError;
+
+/// Bytes every snapshot file starts with.
+const MAGIC: &[u8; 4] = b"BFS\0";
+
+/// Version of the snapshot layout, bumped whenever it changes.
+const VERSION: u16 = 4;
+
+/// Position of execution within one level of the syntax tree.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    /// Index of the next expression to run in the body, one past the end once the
+    /// body has finished and the loop condition is about to be tested
+    pub index: usize,
+    /// Number of times the loop body has run since the loop was entered
+    pub iterations: u64,
+}
+
+/// The state of an interpreter at a point during a run.
+///
+/// The binary file is the magic bytes `BFS\0`, a little-endian `u16` version and
+/// then every field in order, with numbers as unsigned LEB128 varints and byte
+/// strings and lists prefixed by their length.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /// Identifies the syntax tree the snapshot was taken while running
+    pub fingerprint: u64,
+    /// The memory tape
+    pub cell_memory: Vec<u8>,
+    /// Index of the cell under the pointer
+    pub cell_ptr: usize,
+    /// Extended Brainfuck Type I's storage register
+    pub storage: u8,
+    /// The program counter, with one frame for the top level and one for every loop being run
+    pub frames: Vec<Frame>,
+    /// Number of every pbrain procedure defined so far, with the position of its
+    /// definition, ordered by number
+    pub procedures: Vec<(u8, Vec<Frame>)>,
+    /// Where each procedure call being run returns to, innermost last
+    pub call_stack: Vec<Vec<Frame>>,
+    /// Number of steps counted towards the step limit
+    pub steps: u64,
+    /// Metrics collected so far
+    pub stats: ExecutionStats,
+    /// Bytes read from stdin that have not been consumed yet
+    pub pending_input: Vec<u8>,
+    /// Bytes written by the program that have not been flushed yet
+    pub pending_output: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Computes a value identifying the shape of a syntax tree, ignoring where its
+    /// nodes came from in the source.
+    ///
+    /// # Arguments
+    ///
+    /// * `ast` - The abstract syntax tree (AST) to identify.
+    ///
+    /// # Returns
+    ///
+    /// The FNV-1a hash of the commands, counts and loop structure of the tree.
+    pub fn fingerprint(ast: &[Expression]) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        fn mix(state: &mut u64, bytes: &[u8]) {
+            for byte in bytes {
+                *state = (*state ^ *byte as u64).wrapping_mul(PRIME);
+            }
+        }
+
+        fn hash(ast: &[Expression], state: &mut u64) {
+            for expression in ast {
+                match expression {
+                    Expression::Operator(operator) => {
+                        mix(state, &[operator.type_name as u8]);
+                        mix(state, &(operator.count as u64).to_le_bytes());
+                    }
+                    Expression::Loop(_loop) => {
+                        mix(state, b"[");
+                        hash(&_loop.body, state);
+                        mix(state, b"]");
+                    }
+                    Expression::Procedure(procedure) => {
+                        mix(state, b"(");
+                        hash(&procedure.body, state);
+                        mix(state, b")");
+                    }
+                }
+            }
+        }
+
+        let mut state = OFFSET_BASIS;
+        hash(ast, &mut state);
+        state
+    }
+
+    /// Serializes the snapshot into its binary file format.
+    ///
+    /// # Returns
+    ///
+    /// The encoded bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        write_header(&mut bytes, MAGIC, VERSION);
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        write_bytes(&mut bytes, &self.cell_memory);
+        write_varint(&mut bytes, self.cell_ptr as u64);
+        bytes.push(self.storage);
+
+        Self::write_frames(&mut bytes, &self.frames);
+        write_varint(&mut bytes, self.procedures.len() as u64);
+        for (number, position) in &self.procedures {
+            bytes.push(*number);
+            Self::write_frames(&mut bytes, position);
+        }
+        write_varint(&mut bytes, self.call_stack.len() as u64);
+        for frames in &self.call_stack {
+            Self::write_frames(&mut bytes, frames);
+        }
+
+        write_varint(&mut bytes, self.steps);
+        for value in [
+            self.stats.instructions,
+            self.stats.commands,
+            self.stats.loop_iterations,
+            self.stats.pointer_max as u64,
+            self.stats.input_bytes as u64,
+            self.stats.output_bytes as u64,
+            self.stats.wall_time.as_nanos() as u64,
+        ] {
+            write_varint(&mut bytes, value);
+        }
+
+        write_bytes(&mut bytes, &self.pending_input);
+        write_bytes(&mut bytes, &self.pending_output);
+        bytes
+    }
+
+    /// Reads a snapshot from its binary file format.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The encoded snapshot.
+    ///
+    /// # Returns
+    ///
+    /// The snapshot, or why it could not be read.
+    pub fn decode(bytes: &[u8]) -> Result<Self, LoadError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(MAGIC, VERSION)?;
+
+        let mut fingerprint = [0; 8];
+        fingerprint.copy_from_slice(reader.take(8)?);
+        let cell_memory = reader.bytes()?.to_vec();
+        let cell_ptr = reader.usize()?;
+        let storage = reader.byte()?;
+
+        let frames = Self::read_frames(&mut reader)?;
+        let procedure_count = reader.usize()?;
+        let mut procedures = Vec::with_capacity(procedure_count.min(reader.remaining()));
+        for _ in 0..procedure_count {
+            procedures.push((reader.byte()?, Self::read_frames(&mut reader)?));
+        }
+        let call_count = reader.usize()?;
+        let mut call_stack = Vec::with_capacity(call_count.min(reader.remaining()));
+        for _ in 0..call_count {
+            call_stack.push(Self::read_frames(&mut reader)?);
+        }
+
+        let steps = reader.varint()?;
+        let stats = ExecutionStats {
+            instructions: reader.varint()?,
+            commands: reader.varint()?,
+            loop_iterations: reader.varint()?,
+            pointer_max: reader.usize()?,
+            input_bytes: reader.usize()?,
+            output_bytes: reader.usize()?,
+            wall_time: Duration::from_nanos(reader.varint()?),
+        };
+
+        Ok(Snapshot {
+            fingerprint: u64::from_le_bytes(fingerprint),
+            cell_memory,
+            cell_ptr,
+            storage,
+            frames,
+            procedures,
+            call_stack,
+            steps,
+            stats,
+            pending_input: reader.bytes()?.to_vec(),
+            pending_output: reader.bytes()?.to_vec(),
+        })
+    }
+
+    /// Appends a program counter as its number of frames followed by every frame.
+    fn write_frames(bytes: &mut Vec<u8>, frames: &[Frame]) {
+        write_varint(bytes, frames.len() as u64);
+        for frame in frames {
+            write_varint(bytes, frame.index as u64);
+            write_varint(bytes, frame.iterations);
+        }
+    }
+
+    /// Reads a program counter written by `write_frames`.
+    fn read_frames(reader: &mut Reader) -> Result<Vec<Frame>, LoadError> {
+        let count = reader.usize()?;
+        let mut frames = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            frames.push(Frame {
+                index: reader.usize()?,
+                iterations: reader.varint()?,
+            });
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::lexer::LexerOptions;
+    use crate::pipeline::Pipeline;
+
+    /// Parses a pbrain program without optimizing it.
+    fn compile(program: &str) -> Vec<Expression> {
+        let mut options = LexerOptions::default();
+        options.extensions.pbrain = true;
+        Pipeline::compile(String::from(program), false, options).into_ast()
+    }
+
+    /// Takes a snapshot after running `steps` steps of the program.
+    fn snapshot_after(ast: &[Expression], steps: usize) -> Snapshot {
+        let mut interpreter = Interpreter::new(ast);
+        for _ in 0..steps {
+            interpreter.step().unwrap();
+        }
+        interpreter.snapshot()
+    }
+
+    #[test]
+    fn round_trip_keeps_state() {
+        let ast = compile("+(>++[-<+>]<):>+++");
+        // Stops inside the loop of the procedure, with a caller to return to
+        let snapshot = snapshot_after(&ast, 8);
+        assert_eq!(snapshot.call_stack.len(), 1);
+        assert_eq!(snapshot.procedures.len(), 1);
+
+        assert_eq!(Snapshot::decode(&snapshot.encode()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = snapshot_after(&compile("+"), 1).encode();
+        bytes[0] = b'X';
+        assert_eq!(Snapshot::decode(&bytes).unwrap_err(), LoadError::BadMagic);
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut bytes = snapshot_after(&compile("+"), 1).encode();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Snapshot::decode(&bytes).unwrap_err(),
+            LoadError::UnsupportedVersion(VERSION + 1)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let bytes = snapshot_after(&compile("+(>+):"), 3).encode();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Snapshot::decode(&bytes[..len]).unwrap_err(),
+                LoadError::Truncated,
+                "decoding the first {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn restore_rejects_other_program() {
+        let snapshot = snapshot_after(&compile("+>+"), 2);
+        let other = compile("+>-");
+        let mut interpreter = Interpreter::new(&other);
+        assert_eq!(
+            interpreter.restore(snapshot),
+            Err(LoadError::ProgramMismatch)
+        );
+    }
+
+    #[test]
+    fn restore_rejects_pointer_past_tape() {
+        let ast = compile("+>+");
+        let mut snapshot = snapshot_after(&ast, 2);
+        snapshot.cell_ptr = snapshot.cell_memory.len();
+        let mut interpreter = Interpreter::new(&ast);
+        assert_eq!(interpreter.restore(snapshot), Err(LoadError::InvalidState));
+    }
+
+    #[test]
+    fn restore_continues_from_snapshot() {
+        let ast = compile("+(>++[-<+>]<):>+++");
+        let snapshot = snapshot_after(&ast, 8);
+
+        let mut interpreter = Interpreter::new(&ast);
+        interpreter.restore(snapshot).unwrap();
+        interpreter.run_code().unwrap();
+        assert_eq!(&interpreter.tape()[..2], &[3, 3]);
+        assert_eq!(interpreter.cell_ptr(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+/// The ways the execution statistics can be printed.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum StatsFormat {
+    /// A table meant to be read by people
+    #[default]
+    Human,
+    /// A single JSON object
+    Json,
+}
+
+impl std::str::FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "human" => Ok(StatsFormat::Human),
+            "json" => Ok(StatsFormat::Json),
+            _ => Err(format!("Unknown stats format '{}'", format)),
+        }
+    }
+}
+
+/// Metrics collected while a program runs.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecutionStats {
+    /// Number of operators executed, counting a folded operator once
+    pub instructions: u64,
+    /// Number of source commands executed, counting every command a folded operator stands for
+    pub commands: u64,
+    /// Number of times a loop body was run
+    pub loop_iterations: u64,
+    /// Highest cell the pointer reached
+    pub pointer_max: usize,
+    /// Number of bytes read from stdin
+    pub input_bytes: usize,
+    /// Number of bytes written to stdout
+    pub output_bytes: usize,
+    /// Wall clock time the run took
+    pub wall_time: Duration,
+}
+
+impl ExecutionStats {
+    /// Gets the number of cells the pointer reached, which always start from the first cell.
+    pub fn tape_extent(&self) -> usize {
+        self.pointer_max + 1
+    }
+
+    /// Gets how many instructions were executed per second of wall clock time.
+    pub fn instructions_per_second(&self) -> f64 {
+        if self.wall_time.is_zero() {
+            return 0.0;
+        }
+        self.instructions as f64 / self.wall_time.as_secs_f64()
+    }
+
+    /// Renders the statistics in the given format.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - How to render the statistics.
+    ///
+    /// # Returns
+    ///
+    /// The statistics as a printable string.
+    pub fn render(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Human => self.report(),
+            StatsFormat::Json => self.to_json(),
+        }
+    }
+
+    /// Renders a table of the statistics.
+    pub fn report(&self) -> String {
+        format!(
+            r#"| Execution statistics:
+| Instructions executed : {}
+| Commands executed     : {}
+| Loop iterations       : {}
+| Tape extent           : {} cells
+| Pointer max           : {}
+| Bytes in / out        : {} / {}
+| Wall time             : {:.2?}
+| Instructions / second : {:.0}"#,
+            self.instructions,
+            self.commands,
+            self.loop_iterations,
+            self.tape_extent(),
+            self.pointer_max,
+            self.input_bytes,
+            self.output_bytes,
+            self.wall_time,
+            self.instructions_per_second()
+        )
+    }
+
+    /// Renders the statistics as a JSON object on a single line.
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"instructions": {}, "commands": {}, "loop_iterations": {}, "tape_extent": {}, "pointer_max": {}, "input_bytes": {}, "output_bytes": {}, "wall_time_secs": {}, "instructions_per_second": {:.0}}}"#,
+            self.instructions,
+            self.commands,
+            self.loop_iterations,
+            self.tape_extent(),
+            self.pointer_max,
+            self.input_bytes,
+            self.output_bytes,
+            self.wall_time.as_secs_f64(),
+            self.instructions_per_second()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> ExecutionStats {
+        ExecutionStats {
+            instructions: 10,
+            commands: 25,
+            loop_iterations: 3,
+            pointer_max: 4,
+            input_bytes: 1,
+            output_bytes: 2,
+            wall_time: Duration::from_millis(500),
+        }
+    }
+
+    #[test]
+    fn tape_extent_counts_from_first_cell() {
+        assert_eq!(ExecutionStats::default().tape_extent(), 1);
+        assert_eq!(stats().tape_extent(), 5);
+    }
+
+    #[test]
+    fn instructions_per_second_without_time_is_zero() {
+        assert_eq!(ExecutionStats::default().instructions_per_second(), 0.0);
+        assert_eq!(stats().instructions_per_second(), 20.0);
+    }
+
+    #[test]
+    fn renders_json_object() {
+        assert_eq!(
+            stats().render(StatsFormat::Json),
+            r#"{"instructions": 10, "commands": 25, "loop_iterations": 3, "tape_extent": 5, "pointer_max": 4, "input_bytes": 1, "output_bytes": 2, "wall_time_secs": 0.5, "instructions_per_second": 20}"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_stats() {
+        let text = toml::to_string(&stats()).unwrap();
+        assert_eq!(toml::from_str::<ExecutionStats>(&text).unwrap(), stats());
+    }
+
+    #[test]
+    fn renders_table() {
+        let report = stats().render(StatsFormat::Human);
+        assert!(
+            report.contains("| Tape extent           : 5 cells"),
+            "{}",
+            report
+        );
+        assert!(report.contains("| Pointer max           : 4"), "{}", report);
+        assert!(
+            report.contains("| Bytes in / out        : 1 / 2"),
+            "{}",
+            report
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use crate::grammar::Token;
+use crate::watch::Access;
+
+/// Target of the `log` records carrying trace lines, so a logger can write them
+/// somewhere other than its regular messages.
+pub const TARGET: &str = "brainfk::trace";
+
+/// The ways a trace line can be written.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum TraceFormat {
+    /// Space separated fields meant to be read by people
+    #[default]
+    Text,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(TraceFormat::Text),
+            "jsonl" => Ok(TraceFormat::Jsonl),
+            _ => Err(format!("Unknown trace format '{}'", format)),
+        }
+    }
+}
+
+/// Options selecting which steps are traced and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceOptions {
+    /// How each line is written
+    pub format: TraceFormat,
+    /// Trace only every `every`th step that passes the filter, counting from the first
+    pub every: u64,
+    /// Commands to trace, where `[` is entering a loop and `]` is testing it again,
+    /// `(` is defining a procedure and `)` is returning from it, or every command if empty
+    pub tokens: Vec<Token>,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        TraceOptions {
+            format: TraceFormat::default(),
+            every: 1,
+            tokens: vec![],
+        }
+    }
+}
+
+/// Logs the steps of a run selected by the trace options.
+#[derive(Debug)]
+pub struct Tracer {
+    options: TraceOptions,
+    /// Number of steps that passed the command filter so far
+    matched: u64,
+}
+
+impl Tracer {
+    /// Creates a tracer that has not seen any steps yet.
+    pub fn new(options: TraceOptions) -> Self {
+        Tracer {
+            options,
+            matched: 0,
+        }
+    }
+
+    /// Logs a step as a `log` record with the `TARGET` target if it passes the command
+    /// filter, sampling every `every`th step that does.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The step that was executed.
+    pub fn record(&mut self, event: &TraceEvent) {
+        if !self.options.tokens.is_empty() && !self.options.tokens.contains(&event.token) {
+            return;
+        }
+        self.matched += 1;
+        if (self.matched - 1).is_multiple_of(self.options.every.max(1)) {
+            log::trace!(target: TARGET, "{}", event.render(self.options.format));
+        }
+    }
+}
+
+/// A single executed step.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// Number of the step, counting from one
+    pub step: u64,
+    /// The command executed, `[` when a loop is entered and `]` when it is tested again,
+    /// `(` when a procedure is defined and `)` when it returns
+    pub token: Token,
+    /// What the step did to the pointer and the cell under it
+    pub access: Access,
+}
+
+impl TraceEvent {
+    /// Renders the step as a single line, without the trailing newline.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - How to render the line.
+    pub fn render(&self, format: TraceFormat) -> String {
+        let access = &self.access;
+        match format {
+            TraceFormat::Text => {
+                let new_cell_ptr = if access.new_cell_ptr != access.cell {
+                    format!("->{}", access.new_cell_ptr)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{:>8} {:<8} {} ptr={}{} cell={}->{}",
+                    self.step,
+                    access.span.to_string(),
+                    self.token as u8 as char,
+                    access.cell,
+                    new_cell_ptr,
+                    access.old_value,
+                    access.new_value
+                )
+            }
+            TraceFormat::Jsonl => format!(
+                r#"{{"step": {}, "span": {{"line": {}, "col": {}, "offset": {}, "len": {}}}, "token": "{}", "ptr": {}, "new_ptr": {}, "before": {}, "after": {}}}"#,
+                self.step,
+                access.span.line,
+                access.span.col,
+                access.span.byte_offset,
+                access.span.len,
+                self.token as u8 as char,
+                access.cell,
+                access.new_cell_ptr,
+                access.old_value,
+                access.new_value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Span;
+
+    /// A `>` on line 2, column 3 that moved the pointer from cell 4 to 5.
+    fn move_forward() -> TraceEvent {
+        TraceEvent {
+            step: 12,
+            token: Token::MoveForward,
+            access: Access {
+                span: Span {
+                    line: 2,
+                    col: 3,
+                    byte_offset: 9,
+                    len: 1,
+                },
+                reads: false,
+                writes: false,
+                cell: 4,
+                old_value: 7,
+                new_value: 7,
+                new_cell_ptr: 5,
+            },
+        }
+    }
+
+    #[test]
+    fn renders_text_line() {
+        assert_eq!(
+            move_forward().render(TraceFormat::Text),
+            "      12 2:3      > ptr=4->5 cell=7->7"
+        );
+    }
+
+    #[test]
+    fn text_line_omits_unmoved_pointer() {
+        let mut event = move_forward();
+        event.token = Token::Add;
+        event.access.new_cell_ptr = 4;
+        event.access.new_value = 8;
+        assert_eq!(
+            event.render(TraceFormat::Text),
+            "      12 2:3      + ptr=4 cell=7->8"
+        );
+    }
+
+    #[test]
+    fn renders_jsonl_line() {
+        assert_eq!(
+            move_forward().render(TraceFormat::Jsonl),
+            r#"{"step": 12, "span": {"line": 2, "col": 3, "offset": 9, "len": 1}, "token": ">", "ptr": 4, "new_ptr": 5, "before": 7, "after": 7}"#
+        );
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("text".parse(), Ok(TraceFormat::Text));
+        assert_eq!("jsonl".parse(), Ok(TraceFormat::Jsonl));
+        assert!("json".parse::<TraceFormat>().is_err());
+    }
+}
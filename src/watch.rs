@@ -0,0 +1,253 @@
+use std::fmt;
+
+use crate::grammar::{Expression, Span, Token};
+
+/// A condition on the tape checked after every step of a run.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Watchpoint {
+    /// The cell is read by `+`, `-`, `.`, `:`, `$`, a shift, a bitwise operator or a loop test
+    Read(usize),
+    /// The cell is written by `+`, `-`, `,`, `Y`, `!`, a shift or a bitwise operator
+    Write(usize),
+    /// The cell is written with a value different from the one it held
+    Change(usize),
+    /// The cell takes the value, having held a different one
+    Value(usize, u8),
+    /// The pointer moves onto one of the cells from `start` to `end`, inclusive
+    Pointer { start: usize, end: usize },
+}
+
+/// What to do when a watchpoint is hit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatchAction {
+    /// Keep the hit so a stepping caller can stop on it, see `Interpreter::take_watch_hits`
+    Break,
+    /// Print the hit to stderr and keep running
+    Log,
+}
+
+/// What a single step did to the pointer and the cell under it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Access {
+    /// Location of the instruction or loop test
+    pub span: Span,
+    /// Flag indicating whether the step read the cell under the pointer
+    pub reads: bool,
+    /// Flag indicating whether the step wrote the cell under the pointer
+    pub writes: bool,
+    /// The pointer before the step, which is the only cell a step can touch
+    pub cell: usize,
+    /// Value of the cell before the step
+    pub old_value: u8,
+    /// Value of the cell after the step
+    pub new_value: u8,
+    /// The pointer after the step
+    pub new_cell_ptr: usize,
+}
+
+impl Access {
+    /// Describes a step about to run `expression` with the pointer on `cell`,
+    /// leaving the cell and pointer unchanged until the step has run.
+    ///
+    /// # Arguments
+    ///
+    /// * `expression` - The operator to execute, or the loop whose condition is tested.
+    /// * `cell` - The cell pointer before the step.
+    /// * `value` - Value of the cell before the step.
+    pub(crate) fn new(expression: &Expression, cell: usize, value: u8) -> Self {
+        let (reads, writes) = match expression {
+            Expression::Operator(operator) => match operator.type_name {
+                Token::Add
+                | Token::Sub
+                | Token::ShiftRight
+                | Token::ShiftLeft
+                | Token::Not
+                | Token::Xor
+                | Token::And
+                | Token::Or => (true, true),
+                Token::StdOut | Token::Call | Token::Store => (true, false),
+                Token::StdIn | Token::Fork | Token::Retrieve => (false, true),
+                _ => (false, false),
+            },
+            Expression::Loop(_) => (true, false),
+            Expression::Procedure(_) => (false, false),
+        };
+
+        Access {
+            span: expression.span(),
+            reads,
+            writes,
+            cell,
+            old_value: value,
+            new_value: value,
+            new_cell_ptr: cell,
+        }
+    }
+}
+
+/// A watchpoint that was hit by a step.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WatchHit {
+    /// Index of the watchpoint in the order they were added
+    pub index: usize,
+    /// The watchpoint that was hit
+    pub watchpoint: Watchpoint,
+    /// What the step did
+    pub access: Access,
+}
+
+impl Watchpoint {
+    /// Checks whether a step hits the watchpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `access` - What the step did.
+    pub fn is_hit(&self, access: &Access) -> bool {
+        match *self {
+            Watchpoint::Read(cell) => access.reads && access.cell == cell,
+            Watchpoint::Write(cell) => access.writes && access.cell == cell,
+            Watchpoint::Change(cell) => {
+                access.writes && access.cell == cell && access.old_value != access.new_value
+            }
+            Watchpoint::Value(cell, value) => {
+                access.writes
+                    && access.cell == cell
+                    && access.old_value != value
+                    && access.new_value == value
+            }
+            Watchpoint::Pointer { start, end } => {
+                let range = start..=end;
+                !range.contains(&access.cell) && range.contains(&access.new_cell_ptr)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Watchpoint {
+    type Err = String;
+
+    /// Parses `read CELL`, `write CELL`, `change CELL`, `value CELL VALUE` or `ptr START END`.
+    fn from_str(watchpoint: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid watchpoint '{}'", watchpoint);
+        let words: Vec<&str> = watchpoint.split_whitespace().collect();
+        let number = |idx: usize| -> Result<usize, String> {
+            words
+                .get(idx)
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(invalid)
+        };
+
+        let (parsed, len) = match words.first() {
+            Some(&"read") => (Watchpoint::Read(number(1)?), 2),
+            Some(&"write") => (Watchpoint::Write(number(1)?), 2),
+            Some(&"change") => (Watchpoint::Change(number(1)?), 2),
+            Some(&"value") => {
+                let value = u8::try_from(number(2)?).map_err(|_| invalid())?;
+                (Watchpoint::Value(number(1)?, value), 3)
+            }
+            Some(&"ptr") => {
+                let (start, end) = (number(1)?, number(2)?);
+                if start > end {
+                    return Err(invalid());
+                }
+                (Watchpoint::Pointer { start, end }, 3)
+            }
+            _ => return Err(invalid()),
+        };
+        if words.len() != len {
+            return Err(invalid());
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Read(cell) => write!(f, "read {}", cell),
+            Watchpoint::Write(cell) => write!(f, "write {}", cell),
+            Watchpoint::Change(cell) => write!(f, "change {}", cell),
+            Watchpoint::Value(cell, value) => write!(f, "value {} {}", cell, value),
+            Watchpoint::Pointer { start, end } => write!(f, "ptr {} {}", start, end),
+        }
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = &self.access;
+        match self.watchpoint {
+            Watchpoint::Read(cell) => write!(f, "cell {} read, value {}", cell, access.old_value)?,
+            Watchpoint::Write(cell) | Watchpoint::Change(cell) | Watchpoint::Value(cell, _) => {
+                write!(
+                    f,
+                    "cell {} changed {} -> {}",
+                    cell, access.old_value, access.new_value
+                )?
+            }
+            Watchpoint::Pointer { start, end } => write!(
+                f,
+                "pointer moved {} -> {}, entering {}..={}",
+                access.cell, access.new_cell_ptr, start, end
+            )?,
+        }
+        write!(f, " @ {}", access.span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Describes a step that wrote `old_value` then `new_value` to cell 3.
+    fn write(old_value: u8, new_value: u8) -> Access {
+        Access {
+            span: Span::default(),
+            reads: true,
+            writes: true,
+            cell: 3,
+            old_value,
+            new_value,
+            new_cell_ptr: 3,
+        }
+    }
+
+    #[test]
+    fn change_ignores_writes_of_same_value() {
+        assert!(Watchpoint::Write(3).is_hit(&write(7, 7)));
+        assert!(!Watchpoint::Change(3).is_hit(&write(7, 7)));
+        assert!(Watchpoint::Change(3).is_hit(&write(7, 8)));
+        assert!(!Watchpoint::Change(4).is_hit(&write(7, 8)));
+    }
+
+    #[test]
+    fn value_is_hit_only_when_reached() {
+        assert!(Watchpoint::Value(3, 8).is_hit(&write(7, 8)));
+        assert!(!Watchpoint::Value(3, 8).is_hit(&write(8, 8)));
+    }
+
+    #[test]
+    fn pointer_is_hit_when_entering_range() {
+        let mut access = write(0, 0);
+        access.new_cell_ptr = 5;
+        assert!(Watchpoint::Pointer { start: 4, end: 6 }.is_hit(&access));
+        access.cell = 4;
+        assert!(!Watchpoint::Pointer { start: 4, end: 6 }.is_hit(&access));
+    }
+
+    #[test]
+    fn parses_what_it_displays() {
+        for watchpoint in [
+            Watchpoint::Read(1),
+            Watchpoint::Write(2),
+            Watchpoint::Change(3),
+            Watchpoint::Value(4, 255),
+            Watchpoint::Pointer { start: 5, end: 6 },
+        ] {
+            assert_eq!(watchpoint.to_string().parse(), Ok(watchpoint));
+        }
+        assert!("value 1 256".parse::<Watchpoint>().is_err());
+        assert!("ptr 6 5".parse::<Watchpoint>().is_err());
+        assert!("write".parse::<Watchpoint>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::process::Command;
+
+#[test]
+fn macros_are_rejected_with_type1_extension() {
+    let output = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .args(["--macros", "--ext", "type1", "program.bf"])
+        .output()
+        .expect("couldn't run the interpreter");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Macros can't be used with '--ext type1'"),
+        "{}",
+        stderr
+    );
+}
+
+/// Writes `content` to a file in a fresh temporary directory and returns its path.
+fn temp_program(name: &str, content: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("brainfk-cli-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).expect("couldn't create a temporary directory");
+    let path = dir.join("program.bf");
+    std::fs::write(&path, content).expect("couldn't write the program");
+    path
+}
+
+#[test]
+fn fmt_check_leaves_unformatted_file_untouched() {
+    let unformatted = "+++   set up\n\n\n\n>[-]";
+    let path = temp_program("fmt-check", unformatted);
+    let output = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .arg("fmt")
+        .arg("--check")
+        .arg(&path)
+        .output()
+        .expect("couldn't run the formatter");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is not formatted"), "{}", stderr);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), unformatted);
+}
+
+#[test]
+fn fmt_rewrites_file_then_check_passes() {
+    let path = temp_program("fmt-rewrite", "+++   set up\n\n\n\n>[-]");
+    let status = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .arg("fmt")
+        .arg(&path)
+        .status()
+        .expect("couldn't run the formatter");
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("+++{}set up\n\n>[-]\n", " ".repeat(21))
+    );
+
+    let status = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .arg("fmt")
+        .arg("--check")
+        .arg(&path)
+        .status()
+        .expect("couldn't run the formatter");
+    assert!(status.success());
+}
+
+#[test]
+fn translate_round_trips_through_dialect() {
+    let path = temp_program("translate", "+[>-<], comment .\n");
+    let ook = path.with_extension("ook");
+    let output = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .args(["translate", "--to", "alphuck"])
+        .arg(&path)
+        .output()
+        .expect("couldn't run the translator");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "epaicsoj\n");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .args(["translate", "--to", "ook", "-o"])
+        .arg(&ook)
+        .arg(&path)
+        .status()
+        .expect("couldn't run the translator");
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .args(["translate", "--to", "brainfuck"])
+        .arg(&ook)
+        .output()
+        .expect("couldn't run the translator");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "+[>-<],.\n");
+}
+
+#[test]
+fn lint_reads_levels_from_config_next_to_program() {
+    let path = temp_program("lint-config", "+[>+]\n");
+    let lint = || {
+        Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+            .arg("lint")
+            .arg(&path)
+            .output()
+            .expect("couldn't run the linter")
+    };
+
+    let output = lint();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 warning(s), 0 error(s)"));
+
+    let config = path.with_file_name("brainfk.toml");
+    std::fs::write(&config, "[lints]\npointer-drift = \"deny\"\n").unwrap();
+    let output = lint();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error[BF004]"));
+
+    std::fs::write(&config, "[lints]\npointer-drift = \"allow\"\n").unwrap();
+    let output = lint();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("0 warning(s), 0 error(s)"));
+}
+
+#[test]
+fn trace_cell_skips_writes_that_keep_the_value() {
+    // `!` writes the value `$` stored back to the same cell
+    let path = temp_program("trace-cell", "+$!+\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .args(["--ext", "type1", "--no-optimise", "--trace-cell", "0"])
+        .arg(&path)
+        .output()
+        .expect("couldn't run the interpreter");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let changes: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.contains("cell 0 changed"))
+        .collect();
+    assert_eq!(changes.len(), 2, "{}", stderr);
+    assert!(changes[0].ends_with("0 -> 1 @ 1:1"), "{}", stderr);
+    assert!(changes[1].ends_with("1 -> 2 @ 1:4"), "{}", stderr);
+}
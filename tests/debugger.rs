@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Debugs `program` with `commands` on stdin and returns what the debugger printed.
+fn debug(name: &str, program: &str, commands: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("brainfk-debug-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).expect("couldn't create a temporary directory");
+    let path = dir.join("program.bf");
+    std::fs::write(&path, program).expect("couldn't write the program");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .arg("debug")
+        .arg("--no-optimise")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("couldn't start the debugger");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(commands.as_bytes())
+        .expect("couldn't write to the debugger");
+
+    let output = child
+        .wait_with_output()
+        .expect("couldn't wait for the debugger");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).expect("the debugger printed invalid UTF-8")
+}
+
+#[test]
+fn write_watchpoint_stops_continue() {
+    let output = debug("write", "++[>+<-]>.", "watch write 1\ncontinue\nptr\n");
+    assert!(
+        output.contains("Watchpoint 1 (write 1): cell 1 changed 0 -> 1 @ 1:5"),
+        "{}",
+        output
+    );
+    assert!(output.contains("cell_ptr=1 value=1"), "{}", output);
+    assert!(!output.contains("Program finished"), "{}", output);
+}
+
+#[test]
+fn read_watchpoint_stops_continue() {
+    let output = debug("read", "+>++<", "watch read 1\ncontinue\ncontinue\nptr\n");
+    assert!(
+        output.contains("Watchpoint 1 (read 1): cell 1 read, value 0 @ 1:3"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("Watchpoint 1 (read 1): cell 1 read, value 1 @ 1:4"),
+        "{}",
+        output
+    );
+    assert!(output.contains("cell_ptr=1 value=2"), "{}", output);
+}
+
+#[test]
+fn reverse_continue_stops_at_watchpoint() {
+    let output = debug(
+        "reverse",
+        "+>+++<",
+        "watch change 1\ncontinue\ncontinue\nreverse-continue\nptr\n",
+    );
+    let hits = output.matches("Watchpoint 1 (change 1)").count();
+    assert_eq!(hits, 3, "{}", output);
+    // Undoing the second `+` reports it again and leaves the first one's value
+    assert!(output.contains("cell_ptr=1 value=1"), "{}", output);
+}
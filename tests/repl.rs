@@ -0,0 +1,59 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs the REPL with the options in `args` and `input` on stdin, and returns what it
+/// printed to stdout and stderr.
+fn run_repl(args: &[&str], input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_BrainFk"))
+        .arg("repl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("couldn't start the REPL");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .expect("couldn't write to the REPL");
+
+    let output = child
+        .wait_with_output()
+        .expect("couldn't wait for the REPL");
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).expect("the REPL printed invalid UTF-8"),
+        String::from_utf8(output.stderr).expect("the REPL printed invalid UTF-8"),
+    )
+}
+
+#[test]
+fn loop_at_start_of_line_runs_on_non_zero_cell() {
+    let (output, _) = run_repl(&[], "+++\n[-]\n:ptr\n");
+    assert!(output.contains("cell_ptr=0 value=0"), "{}", output);
+}
+
+#[test]
+fn tape_is_kept_between_lines() {
+    let (output, _) = run_repl(&[], "+++\n>++\n:ptr\n");
+    assert!(output.contains("cell_ptr=1 value=2"), "{}", output);
+}
+
+#[test]
+fn procedures_are_kept_between_lines() {
+    let (output, errors) = run_repl(&["--ext", "pbrain"], "+(++)\n:\n:ptr\n");
+    assert!(output.contains("cell_ptr=0 value=3"), "{}", output);
+    assert!(errors.is_empty(), "{}", errors);
+}
+
+#[test]
+fn step_limit_applies_to_each_line() {
+    let (output, errors) = run_repl(
+        &["--no-optimise", "--max-steps", "4"],
+        "+++\n+++\n+++\n:ptr\n+++++\n",
+    );
+    assert!(output.contains("cell_ptr=0 value=9"), "{}", output);
+    assert_eq!(errors.matches("Error").count(), 1, "{}", errors);
+}